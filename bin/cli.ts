@@ -12,7 +12,7 @@ import { submitDeposit } from "./commands/submit_deposit";
 import { queueWithdrawal } from "./commands/queue_withdrawal";
 import { submitWithdrawal } from "./commands/submit_withdrawal";
 import { signWithdrawal } from "./commands/sign_withdrawal";
-import { upgrade } from "./commands/upgrade";
+import { deployStagedUpgrade, stageUpgrade } from "./commands/upgrade";
 import { soloWithdraw } from "./commands/solo_withdraw";
 
 yargs(hideBin(process.argv))
@@ -27,6 +27,7 @@ yargs(hideBin(process.argv))
   .command(signWithdrawal)
   .command(init)
   .command(initBip322)
-  .command(upgrade)
+  .command(stageUpgrade)
+  .command(deployStagedUpgrade)
   .command(soloWithdraw)
   .parse();
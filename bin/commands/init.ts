@@ -24,6 +24,7 @@ export const init: CommandModule<unknown, Args> = {
       chain_signatures_id: config.accountIds.chainSignatures,
       n_confirmation: config.params.nConfirmation,
       withdrawal_waiting_time_ms: config.params.withdrawalWaitingTimeMs,
+      pending_sign_psbt_expiry_ms: config.params.pendingSignPsbtExpiryMs,
       min_deposit_satoshi: config.params.minDepositSatoshi,
       earliest_deposit_block_height: config.params.earliestDepositBlockHeight,
       solo_withdrawal_seq_heights: config.params.soloWithdrawSeqHeights,
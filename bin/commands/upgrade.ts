@@ -1,24 +1,56 @@
-import { envBuilder } from "../helper";
+import { confirmAction, envBuilder, nearTGas } from "../helper";
 import { getConfig } from "../config";
 import { CommandModule } from "yargs";
 import { initNear } from "../near";
+import fs from "fs";
 
 interface Args {
   env: string;
 }
 
-export const upgrade: CommandModule<unknown, Args> = {
-  command: "upgrade",
-  describe: "Call upgrade on BitHive contract",
+interface DeployArgs extends Args {
+  codeHash?: string;
+}
+
+export const stageUpgrade: CommandModule<unknown, Args> = {
+  command: "stage-upgrade",
+  describe: "Stage new code of BitHive contract for upgrade",
   builder: {
     env: envBuilder,
   },
   async handler({ env }) {
+    const config = await getConfig(env);
+    const { signer } = await initNear(env, config.accountIds.owner);
+    const code = fs.readFileSync("res/bithive.wasm");
+    await confirmAction(`Stage upgrade of ${config.accountIds.bithive}`);
+    await signer.functionCall({
+      contractId: config.accountIds.bithive,
+      methodName: "stage_upgrade",
+      args: code,
+      gas: nearTGas(300),
+      attachedDeposit: BigInt(1),
+    });
+  },
+};
+
+export const deployStagedUpgrade: CommandModule<unknown, DeployArgs> = {
+  command: "deploy-staged-upgrade",
+  describe: "Deploy staged code of BitHive contract",
+  builder: {
+    env: envBuilder,
+    codeHash: {
+      describe: "Expected hex encoded sha256 hash of the staged code",
+      type: "string",
+    },
+  },
+  async handler({ env, codeHash }) {
     const config = await getConfig(env);
     const { signer } = await initNear(env, config.accountIds.owner);
     await signer.functionCall({
       contractId: config.accountIds.bithive,
-      methodName: "upgrade",
+      methodName: "deploy_staged_upgrade",
+      args: { expected_code_hash: codeHash ?? null },
+      gas: nearTGas(300),
       attachedDeposit: BigInt(1),
     });
   },
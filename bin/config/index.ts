@@ -21,6 +21,7 @@ export interface Config {
   params: {
     nConfirmation: number;
     withdrawalWaitingTimeMs: number;
+    pendingSignPsbtExpiryMs: number;
     minDepositSatoshi: number;
     earliestDepositBlockHeight: number;
     soloWithdrawSeqHeights: number[];
@@ -28,6 +28,7 @@ export const config: Config = {
   params: {
     nConfirmation: 2,
     withdrawalWaitingTimeMs: 5 * 60 * 1000, // 5 minutes
+    pendingSignPsbtExpiryMs: 60 * 60 * 1000, // 1 hour
     minDepositSatoshi: 0,
     earliestDepositBlockHeight: 0,
     soloWithdrawSeqHeights: [2],
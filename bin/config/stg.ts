@@ -28,6 +28,7 @@ export const config: Config = {
   params: {
     nConfirmation: 6,
     withdrawalWaitingTimeMs: 2 * 24 * 3600 * 1000, // 2 days
+    pendingSignPsbtExpiryMs: 7 * 24 * 3600 * 1000, // 7 days
     minDepositSatoshi: 0,
     earliestDepositBlockHeight: 884168,
     soloWithdrawSeqHeights: [64000],
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::RedeemVersion;
+
+/// A queued withdrawal request, which can be signed once its waiting time has passed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WithdrawalTicket {
+    /// nonce of the signed queue withdrawal message
+    pub nonce: u64,
+    /// amount to withdraw in full BTC decimals
+    pub amount: u64,
+    /// timestamp when the ticket is queued in ms
+    pub start_ts: u64,
+    /// waiting time in ms fixed by the tier when the ticket is queued,
+    /// empty for tickets migrated from the legacy queue, which wait the base waiting time
+    pub waiting_time_ms: Option<u64>,
+    /// BTC tip height when the ticket is queued, only recorded if waiting time is measured in BTC blocks
+    pub start_btc_height: Option<u64>,
+}
+
+/// Restrictions on multisig withdrawals set by the user
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WithdrawalPolicy {
+    /// max amount in satoshi that could be withdrawn within a day, empty for no limit
+    pub daily_limit: Option<u64>,
+    /// hex encoded script pubkeys that withdrawals could be sent to, empty to allow any
+    pub allowed_destinations: Option<Vec<String>>,
+    /// how long a looser policy needs to wait before taking effect in ms
+    pub loosen_delay_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PendingWithdrawalPolicy {
+    pub policy: WithdrawalPolicy,
+    /// timestamp when the policy takes effect in ms
+    pub effective_ts: u64,
+}
+
+/// Account freeze made for compliance reasons
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AccountFreeze {
+    /// reason code defined off-chain
+    pub reason_code: u16,
+    /// timestamp when the account is frozen in ms
+    pub frozen_ts: u64,
+}
+
+/// A request made by the guardian to recover a deposit
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecoveryRequest {
+    /// ID of the deposit to recover, in the form of `{tx_id}:{vout}`
+    pub deposit_id: String,
+    /// timestamp when the recovery is requested in ms
+    pub start_ts: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum DepositStatus {
+    Active,
+    Withdrawn,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Deposit {
+    /// user pubkey
+    pub user_pubkey: String,
+    /// deposit status
+    pub status: DepositStatus,
+    /// redeem version allows us to use the correct params to sign withdrawal txn
+    pub redeem_version: RedeemVersion,
+    /// deposit transaction ID
+    pub deposit_tx_id: String,
+    /// deposit UTXO vout in the above transaction
+    pub deposit_vout: u64,
+    /// deposit amount in full BTC decimals
+    pub value: u64,
+    /// encoded sequence number of the deposit
+    pub sequence: u32,
+    /// stake weight multiplier in basis points, boosted for fixed-term deposits
+    pub stake_weight_bps: u32,
+    /// complete withdrawal time in ms
+    pub complete_withdrawal_ts: u64,
+    /// withdrawal txn ID
+    pub withdrawal_tx_id: Option<String>,
+}
@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::types::WithdrawalWaitingTier;
+
+/// Sensitive config changes that could only be executed after the config timelock
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ConfigChange {
+    BtcLightClientId(String),
+    Bip322VerifierId(Option<String>),
+    ChainSignaturesId(String),
+    NConfirmation(u64),
+    WithdrawalWaitingTime(u64),
+    WithdrawalWaitingTiers(Vec<WithdrawalWaitingTier>),
+    WithdrawalWaitingBtcBlocks(Option<u64>),
+    GuardianRecoveryWaitingTime(u64),
+    ConfigTimelock(u64),
+    UpgradeDelay(u64),
+}
+
+/// Old and new values of a config field updated via `update_config` or a config setter
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ConfigFieldChange {
+    pub field: String,
+    pub old_value: Value,
+    pub new_value: Value,
+}
@@ -0,0 +1,357 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::account::WithdrawalPolicy;
+use crate::config::{ConfigChange, ConfigFieldChange};
+use crate::json::U64;
+use crate::types::{DepositMode, RedeemVersion, Role};
+
+pub const EVENT_STANDARD: &str = "bithive";
+pub const EVENT_STANDARD_VERSION: &str = "1.1.0";
+pub const EVENT_STANDARD_VERSION_V2: &str = "2.0.0";
+
+const EVENT_LOG_PREFIX: &str = "EVENT_JSON:";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "event", content = "data")]
+#[must_use = "Don't forget to `.emit()` this event"]
+pub enum Event {
+    Deposit {
+        user_pubkey: String,
+        tx_id: String,
+        deposit_vout: U64,
+        value: U64,
+    },
+    DepositVerificationFailed {
+        tx_id: String,
+        deposit_vout: U64,
+        reason: String,
+    },
+    QueueWithdrawal {
+        user_pubkey: String,
+        amount: U64,
+        withdrawal_msg: String,
+        withdrawal_sig: String,
+    },
+    CancelPendingSignPsbt {
+        user_pubkey: String,
+        tx_id: String,
+        cancel_msg: String,
+        cancel_sig: String,
+    },
+    PendingSignPsbtExpired {
+        user_pubkey: String,
+        tx_id: String,
+    },
+    SetWithdrawalPolicy {
+        user_pubkey: String,
+        policy: WithdrawalPolicy,
+        effective_ts: U64,
+        policy_msg: String,
+        policy_sig: String,
+    },
+    RequestRecovery {
+        user_pubkey: String,
+        deposit_id: String,
+        recovery_msg: String,
+        recovery_sig: String,
+    },
+    CancelRecovery {
+        user_pubkey: String,
+        deposit_id: String,
+        cancel_msg: String,
+        cancel_sig: String,
+    },
+    SignRecovery {
+        user_pubkey: String,
+        deposit_id: String,
+    },
+    SignWithdrawal {
+        user_pubkey: String,
+    },
+    SignWithdrawalFailed {
+        user_pubkey: String,
+        tx_id: String,
+        vin_to_sign: U64,
+        reason: String,
+    },
+    WithdrawalRateLimitExceeded {
+        user_pubkey: String,
+        amount: U64,
+        used_amount: U64,
+        max_amount: U64,
+    },
+    Withdrawn {
+        user_pubkey: String,
+        withdrawal_tx_id: String,
+        deposit_tx_id: String,
+        deposit_vout: U64,
+        is_multisig: bool,
+    },
+    WithdrawalRejected {
+        user_pubkey: String,
+        tx_id: String,
+        reason: String,
+    },
+    ProposeChangeOwner {
+        owner: String,
+        pending_owner: String,
+    },
+    OwnerChanged {
+        old_owner: String,
+        new_owner: String,
+    },
+    Paused {
+        paused: bool,
+    },
+    SetDepositMode {
+        mode: DepositMode,
+    },
+    SetWithdrawalRateLimit {
+        max_amount: Option<U64>,
+        window_ms: U64,
+    },
+    ResetWithdrawalRateLimit {
+        used_amount: U64,
+    },
+    SyncChainSignaturesRootPubkey {
+        root_pubkey: String,
+    },
+    ConfigChanged {
+        changes: Vec<ConfigFieldChange>,
+    },
+    ProposeConfigChange {
+        id: U64,
+        change: ConfigChange,
+        eta_ms: U64,
+    },
+    ExecuteConfigChange {
+        id: U64,
+        change: ConfigChange,
+    },
+    CancelConfigChange {
+        id: U64,
+        change: ConfigChange,
+    },
+    StageUpgrade {
+        code_hash: String,
+        eta_ms: U64,
+    },
+    CancelStagedUpgrade {
+        code_hash: String,
+    },
+    DeployUpgrade {
+        code_hash: String,
+    },
+    MigrateState {
+        from_version: u32,
+        to_version: u32,
+    },
+    FinishStateMigration {
+        migrated_accounts: U64,
+    },
+    RoleGranted {
+        account_id: String,
+        role: Role,
+    },
+    RoleRevoked {
+        account_id: String,
+        role: Role,
+    },
+    AddToDepositAllowlist {
+        user_pubkeys: Vec<String>,
+    },
+    RemoveFromDepositAllowlist {
+        user_pubkeys: Vec<String>,
+    },
+    AddToDenylist {
+        user_pubkeys: Vec<String>,
+        outpoints: Vec<String>,
+    },
+    RemoveFromDenylist {
+        user_pubkeys: Vec<String>,
+        outpoints: Vec<String>,
+    },
+    FreezeAccount {
+        user_pubkey: String,
+        reason_code: u16,
+    },
+    UnfreezeAccount {
+        user_pubkey: String,
+    },
+}
+
+impl Event {
+    #[cfg(feature = "near-sdk")]
+    pub fn emit(&self) {
+        near_sdk::env::log_str(&self.to_log());
+    }
+
+    /// Log line of the event in the form of `EVENT_JSON:{...}`
+    pub fn to_log(&self) -> String {
+        event_log(EVENT_STANDARD_VERSION, json!(self))
+    }
+}
+
+/// Events whose payloads are extended in version 2 of the event standard.
+/// They are emitted along with their v1 counterparts until legacy events are disabled by the owner
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "event", content = "data")]
+#[must_use = "Don't forget to `.emit()` this event"]
+pub enum EventV2 {
+    Deposit {
+        user_pubkey: String,
+        tx_id: String,
+        deposit_vout: U64,
+        value: U64,
+        sequence_height: u16,
+        redeem_version: RedeemVersion,
+        tx_block_hash: String,
+        caller_id: String,
+        /// total deposit of the user after the deposit
+        total_deposit: U64,
+    },
+    Withdrawn {
+        user_pubkey: String,
+        withdrawal_tx_id: String,
+        deposit_tx_id: String,
+        deposit_vout: U64,
+        value: U64,
+        redeem_version: RedeemVersion,
+        is_multisig: bool,
+        /// total deposit of the user after the withdrawal
+        total_deposit: U64,
+    },
+}
+
+impl EventV2 {
+    #[cfg(feature = "near-sdk")]
+    pub fn emit(&self) {
+        near_sdk::env::log_str(&self.to_log());
+    }
+
+    /// Log line of the event in the form of `EVENT_JSON:{...}`
+    pub fn to_log(&self) -> String {
+        event_log(EVENT_STANDARD_VERSION_V2, json!(self))
+    }
+}
+
+fn event_log(version: &str, json: Value) -> String {
+    let event_json = json!({
+        "standard": EVENT_STANDARD,
+        "version": version,
+        "event": json["event"],
+        "data": [json["data"]]
+    });
+    format!("{}{}", EVENT_LOG_PREFIX, event_json)
+}
+
+/// Event decoded from a log line, by the major version of the event standard
+pub enum VersionedEvent {
+    V1(Event),
+    V2(EventV2),
+}
+
+#[derive(Debug)]
+pub enum EventParseError {
+    /// the log is not an event of the bithive standard
+    NotBithiveEvent,
+    /// the major version of the event standard is unknown
+    UnknownVersion(String),
+    /// the event does not match the schema of its version
+    InvalidEvent(serde_json::Error),
+}
+
+#[derive(Deserialize)]
+struct EventLog {
+    version: String,
+    event: String,
+    data: [Value; 1],
+}
+
+/// Decode a log line emitted by the contract, logs of other standards are rejected
+/// with `EventParseError::NotBithiveEvent`
+pub fn parse_event_log(log: &str) -> Result<VersionedEvent, EventParseError> {
+    let json: Value = log
+        .strip_prefix(EVENT_LOG_PREFIX)
+        .and_then(|json| serde_json::from_str(json).ok())
+        .ok_or(EventParseError::NotBithiveEvent)?;
+    if json["standard"] != EVENT_STANDARD {
+        return Err(EventParseError::NotBithiveEvent);
+    }
+    let EventLog {
+        version,
+        event,
+        data: [data],
+    } = serde_json::from_value(json).map_err(EventParseError::InvalidEvent)?;
+    let tagged = json!({ "event": event, "data": data });
+    match version.split('.').next() {
+        Some("1") => serde_json::from_value(tagged).map(VersionedEvent::V1),
+        Some("2") => serde_json::from_value(tagged).map(VersionedEvent::V2),
+        _ => return Err(EventParseError::UnknownVersion(version)),
+    }
+    .map_err(EventParseError::InvalidEvent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_event_log() {
+        let log = Event::Paused { paused: true }.to_log();
+        assert_eq!(
+            log,
+            r#"EVENT_JSON:{"standard":"bithive","version":"1.1.0","event":"paused","data":[{"paused":true}]}"#
+        );
+        assert!(matches!(
+            parse_event_log(&log),
+            Ok(VersionedEvent::V1(Event::Paused { paused: true }))
+        ));
+
+        let log = EventV2::Withdrawn {
+            user_pubkey: "02aa".to_string(),
+            withdrawal_tx_id: "bb".to_string(),
+            deposit_tx_id: "cc".to_string(),
+            deposit_vout: 1.into(),
+            value: 1000.into(),
+            redeem_version: RedeemVersion::V3 {
+                maturity_height: 900000,
+            },
+            is_multisig: false,
+            total_deposit: 0.into(),
+        }
+        .to_log();
+        match parse_event_log(&log) {
+            Ok(VersionedEvent::V2(EventV2::Withdrawn {
+                value,
+                redeem_version: RedeemVersion::V3 { maturity_height },
+                ..
+            })) => {
+                assert_eq!(value.0, 1000);
+                assert_eq!(maturity_height, 900000);
+            }
+            _ => panic!("unexpected event"),
+        }
+    }
+
+    #[test]
+    fn test_parse_non_bithive_event_log() {
+        assert!(matches!(
+            parse_event_log(
+                r#"EVENT_JSON:{"standard":"nep141","version":"1.0.0","event":"ft_transfer","data":[]}"#
+            ),
+            Err(EventParseError::NotBithiveEvent)
+        ));
+        assert!(matches!(
+            parse_event_log("Transaction not confirmed"),
+            Err(EventParseError::NotBithiveEvent)
+        ));
+        assert!(matches!(
+            parse_event_log(
+                r#"EVENT_JSON:{"standard":"bithive","version":"3.0.0","event":"paused","data":[{"paused":true}]}"#
+            ),
+            Err(EventParseError::UnknownVersion(_))
+        ));
+    }
+}
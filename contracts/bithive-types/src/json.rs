@@ -0,0 +1,38 @@
+//! Integers serialized as strings, which could exceed the precision of JSON numbers.
+//! They are encoded the same way as `near_sdk::json_types`
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+macro_rules! impl_str_int {
+    ($name:ident, $inner:ty) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+        pub struct $name(pub $inner);
+
+        impl From<$inner> for $name {
+            fn from(value: $inner) -> Self {
+                Self(value)
+            }
+        }
+
+        impl From<$name> for $inner {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.0.to_string())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = String::deserialize(deserializer)?;
+                s.parse().map(Self).map_err(de::Error::custom)
+            }
+        }
+    };
+}
+
+impl_str_int!(U64, u64);
+impl_str_int!(U128, u128);
@@ -0,0 +1,10 @@
+//! JSON types of the events and view results of the bithive contract,
+//! e.g. for indexers decoding event logs and view call results.
+//! Storage layouts of the contract are not part of this crate.
+//! Enable the `near-sdk` feature to emit events from within a contract
+pub mod account;
+pub mod config;
+pub mod events;
+pub mod json;
+pub mod types;
+pub mod view;
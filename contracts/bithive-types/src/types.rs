@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+/// Roles granted by the owner to call admin methods, the owner could call all of them
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum Role {
+    /// could pause and unpause the contract
+    Pauser,
+    /// could update contract configs and deposit allowlist
+    ConfigManager,
+    /// could manage the denylist and freeze accounts
+    Compliance,
+    /// could upgrade the contract code
+    Upgrader,
+}
+
+/// Who could make deposits
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum DepositMode {
+    /// anyone could deposit
+    Open,
+    /// only users in the deposit allowlist could deposit
+    Allowlist,
+    /// no one could deposit
+    Closed,
+}
+
+/// Withdrawals of at least `min_amount` need to wait for `waiting_time_ms`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WithdrawalWaitingTier {
+    /// minimum withdrawal amount in satoshi
+    pub min_amount: u64,
+    pub waiting_time_ms: u64,
+}
+
+/// Amount limits of deposits in satoshi
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DepositLimits {
+    /// minimum amount of a single deposit
+    pub min_deposit_satoshi: u64,
+    /// maximum amount of a single deposit
+    pub max_deposit_satoshi: Option<u64>,
+    /// maximum total amount of active deposits of a single user
+    pub max_user_deposit_satoshi: Option<u64>,
+}
+
+/// Deposit limits that override the default ones for deposits with the sequence height
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SequenceHeightDepositLimits {
+    pub sequence_height: u16,
+    pub limits: DepositLimits,
+}
+
+/// Version of redeem script
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum RedeemVersion {
+    V1,
+    /// guardian key could spend with bithive key after a long timelock
+    V2 {
+        guardian_pubkey: String,
+        recovery_sequence: u32,
+    },
+    /// fixed-term deposit locked until an absolute BTC block height
+    V3 {
+        maturity_height: u32,
+    },
+}
+
+/// Fixed deposit term configured by the owner
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DepositTerm {
+    /// BTC block height after which the deposit could be withdrawn
+    pub maturity_height: u32,
+    /// stake weight multiplier of deposits with this term in basis points
+    pub stake_weight_bps: u32,
+}
@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+
+use crate::account::{
+    AccountFreeze, PendingWithdrawalPolicy, RecoveryRequest, WithdrawalPolicy, WithdrawalTicket,
+};
+use crate::json::U128;
+use crate::types::{DepositMode, DepositTerm, SequenceHeightDepositLimits, WithdrawalWaitingTier};
+
+#[derive(Serialize, Deserialize)]
+pub struct ContractSummary {
+    pub owner_id: String,
+    pub btc_light_client_id: String,
+    pub bip322_verifier_id: Option<String>,
+    pub chain_signatures_id: String,
+    pub chain_signatures_root_pubkey: Option<String>,
+    pub n_confirmation: u64,
+    pub withdrawal_waiting_time_ms: u64,
+    pub withdrawal_waiting_tiers: Vec<WithdrawalWaitingTier>,
+    pub withdrawal_waiting_btc_blocks: Option<u64>,
+    /// BTC tip height last synced from btc light client, fixed-term deposits can only be signed
+    /// for withdrawal once it reaches their maturity height. It is not synced automatically,
+    /// call `sync_btc_tip_height` first if it is behind the light client
+    pub btc_tip_height: u64,
+    pub pending_sign_psbt_expiry_ms: u64,
+    pub withdrawal_rate_limit: Option<WithdrawalRateLimitView>,
+    pub min_deposit_satoshi: u64,
+    pub max_deposit_satoshi: Option<u64>,
+    pub max_user_deposit_satoshi: Option<u64>,
+    pub sequence_height_deposit_limits: Vec<SequenceHeightDepositLimits>,
+    pub max_total_deposit_satoshi: Option<u64>,
+    pub total_deposit: u64,
+    pub earliest_deposit_block_height: u32,
+    pub solo_withdrawal_sequence_heights: Vec<u16>,
+    pub guardian_recovery_sequence_heights: Vec<u16>,
+    pub guardian_recovery_waiting_time_ms: u64,
+    pub deposit_terms: Vec<DepositTerm>,
+    pub deposit_mode: DepositMode,
+    pub config_timelock_ms: u64,
+    pub upgrade_delay_ms: u64,
+    pub emit_legacy_events: bool,
+    pub paused: bool,
+    /// version of the contract state layout
+    pub state_version: u32,
+    /// redeem script versions accepted for new deposits
+    pub supported_redeem_versions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WithdrawalRateLimitView {
+    pub max_amount: u64,
+    pub window_ms: u64,
+    /// amount already cosigned within the current window
+    pub used_amount: u64,
+}
+
+/// Constants for version 1 of the deposit script
+#[derive(Serialize, Deserialize)]
+pub struct DepositConstantsV1 {
+    /// bithive pubkey used in the deposit script
+    pub bithive_pubkey: String,
+    /// message that needs to be embedded in the deposit transaction via OP_RETURN
+    pub deposit_embed_msg: Option<String>,
+    /// minimum deposit amount in satoshi
+    pub min_deposit_satoshi: u64,
+    /// earliest deposit block height
+    pub earliest_deposit_block_height: u32,
+    /// the current active value of sequence height for solo withdrawal
+    pub solo_withdrawal_sequence_height: u16,
+}
+
+/// Constants for version 2 of the deposit script, which has a guardian recovery path
+#[derive(Serialize, Deserialize)]
+pub struct DepositConstantsV2 {
+    /// bithive pubkey used in the deposit script
+    pub bithive_pubkey: String,
+    /// message that needs to be embedded in the deposit transaction via OP_RETURN
+    pub deposit_embed_msg: Option<String>,
+    /// minimum deposit amount in satoshi
+    pub min_deposit_satoshi: u64,
+    /// earliest deposit block height
+    pub earliest_deposit_block_height: u32,
+    /// the current active value of sequence height for solo withdrawal
+    pub solo_withdrawal_sequence_height: u16,
+    /// the current active value of sequence height for guardian recovery
+    pub guardian_recovery_sequence_height: u16,
+}
+
+/// Constants for version 3 of the deposit script, which is locked until a maturity height
+#[derive(Serialize, Deserialize)]
+pub struct DepositConstantsV3 {
+    /// bithive pubkey used in the deposit script
+    pub bithive_pubkey: String,
+    /// message that needs to be embedded in the deposit transaction via OP_RETURN
+    pub deposit_embed_msg: Option<String>,
+    /// minimum deposit amount in satoshi
+    pub min_deposit_satoshi: u64,
+    /// earliest deposit block height
+    pub earliest_deposit_block_height: u32,
+    /// the current active value of sequence height for solo withdrawal
+    pub solo_withdrawal_sequence_height: u16,
+    /// available deposit terms
+    pub deposit_terms: Vec<DepositTerm>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AccountView {
+    pub pubkey: String,
+    /// total deposit amount in full BTC decimals
+    pub total_deposit: u64,
+    /// total deposit amount weighted by the stake weight of each deposit
+    pub total_weighted_deposit: u64,
+    /// amount of deposits queued for withdrawal in full BTC decimals
+    pub queue_withdrawal_amount: u64,
+    /// timestamp when the oldest withdrawal ticket is queued in ms, 0 if nothing is queued
+    pub queue_withdrawal_start_ts: u64,
+    /// latest `unlock_ts` of the withdrawal tickets in ms, 0 if nothing is queued.
+    /// Tickets unlock independently, see `withdrawal_tickets` for when each one can be signed
+    pub queue_withdrawal_end_ts: u64,
+    /// queued withdrawal tickets, from the oldest to the newest
+    pub withdrawal_tickets: Vec<WithdrawalTicketView>,
+    /// nonce is used in signing messages to prevent replay attacks
+    pub nonce: u64,
+    /// nonce of the messages signed by guardians to request recovery
+    pub guardian_nonce: u64,
+    /// number of withdrawal PSBTs that need to be signed via chain signatures
+    pub pending_sign_psbts_len: u64,
+    /// deposit user paid to cover the storage of pending sign PSBTs
+    /// this should only be increased when needed
+    pub pending_sign_deposit: U128,
+    /// withdrawal policy currently in effect
+    pub withdrawal_policy: WithdrawalPolicy,
+    /// looser withdrawal policy that is waiting to take effect
+    pub pending_withdrawal_policy: Option<PendingWithdrawalPolicy>,
+    /// amount withdrawn via multisig within the last day, only recorded if there is a daily limit
+    pub daily_withdrawal_amount: u64,
+    /// recovery requests made by guardians of the deposits
+    pub recovery_requests: Vec<RecoveryRequest>,
+    /// set if the account is frozen for compliance reasons
+    pub freeze: Option<AccountFreeze>,
+    /// number of deposits still stored in a legacy layout
+    pub unmigrated_deposits: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WithdrawalTicketView {
+    #[serde(flatten)]
+    pub ticket: WithdrawalTicket,
+    /// timestamp when the ticket can be signed for withdrawal in ms,
+    /// empty if the ticket is waiting for BTC blocks
+    pub unlock_ts: Option<u64>,
+    /// BTC block height when the ticket can be signed for withdrawal,
+    /// empty if the ticket is waiting for a period of time
+    pub unlock_btc_height: Option<u64>,
+}
+
+/// Constants for withdrawing v1 deposits
+#[derive(Serialize, Deserialize)]
+pub struct WithdrawalConstantsV1 {
+    /// raw message that needs to be signed by the user for queueing withdrawal
+    pub queue_withdrawal_msg: String,
+}
+
+/// Contract source metadata as defined in NEP-330
+#[derive(Serialize, Deserialize)]
+pub struct ContractSourceMetadata {
+    /// crate version of the contract
+    pub version: Option<String>,
+    /// link to the source code repository
+    pub link: Option<String>,
+    /// standards implemented by the contract
+    pub standards: Vec<Standard>,
+    pub build_info: Option<BuildInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Standard {
+    pub standard: String,
+    pub version: String,
+}
+
+/// How the deployed code was built, so that it could be reproduced from the source
+#[derive(Serialize, Deserialize)]
+pub struct BuildInfo {
+    /// toolchain the code was built with
+    pub build_environment: String,
+    pub build_command: Vec<String>,
+    /// path of the contract crate within the repository
+    pub contract_path: String,
+    /// source revision in the form of `git+{repository}?rev={commit}`
+    pub source_code_snapshot: String,
+}
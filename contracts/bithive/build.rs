@@ -0,0 +1,18 @@
+/// Pass the source revision and toolchain set by the reproducible-build pipeline into the contract,
+/// so that a deployed build could be identified on chain via `contract_source_metadata`.
+/// Nothing is probed from the local checkout or toolchain, so identical sources with identical
+/// variables always produce the same code hash. Unset variables fall back to empty values
+const BUILD_ENV_VARS: &[&str] = &[
+    "BITHIVE_GIT_COMMIT",
+    "BITHIVE_GIT_REPOSITORY",
+    "BITHIVE_RUSTC_VERSION",
+];
+
+fn main() {
+    for key in BUILD_ENV_VARS {
+        let value = std::env::var(key).unwrap_or_default();
+        println!("cargo:rustc-env={}={}", key, value);
+        println!("cargo:rerun-if-env-changed={}", key);
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
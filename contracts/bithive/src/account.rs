@@ -1,15 +1,21 @@
 use std::cmp::min;
 
+use bitcoin::TxIn;
 use near_sdk::{
     borsh::{self, BorshDeserialize, BorshSerialize},
     collections::UnorderedMap,
     require, Balance, Timestamp,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     events::Event,
-    types::{output_id, OutputId, PendingSignPsbt, PubKey, RedeemVersion, StorageKey, TxId},
+    legacy::{AccountV1, DepositV1, PendingSignPsbtV1},
+    rate_limit::WithdrawalRateLimit,
+    types::{
+        output_id, OutputId, PendingSignPsbt, PubKey, RedeemVersion, StorageKey, TxId,
+        WithdrawalWaitingTier,
+    },
     utils::current_timestamp_ms,
 };
 
@@ -19,26 +25,62 @@ const ERR_DEPOSIT_ALREADY_WITHDRAWN: &str = "Deposit already withdrawn";
 
 const ERR_INVALID_QUEUE_WITHDRAWAL: &str = "Invalid queue withdrawal amount";
 
+pub(crate) const ERR_PENDING_SIGN_PSBT_NOT_FOUND: &str = "Pending sign PSBT not found";
+pub(crate) const ERR_PENDING_SIGN_PSBT_SIGNED: &str = "Pending sign PSBT already signed by bithive";
+
+const ERR_RECOVERY_ALREADY_REQUESTED: &str = "Recovery already requested";
+pub(crate) const ERR_RECOVERY_NOT_REQUESTED: &str = "Recovery not requested";
+
+const ERR_DAILY_WITHDRAWAL_LIMIT_EXCEEDED: &str = "Daily withdrawal limit exceeded";
+
+const ERR_ACCOUNT_FROZEN: &str = "Account is frozen";
+
+const DAY_MS: u64 = 24 * 3600 * 1000;
+
+/// stake weight of a deposit without any boost, in basis points
+pub const BASE_STAKE_WEIGHT_BPS: u32 = 10_000;
+/// max stake weight of a deposit, which keeps the weighted value of any BTC amount within u64
+pub const MAX_STAKE_WEIGHT_BPS: u32 = 10 * BASE_STAKE_WEIGHT_BPS;
+
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct Account {
     pub pubkey: PubKey,
     /// total deposit amount in full BTC decimals
     pub total_deposit: u64,
+    /// total deposit amount weighted by the stake weight of each deposit
+    pub total_weighted_deposit: u64,
     /// set of deposits that are not known to be withdrawn
     active_deposits: UnorderedMap<OutputId, VersionedDeposit>,
     /// set of deposits that are confirmed to have been withdrawn
     withdrawn_deposits: UnorderedMap<OutputId, VersionedDeposit>,
-    /// amount of deposits queued for withdrawal in full BTC decimals
-    pub queue_withdrawal_amount: u64,
-    /// timestamp when the queue withdrawal started in ms
-    pub queue_withdrawal_start_ts: Timestamp,
+    /// withdrawal requests queued by the user, from the oldest to the newest
+    withdrawal_tickets: Vec<WithdrawalTicket>,
     /// nonce is used in signing messages to prevent replay attacks
     pub nonce: u64,
-    /// PSBT of the withdrawal txn that needs to be signed via chain signatures
-    pub pending_sign_psbt: Option<PendingSignPsbt>,
-    /// deposit user paid to cover the storage of pending sign PSBT
+    /// nonce of the messages signed by guardians, kept apart from `nonce` of the user
+    pub guardian_nonce: u64,
+    /// PSBTs of the withdrawal txns that need to be signed via chain signatures: txn ID -> PSBT
+    /// each of them spends a disjoint set of deposits
+    pending_sign_psbts: UnorderedMap<TxId, PendingSignPsbt>,
+    /// pending sign PSBT of a V1 account upgraded on read, which is moved into
+    /// `pending_sign_psbts` when the account is written
+    #[borsh_skip]
+    legacy_pending_sign_psbt: Option<PendingSignPsbt>,
+    /// deposit user paid to cover the storage of pending sign PSBTs
     /// this should only be increased when needed
     pub pending_sign_deposit: Balance,
+    /// withdrawal restrictions set by the user
+    withdrawal_policy: WithdrawalPolicy,
+    /// a looser withdrawal policy that takes effect later
+    pending_withdrawal_policy: Option<PendingWithdrawalPolicy>,
+    /// amounts withdrawn via multisig within the last day, only recorded if there is a daily limit
+    daily_withdrawals: WithdrawalRateLimit,
+    /// recovery requests made by guardians of the deposits
+    recovery_requests: Vec<RecoveryRequest>,
+    /// frozen accounts cannot queue withdrawals or get multisig signatures
+    freeze: Option<AccountFreeze>,
+    /// number of deposits still stored in a legacy layout
+    unmigrated_deposits: u64,
 }
 
 impl Account {
@@ -46,13 +88,21 @@ impl Account {
         Account {
             pubkey: pubkey.clone(),
             total_deposit: 0,
+            total_weighted_deposit: 0,
             active_deposits: UnorderedMap::new(StorageKey::ActiveDeposits(pubkey.clone())),
-            withdrawn_deposits: UnorderedMap::new(StorageKey::WithdrawnDeposits(pubkey)),
-            queue_withdrawal_amount: 0,
-            queue_withdrawal_start_ts: 0,
+            withdrawn_deposits: UnorderedMap::new(StorageKey::WithdrawnDeposits(pubkey.clone())),
+            withdrawal_tickets: vec![],
             nonce: 0,
-            pending_sign_psbt: None,
+            guardian_nonce: 0,
+            pending_sign_psbts: UnorderedMap::new(StorageKey::PendingSignPsbts(pubkey)),
+            legacy_pending_sign_psbt: None,
             pending_sign_deposit: 0,
+            withdrawal_policy: WithdrawalPolicy::default(),
+            pending_withdrawal_policy: None,
+            daily_withdrawals: WithdrawalRateLimit::new(0, DAY_MS),
+            recovery_requests: vec![],
+            freeze: None,
+            unmigrated_deposits: 0,
         }
     }
 
@@ -82,10 +132,15 @@ impl Account {
     }
 
     pub fn remove_active_deposit(&mut self, tx_id: &TxId, vout: u64) -> Deposit {
-        self.active_deposits
+        let deposit = self
+            .active_deposits
             .remove(&output_id(tx_id, vout))
-            .expect(ERR_DEPOSIT_NOT_ACTIVE)
-            .into()
+            .expect(ERR_DEPOSIT_NOT_ACTIVE);
+        // the deposit is rewritten in the current layout when it's inserted as withdrawn
+        if !deposit.is_current() {
+            self.unmigrated_deposits = self.unmigrated_deposits.saturating_sub(1);
+        }
+        deposit.into()
     }
 
     pub fn try_get_active_deposit(&self, tx_id: &TxId, vout: u64) -> Option<Deposit> {
@@ -101,6 +156,34 @@ impl Account {
             .into()
     }
 
+    pub fn unmigrated_deposits(&self) -> u64 {
+        self.unmigrated_deposits
+    }
+
+    /// Rewrite all deposits stored in a legacy layout
+    /// ### Returns
+    /// number of deposits rewritten
+    pub fn migrate_deposits(&mut self) -> u64 {
+        if self.unmigrated_deposits == 0 {
+            return 0;
+        }
+        let mut migrated = 0;
+        for deposits in [&mut self.active_deposits, &mut self.withdrawn_deposits] {
+            let legacy_ids: Vec<OutputId> = deposits
+                .iter()
+                .filter(|(_, d)| !d.is_current())
+                .map(|(id, _)| id)
+                .collect();
+            for id in legacy_ids {
+                let deposit: Deposit = deposits.get(&id).unwrap().into();
+                deposits.insert(&id, &deposit.into());
+                migrated += 1;
+            }
+        }
+        self.unmigrated_deposits = 0;
+        migrated
+    }
+
     pub fn withdrawn_deposits_len(&self) -> u64 {
         self.withdrawn_deposits.len()
     }
@@ -127,6 +210,142 @@ impl Account {
         self.withdrawn_deposits.insert(deposit_id, &deposit.into());
     }
 
+    pub fn withdrawal_tickets(&self) -> &[WithdrawalTicket] {
+        &self.withdrawal_tickets
+    }
+
+    /// total amount of deposits queued for withdrawal in full BTC decimals
+    pub fn queue_withdrawal_amount(&self) -> u64 {
+        self.withdrawal_tickets
+            .iter()
+            .map(|ticket| ticket.amount)
+            .sum()
+    }
+
+    /// amount of the queued withdrawal tickets whose waiting time has passed
+    pub fn matured_withdrawal_amount(&self, waiting_time: &WithdrawalWaitingTime) -> u64 {
+        self.withdrawal_tickets
+            .iter()
+            .filter(|ticket| ticket.is_matured(waiting_time))
+            .map(|ticket| ticket.amount)
+            .sum()
+    }
+
+    /// Take the given amount from matured withdrawal tickets, the oldest ones are used first
+    pub fn take_matured_withdrawal_amount(
+        &mut self,
+        amount: u64,
+        waiting_time: &WithdrawalWaitingTime,
+    ) {
+        require!(
+            amount <= self.matured_withdrawal_amount(waiting_time),
+            ERR_INVALID_QUEUE_WITHDRAWAL
+        );
+        let mut remaining = amount;
+        for ticket in self.withdrawal_tickets.iter_mut() {
+            if remaining == 0 {
+                break;
+            }
+            if !ticket.is_matured(waiting_time) {
+                continue;
+            }
+            let taken = min(ticket.amount, remaining);
+            ticket.amount -= taken;
+            remaining -= taken;
+        }
+        self.withdrawal_tickets.retain(|ticket| ticket.amount > 0);
+    }
+
+    /// Make sure the queued amount does not exceed the given amount,
+    /// the newest tickets are reduced first
+    fn trim_withdrawal_tickets(&mut self, max_amount: u64) {
+        let mut excess = self.queue_withdrawal_amount().saturating_sub(max_amount);
+        for ticket in self.withdrawal_tickets.iter_mut().rev() {
+            if excess == 0 {
+                break;
+            }
+            let trimmed = min(ticket.amount, excess);
+            ticket.amount -= trimmed;
+            excess -= trimmed;
+        }
+        self.withdrawal_tickets.retain(|ticket| ticket.amount > 0);
+    }
+
+    pub fn pending_sign_psbts_len(&self) -> u64 {
+        self.pending_sign_psbts.len() + self.legacy_pending_sign_psbt.is_some() as u64
+    }
+
+    pub fn get_pending_sign_psbt_by_index(&self, idx: u64) -> Option<PendingSignPsbt> {
+        self.pending_sign_psbt_values().nth(idx as usize)
+    }
+
+    pub fn get_pending_sign_psbt(&self, tx_id: &TxId) -> Option<PendingSignPsbt> {
+        match &self.legacy_pending_sign_psbt {
+            Some(legacy) if &legacy.tx_id() == tx_id => Some(legacy.clone()),
+            _ => self.pending_sign_psbts.get(tx_id),
+        }
+    }
+
+    /// Find the pending sign PSBT that spends any of the given inputs
+    pub fn find_pending_sign_psbt(&self, inputs: &[TxIn]) -> Option<PendingSignPsbt> {
+        self.pending_sign_psbt_values()
+            .find(|pending_sign_psbt| pending_sign_psbt.spends_any(inputs))
+    }
+
+    /// total size in bytes of all pending sign PSBTs
+    pub fn pending_sign_psbts_size(&self) -> u64 {
+        self.pending_sign_psbt_values()
+            .map(|pending_sign_psbt| pending_sign_psbt.psbt.as_ref().serialize().len() as u64)
+            .sum()
+    }
+
+    /// total amount taken from the queue by pending sign PSBTs
+    pub fn pending_sign_withdraw_amount(&self) -> u64 {
+        self.pending_sign_psbt_values()
+            .map(|pending_sign_psbt| pending_sign_psbt.withdraw_amount)
+            .sum()
+    }
+
+    pub fn insert_pending_sign_psbt(&mut self, pending_sign_psbt: PendingSignPsbt) {
+        if self
+            .legacy_pending_sign_psbt
+            .as_ref()
+            .is_some_and(|legacy| legacy.tx_id() == pending_sign_psbt.tx_id())
+        {
+            self.legacy_pending_sign_psbt = None;
+        }
+        self.pending_sign_psbts
+            .insert(&pending_sign_psbt.tx_id(), &pending_sign_psbt);
+    }
+
+    pub fn remove_pending_sign_psbt(&mut self, tx_id: &TxId) -> PendingSignPsbt {
+        if self
+            .legacy_pending_sign_psbt
+            .as_ref()
+            .is_some_and(|legacy| &legacy.tx_id() == tx_id)
+        {
+            return self.legacy_pending_sign_psbt.take().unwrap();
+        }
+        self.pending_sign_psbts
+            .remove(tx_id)
+            .expect(ERR_PENDING_SIGN_PSBT_NOT_FOUND)
+    }
+
+    fn pending_sign_psbt_values(&self) -> impl Iterator<Item = PendingSignPsbt> + '_ {
+        self.legacy_pending_sign_psbt
+            .iter()
+            .cloned()
+            .chain(self.pending_sign_psbts.values())
+    }
+
+    /// Remove all pending sign PSBTs that spend any of the given inputs,
+    /// they are no longer valid once the inputs are spent
+    pub fn clear_pending_sign_psbts(&mut self, inputs: &[TxIn]) {
+        while let Some(pending_sign_psbt) = self.find_pending_sign_psbt(inputs) {
+            self.remove_pending_sign_psbt(&pending_sign_psbt.tx_id());
+        }
+    }
+
     pub fn create_deposit(&mut self, deposit: Deposit) {
         // make sure the deposit is not in withdrawn set
         require!(
@@ -134,38 +353,275 @@ impl Account {
                 .is_none(),
             ERR_DEPOSIT_ALREADY_WITHDRAWN
         );
-        let value = deposit.value;
-        let vout = deposit.deposit_vout;
-        let tx_id = deposit.deposit_tx_id.clone();
-
-        self.total_deposit += value;
+        self.total_deposit += deposit.value;
+        self.total_weighted_deposit += deposit.weighted_value();
         // this makes sure the deposit is not in active set
         self.insert_active_deposit(deposit);
+    }
 
-        Event::Deposit {
-            user_pubkey: &self.pubkey.clone().into(),
-            tx_id: &tx_id.into(),
-            deposit_vout: vout.into(),
-            value: value.into(),
-        }
-        .emit();
+    /// total amount queued for withdrawal or taken by pending sign PSBTs
+    fn outstanding_withdrawal_amount(&self) -> u64 {
+        self.queue_withdrawal_amount() + self.pending_sign_withdraw_amount()
     }
 
-    pub fn queue_withdrawal(&mut self, amount: u64, msg: Vec<u8>, msg_sig: &String) {
+    pub fn queue_withdrawal(
+        &mut self,
+        amount: u64,
+        waiting_time: &WithdrawalWaitingTime,
+        btc_tip_height: Option<u64>,
+        msg: Vec<u8>,
+        msg_sig: &str,
+    ) {
+        self.assert_not_frozen();
+        // amount taken by pending sign PSBTs is not withdrawn yet
+        let outstanding_amount = self.outstanding_withdrawal_amount() + amount;
         require!(
-            self.queue_withdrawal_amount + amount <= self.total_deposit,
+            outstanding_amount <= self.total_deposit,
             ERR_INVALID_QUEUE_WITHDRAWAL
         );
-        self.queue_withdrawal_amount += amount;
-        self.queue_withdrawal_start_ts = current_timestamp_ms();
+        // the tier is picked by the total outstanding amount, so splitting a large withdrawal
+        // into smaller tickets or PSBTs does not shorten its waiting time
+        self.withdrawal_tickets.push(WithdrawalTicket::new(
+            self.nonce,
+            amount,
+            waiting_time.tier_ms(outstanding_amount),
+            btc_tip_height,
+        ));
         self.nonce += 1;
-        self.pending_sign_psbt = None;
 
         Event::QueueWithdrawal {
-            user_pubkey: &self.pubkey.clone().into(),
+            user_pubkey: self.pubkey.to_string(),
             amount: amount.into(),
-            withdrawal_msg: &hex::encode(msg),
-            withdrawal_sig: msg_sig,
+            withdrawal_msg: hex::encode(msg),
+            withdrawal_sig: msg_sig.to_string(),
+        }
+        .emit();
+    }
+
+    pub fn cancel_pending_sign_psbt(
+        &mut self,
+        tx_id: &TxId,
+        waiting_time: &WithdrawalWaitingTime,
+        btc_tip_height: Option<u64>,
+        msg: Vec<u8>,
+        msg_sig: &str,
+    ) -> PendingSignPsbt {
+        // a signed txn could still be broadcasted, so its amount must stay reserved
+        require!(
+            !self
+                .get_pending_sign_psbt(tx_id)
+                .expect(ERR_PENDING_SIGN_PSBT_NOT_FOUND)
+                .bithive_signed,
+            ERR_PENDING_SIGN_PSBT_SIGNED
+        );
+        let pending_sign_psbt = self.discard_pending_sign_psbt(tx_id, waiting_time, btc_tip_height);
+        self.nonce += 1;
+
+        Event::CancelPendingSignPsbt {
+            user_pubkey: self.pubkey.to_string(),
+            tx_id: tx_id.to_string(),
+            cancel_msg: hex::encode(msg),
+            cancel_sig: msg_sig.to_string(),
+        }
+        .emit();
+
+        pending_sign_psbt
+    }
+
+    pub fn expire_pending_sign_psbt(
+        &mut self,
+        tx_id: &TxId,
+        waiting_time: &WithdrawalWaitingTime,
+        btc_tip_height: Option<u64>,
+    ) -> PendingSignPsbt {
+        // a signed txn could still be broadcasted after it expires,
+        // its amount is released once the inputs are spent instead
+        require!(
+            !self
+                .get_pending_sign_psbt(tx_id)
+                .expect(ERR_PENDING_SIGN_PSBT_NOT_FOUND)
+                .bithive_signed,
+            ERR_PENDING_SIGN_PSBT_SIGNED
+        );
+        let pending_sign_psbt = self.discard_pending_sign_psbt(tx_id, waiting_time, btc_tip_height);
+        // the restored withdrawal ticket takes the current nonce
+        self.nonce += 1;
+
+        Event::PendingSignPsbtExpired {
+            user_pubkey: self.pubkey.to_string(),
+            tx_id: tx_id.to_string(),
+        }
+        .emit();
+
+        pending_sign_psbt
+    }
+
+    /// Remove a pending sign PSBT that will not be broadcasted and put its amount back to the queue
+    /// as a new withdrawal ticket, which needs to wait again
+    fn discard_pending_sign_psbt(
+        &mut self,
+        tx_id: &TxId,
+        waiting_time: &WithdrawalWaitingTime,
+        btc_tip_height: Option<u64>,
+    ) -> PendingSignPsbt {
+        // the amount stays outstanding, only moved from the PSBT back to the queue
+        let waiting_time_ms = waiting_time.tier_ms(self.outstanding_withdrawal_amount());
+        let pending_sign_psbt = self.remove_pending_sign_psbt(tx_id);
+        if pending_sign_psbt.withdraw_amount > 0 {
+            self.withdrawal_tickets.push(WithdrawalTicket::new(
+                self.nonce,
+                pending_sign_psbt.withdraw_amount,
+                waiting_time_ms,
+                btc_tip_height,
+            ));
+        }
+        // the storage deposit is refunded to its payer
+        self.pending_sign_deposit = self
+            .pending_sign_deposit
+            .saturating_sub(pending_sign_psbt.storage_deposit.0);
+        pending_sign_psbt
+    }
+
+    /// withdrawal policy currently in effect
+    pub fn withdrawal_policy(&self) -> &WithdrawalPolicy {
+        match &self.pending_withdrawal_policy {
+            Some(pending) if pending.is_effective() => &pending.policy,
+            _ => &self.withdrawal_policy,
+        }
+    }
+
+    /// looser withdrawal policy that is not in effect yet
+    pub fn pending_withdrawal_policy(&self) -> Option<&PendingWithdrawalPolicy> {
+        self.pending_withdrawal_policy
+            .as_ref()
+            .filter(|pending| !pending.is_effective())
+    }
+
+    /// A stricter policy takes effect immediately,
+    /// while a looser one only takes effect after the delay of the current policy
+    pub fn set_withdrawal_policy(
+        &mut self,
+        policy: &WithdrawalPolicy,
+        msg: Vec<u8>,
+        msg_sig: &str,
+    ) {
+        if let Some(pending) = self.pending_withdrawal_policy.take() {
+            if pending.is_effective() {
+                self.withdrawal_policy = pending.policy;
+            }
+        }
+
+        let effective_ts = if self.withdrawal_policy.is_loosened_by(policy) {
+            let effective_ts = current_timestamp_ms() + self.withdrawal_policy.loosen_delay_ms;
+            self.pending_withdrawal_policy = Some(PendingWithdrawalPolicy {
+                policy: policy.clone(),
+                effective_ts,
+            });
+            effective_ts
+        } else {
+            self.withdrawal_policy = policy.clone();
+            current_timestamp_ms()
+        };
+        self.nonce += 1;
+
+        Event::SetWithdrawalPolicy {
+            user_pubkey: self.pubkey.to_string(),
+            policy: policy.clone().into(),
+            effective_ts: effective_ts.into(),
+            policy_msg: hex::encode(msg),
+            policy_sig: msg_sig.to_string(),
+        }
+        .emit();
+    }
+
+    /// Record the amount withdrawn via multisig against the daily limit if there is one.
+    /// Returns the timestamp of the record, which is needed to release it
+    pub fn record_daily_withdrawal(&mut self, amount: u64) -> Option<Timestamp> {
+        let daily_limit = self.withdrawal_policy().daily_limit?;
+        self.daily_withdrawals.max_amount = daily_limit;
+        let ts = self
+            .daily_withdrawals
+            .try_consume(amount, current_timestamp_ms())
+            .expect(ERR_DAILY_WITHDRAWAL_LIMIT_EXCEEDED);
+        Some(ts)
+    }
+
+    /// Verify the amount could be withdrawn via multisig without exceeding the daily limit
+    pub fn verify_daily_withdrawal(&self, amount: u64) {
+        if let Some(daily_limit) = self.withdrawal_policy().daily_limit {
+            require!(
+                self.daily_withdrawal_amount().saturating_add(amount) <= daily_limit,
+                ERR_DAILY_WITHDRAWAL_LIMIT_EXCEEDED
+            );
+        }
+    }
+
+    /// Forget the amount recorded at `ts` against the daily limit if it ends up not being cosigned
+    pub fn release_daily_withdrawal(&mut self, ts: Timestamp, amount: u64) {
+        self.daily_withdrawals.release(ts, amount);
+    }
+
+    /// amount withdrawn via multisig within the last day
+    pub fn daily_withdrawal_amount(&self) -> u64 {
+        self.daily_withdrawals.used_amount(current_timestamp_ms())
+    }
+
+    pub fn freeze(&self) -> Option<&AccountFreeze> {
+        self.freeze.as_ref()
+    }
+
+    pub fn set_freeze(&mut self, freeze: Option<AccountFreeze>) {
+        self.freeze = freeze;
+    }
+
+    pub fn assert_not_frozen(&self) {
+        require!(self.freeze.is_none(), ERR_ACCOUNT_FROZEN);
+    }
+
+    pub fn recovery_requests(&self) -> &[RecoveryRequest] {
+        &self.recovery_requests
+    }
+
+    pub fn get_recovery_request(&self, deposit_id: &OutputId) -> Option<&RecoveryRequest> {
+        self.recovery_requests
+            .iter()
+            .find(|request| &request.deposit_id == deposit_id)
+    }
+
+    pub fn request_recovery(&mut self, deposit_id: &OutputId, msg: Vec<u8>, msg_sig: &str) {
+        require!(
+            self.get_recovery_request(deposit_id).is_none(),
+            ERR_RECOVERY_ALREADY_REQUESTED
+        );
+        self.recovery_requests.push(RecoveryRequest {
+            deposit_id: deposit_id.clone(),
+            start_ts: current_timestamp_ms(),
+        });
+        self.guardian_nonce += 1;
+
+        Event::RequestRecovery {
+            user_pubkey: self.pubkey.to_string(),
+            deposit_id: deposit_id.to_string(),
+            recovery_msg: hex::encode(msg),
+            recovery_sig: msg_sig.to_string(),
+        }
+        .emit();
+    }
+
+    pub fn cancel_recovery(&mut self, deposit_id: &OutputId, msg: Vec<u8>, msg_sig: &str) {
+        let idx = self
+            .recovery_requests
+            .iter()
+            .position(|request| &request.deposit_id == deposit_id)
+            .expect(ERR_RECOVERY_NOT_REQUESTED);
+        self.recovery_requests.remove(idx);
+        self.nonce += 1;
+
+        Event::CancelRecovery {
+            user_pubkey: self.pubkey.to_string(),
+            deposit_id: deposit_id.to_string(),
+            cancel_msg: hex::encode(msg),
+            cancel_sig: msg_sig.to_string(),
         }
         .emit();
     }
@@ -173,60 +629,281 @@ impl Account {
     pub fn complete_withdrawal(&mut self, mut deposit: Deposit, tx_id: &TxId, is_multisig: bool) {
         let deposit_tx_id = deposit.deposit_tx_id.clone();
         let deposit_vout = deposit.deposit_vout;
+        // the deposit can no longer be recovered
+        let deposit_id = deposit.id();
+        self.recovery_requests
+            .retain(|request| request.deposit_id != deposit_id);
 
         deposit.complete_withdrawal(tx_id.clone());
         self.total_deposit -= deposit.value;
+        self.total_weighted_deposit -= deposit.weighted_value();
 
         // for non-multisig withdrawal, we need to update the queue withdrawal amount
         // the case for multisig withdrawal is handled during sign withdrawal
         if !is_multisig {
-            self.queue_withdrawal_amount = min(self.total_deposit, self.queue_withdrawal_amount);
-            if self.queue_withdrawal_amount == 0 {
-                self.queue_withdrawal_start_ts = 0;
-            }
+            self.trim_withdrawal_tickets(self.total_deposit);
         }
 
         self.remove_active_deposit(&deposit_tx_id, deposit_vout);
         self.insert_withdrawn_deposit(deposit);
-
-        Event::Withdrawn {
-            user_pubkey: &self.pubkey.clone().into(),
-            withdrawal_tx_id: &tx_id.to_owned().into(),
-            deposit_tx_id: &deposit_tx_id.into(),
-            deposit_vout: deposit_vout.into(),
-            is_multisig,
-        }
-        .emit();
     }
 }
 
+/// Legacy variants are upgraded on read and rewritten in the current layout on next write.
+/// New variants should be added right before `Current`, whose tag changes accordingly
 #[derive(BorshDeserialize, BorshSerialize)]
 pub enum VersionedAccount {
+    V1(AccountV1),
     Current(Account),
 }
 
+impl VersionedAccount {
+    pub fn is_current(&self) -> bool {
+        matches!(self, VersionedAccount::Current(_))
+    }
+}
+
 impl From<VersionedAccount> for Account {
     fn from(value: VersionedAccount) -> Self {
         match value {
+            VersionedAccount::V1(a) => a.into(),
             VersionedAccount::Current(a) => a,
         }
     }
 }
 
+impl Account {
+    /// V1 took the whole queued amount when a PSBT was signed without recording it,
+    /// so the withdraw amount is recovered from the deposits it spends
+    fn pending_sign_psbt_from_v1(&self, legacy: PendingSignPsbtV1) -> PendingSignPsbt {
+        let psbt: bitcoin::Psbt = legacy.psbt.into();
+        let deposit_input_sum = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .filter_map(|input| {
+                self.try_get_active_deposit(
+                    &input.previous_output.txid.to_string().into(),
+                    input.previous_output.vout.into(),
+                )
+            })
+            .map(|deposit| deposit.value)
+            .sum::<u64>();
+        let reinvest_amount = legacy
+            .reinvest_deposit_vout
+            .and_then(|vout| psbt.unsigned_tx.output.get(vout as usize))
+            .map(|output| output.value.to_sat())
+            .unwrap_or(0);
+        PendingSignPsbt {
+            psbt: psbt.into(),
+            withdraw_amount: deposit_input_sum.saturating_sub(reinvest_amount),
+            reinvest_deposit_vout: legacy.reinvest_deposit_vout,
+            reinvest_embed_vout: legacy.reinvest_embed_vout,
+            sign_start_ts: current_timestamp_ms(),
+            // V1 storage is covered by `pending_sign_deposit` of the account, nothing to refund
+            storage_deposit: 0.into(),
+            storage_payer: near_sdk::env::current_account_id(),
+            // V1 only kept the PSBT once it's signed
+            bithive_signed: true,
+            daily_withdrawal_ts: None,
+            rate_limit_ts: None,
+        }
+    }
+}
+
+impl From<AccountV1> for Account {
+    /// The pending sign PSBT is kept in memory, since views cannot write it to the storage
+    fn from(value: AccountV1) -> Self {
+        let mut account = Account::new(value.pubkey);
+        account.total_deposit = value.total_deposit;
+        // all V1 deposits have the base stake weight
+        account.total_weighted_deposit = value.total_deposit;
+        account.unmigrated_deposits = value.active_deposits.len() + value.withdrawn_deposits.len();
+        account.active_deposits = value.active_deposits;
+        account.withdrawn_deposits = value.withdrawn_deposits;
+        if value.queue_withdrawal_amount > 0 {
+            account.withdrawal_tickets.push(WithdrawalTicket {
+                nonce: value.nonce.saturating_sub(1),
+                amount: value.queue_withdrawal_amount,
+                start_ts: value.queue_withdrawal_start_ts,
+                waiting_time_ms: None,
+                start_btc_height: None,
+            });
+        }
+        account.nonce = value.nonce;
+        account.pending_sign_deposit = value.pending_sign_deposit;
+        account.legacy_pending_sign_psbt = value
+            .pending_sign_psbt
+            .map(|legacy| account.pending_sign_psbt_from_v1(legacy));
+        account
+    }
+}
+
 impl From<Account> for VersionedAccount {
-    fn from(value: Account) -> Self {
+    fn from(mut value: Account) -> Self {
+        // not serialized along with the account
+        if let Some(legacy) = value.legacy_pending_sign_psbt.take() {
+            value.pending_sign_psbts.insert(&legacy.tx_id(), &legacy);
+        }
         VersionedAccount::Current(value)
     }
 }
 
-#[derive(Serialize, BorshDeserialize, BorshSerialize)]
+/// A queued withdrawal request, which can be signed once its waiting time has passed
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawalTicket {
+    /// nonce of the signed queue withdrawal message
+    pub nonce: u64,
+    /// amount to withdraw in full BTC decimals
+    pub amount: u64,
+    /// timestamp when the ticket is queued in ms
+    pub start_ts: Timestamp,
+    /// waiting time in ms fixed by the tier when the ticket is queued,
+    /// empty for tickets migrated from the legacy queue, which wait the base waiting time
+    pub waiting_time_ms: Option<u64>,
+    /// BTC tip height when the ticket is queued, only recorded if waiting time is measured in BTC blocks
+    pub start_btc_height: Option<u64>,
+}
+
+impl WithdrawalTicket {
+    pub fn new(
+        nonce: u64,
+        amount: u64,
+        waiting_time_ms: u64,
+        start_btc_height: Option<u64>,
+    ) -> Self {
+        Self {
+            nonce,
+            amount,
+            start_ts: current_timestamp_ms(),
+            waiting_time_ms: Some(waiting_time_ms),
+            start_btc_height,
+        }
+    }
+
+    /// Returns None if the ticket is waiting for BTC blocks
+    pub fn unlock_ts(&self, waiting_time: &WithdrawalWaitingTime) -> Option<Timestamp> {
+        match self.unlock_btc_height(waiting_time) {
+            Some(_) => None,
+            None => Some(self.start_ts + self.waiting_time_ms.unwrap_or(waiting_time.ms)),
+        }
+    }
+
+    /// Returns None if the ticket is waiting for a period of time
+    pub fn unlock_btc_height(&self, waiting_time: &WithdrawalWaitingTime) -> Option<u64> {
+        // tickets queued before switching to BTC blocks still wait in ms
+        Some(self.start_btc_height? + waiting_time.btc_blocks?)
+    }
+
+    pub fn is_matured(&self, waiting_time: &WithdrawalWaitingTime) -> bool {
+        match self.unlock_btc_height(waiting_time) {
+            Some(unlock_height) => waiting_time.btc_tip_height >= unlock_height,
+            None => current_timestamp_ms() >= self.unlock_ts(waiting_time).unwrap(),
+        }
+    }
+}
+
+/// Restrictions on multisig withdrawals set by the user
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawalPolicy {
+    /// max amount in satoshi that could be withdrawn within a day, empty for no limit
+    pub daily_limit: Option<u64>,
+    /// hex encoded script pubkeys that withdrawals could be sent to, empty to allow any
+    pub allowed_destinations: Option<Vec<String>>,
+    /// how long a looser policy needs to wait before taking effect in ms
+    pub loosen_delay_ms: u64,
+}
+
+impl WithdrawalPolicy {
+    /// Whether the new policy is looser than this one in any aspect
+    pub fn is_loosened_by(&self, new: &WithdrawalPolicy) -> bool {
+        let limit_loosened =
+            new.daily_limit.unwrap_or(u64::MAX) > self.daily_limit.unwrap_or(u64::MAX);
+        let destinations_loosened = match (&self.allowed_destinations, &new.allowed_destinations) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(old), Some(new)) => new.iter().any(|d| !old.contains(d)),
+        };
+        limit_loosened || destinations_loosened || new.loosen_delay_ms < self.loosen_delay_ms
+    }
+
+    pub fn is_destination_allowed(&self, script_pubkey_hex: &String) -> bool {
+        match &self.allowed_destinations {
+            Some(destinations) => destinations.contains(script_pubkey_hex),
+            None => true,
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingWithdrawalPolicy {
+    pub policy: WithdrawalPolicy,
+    /// timestamp when the policy takes effect in ms
+    pub effective_ts: Timestamp,
+}
+
+impl PendingWithdrawalPolicy {
+    fn is_effective(&self) -> bool {
+        current_timestamp_ms() >= self.effective_ts
+    }
+}
+
+/// Account freeze made for compliance reasons
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountFreeze {
+    /// reason code defined off-chain
+    pub reason_code: u16,
+    /// timestamp when the account is frozen in ms
+    pub frozen_ts: Timestamp,
+}
+
+/// A request made by the guardian to recover a deposit
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RecoveryRequest {
+    /// ID of the deposit to recover
+    pub deposit_id: OutputId,
+    /// timestamp when the recovery is requested in ms
+    pub start_ts: Timestamp,
+}
+
+/// How long withdrawal tickets need to wait before they can be signed
+pub struct WithdrawalWaitingTime {
+    /// base waiting time in ms
+    pub ms: u64,
+    /// longer waiting time in ms for larger withdrawal amounts, sorted by amount
+    pub tiers: Vec<WithdrawalWaitingTier>,
+    /// if set, number of BTC blocks to wait instead
+    pub btc_blocks: Option<u64>,
+    /// BTC tip height to check against
+    pub btc_tip_height: u64,
+}
+
+impl WithdrawalWaitingTime {
+    /// Waiting time in ms for a ticket queued when the account is withdrawing the given amount in total
+    pub fn tier_ms(&self, total_amount: u64) -> u64 {
+        // tiers are sorted by amount, so the last matched one is the highest
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| total_amount >= tier.min_amount)
+            .map(|tier| tier.waiting_time_ms)
+            .unwrap_or(self.ms)
+    }
+}
+
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize)]
 #[serde(crate = "near_sdk::serde")]
 pub enum DepositStatus {
     Active,
     Withdrawn,
 }
 
-#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Deposit {
     /// user pubkey
@@ -243,6 +920,8 @@ pub struct Deposit {
     pub value: u64,
     /// encoded sequence number of the deposit
     pub sequence: u32,
+    /// stake weight multiplier in basis points, boosted for fixed-term deposits
+    pub stake_weight_bps: u32,
     /// complete withdrawal time in ms
     pub complete_withdrawal_ts: Timestamp,
     /// withdrawal txn ID
@@ -266,6 +945,7 @@ impl Deposit {
             deposit_vout: vout,
             value,
             sequence,
+            stake_weight_bps: BASE_STAKE_WEIGHT_BPS,
             complete_withdrawal_ts: 0,
             withdrawal_tx_id: None,
         }
@@ -275,6 +955,19 @@ impl Deposit {
         output_id(&self.deposit_tx_id, self.deposit_vout)
     }
 
+    /// BTC block height before which a fixed-term deposit cannot be withdrawn
+    pub fn maturity_height(&self) -> Option<u32> {
+        match self.redeem_version {
+            RedeemVersion::V3 { maturity_height } => Some(maturity_height),
+            _ => None,
+        }
+    }
+
+    /// deposit value multiplied by its stake weight
+    pub fn weighted_value(&self) -> u64 {
+        (self.value as u128 * self.stake_weight_bps as u128 / BASE_STAKE_WEIGHT_BPS as u128) as u64
+    }
+
     pub fn complete_withdrawal(&mut self, withdrawal_tx_id: TxId) {
         self.complete_withdrawal_ts = current_timestamp_ms();
         self.withdrawal_tx_id = Some(withdrawal_tx_id);
@@ -282,14 +975,85 @@ impl Deposit {
     }
 }
 
+// conversions to the JSON types shared with off-chain consumers
+
+impl From<WithdrawalTicket> for bithive_types::account::WithdrawalTicket {
+    fn from(value: WithdrawalTicket) -> Self {
+        Self {
+            nonce: value.nonce,
+            amount: value.amount,
+            start_ts: value.start_ts,
+            waiting_time_ms: value.waiting_time_ms,
+            start_btc_height: value.start_btc_height,
+        }
+    }
+}
+
+impl From<WithdrawalPolicy> for bithive_types::account::WithdrawalPolicy {
+    fn from(value: WithdrawalPolicy) -> Self {
+        Self {
+            daily_limit: value.daily_limit,
+            allowed_destinations: value.allowed_destinations,
+            loosen_delay_ms: value.loosen_delay_ms,
+        }
+    }
+}
+
+impl From<PendingWithdrawalPolicy> for bithive_types::account::PendingWithdrawalPolicy {
+    fn from(value: PendingWithdrawalPolicy) -> Self {
+        Self {
+            policy: value.policy.into(),
+            effective_ts: value.effective_ts,
+        }
+    }
+}
+
+impl From<AccountFreeze> for bithive_types::account::AccountFreeze {
+    fn from(value: AccountFreeze) -> Self {
+        Self {
+            reason_code: value.reason_code,
+            frozen_ts: value.frozen_ts,
+        }
+    }
+}
+
+impl From<RecoveryRequest> for bithive_types::account::RecoveryRequest {
+    fn from(value: RecoveryRequest) -> Self {
+        Self {
+            deposit_id: value.deposit_id.into(),
+            start_ts: value.start_ts,
+        }
+    }
+}
+
+/// Legacy variants are upgraded on read and rewritten in the current layout on next write
 #[derive(BorshDeserialize, BorshSerialize)]
 pub enum VersionedDeposit {
+    V1(DepositV1),
     Current(Deposit),
 }
 
+impl VersionedDeposit {
+    pub fn is_current(&self) -> bool {
+        matches!(self, VersionedDeposit::Current(_))
+    }
+}
+
 impl From<VersionedDeposit> for Deposit {
     fn from(value: VersionedDeposit) -> Self {
         match value {
+            VersionedDeposit::V1(d) => Deposit {
+                user_pubkey: d.user_pubkey,
+                status: d.status,
+                redeem_version: d.redeem_version,
+                deposit_tx_id: d.deposit_tx_id,
+                deposit_vout: d.deposit_vout,
+                value: d.value,
+                sequence: d.sequence,
+                stake_weight_bps: BASE_STAKE_WEIGHT_BPS,
+                complete_withdrawal_ts: d.complete_withdrawal_ts,
+                withdrawal_tx_id: d.withdrawal_tx_id,
+            },
             VersionedDeposit::Current(d) => d,
         }
     }
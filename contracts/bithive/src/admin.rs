@@ -1,14 +1,28 @@
+use bitcoin::PublicKey;
+use config::{ConfigFieldChange, ConfigPatch};
 use events::Event;
-use near_sdk::assert_one_yocto;
+use near_sdk::{assert_one_yocto, serde_json::json};
+use std::str::FromStr;
+use types::{DepositLimits, DepositMode, DepositTerm, Role, SequenceHeightDepositLimits};
+use utils::current_timestamp_ms;
 
 use crate::*;
 
 #[near_bindgen]
 impl Contract {
+    /// Only the owner could propose a new owner, this is never granted by a role
+    /// since the owner has all roles
     #[payable]
     pub fn propose_change_owner(&mut self, new_owner_id: AccountId) {
         self.assert_owner();
-        self.pending_owner_id = Some(new_owner_id);
+        require!(new_owner_id != self.owner_id, "Already owner");
+        self.pending_owner_id = Some(new_owner_id.clone());
+
+        Event::ProposeChangeOwner {
+            owner: self.owner_id.to_string(),
+            pending_owner: new_owner_id.to_string(),
+        }
+        .emit();
     }
 
     #[payable]
@@ -25,71 +39,231 @@ impl Contract {
         self.pending_owner_id = None;
 
         Event::OwnerChanged {
-            old_owner: &current_owner_id.to_string(),
-            new_owner: &pending_owner_id.to_string(),
+            old_owner: current_owner_id.to_string(),
+            new_owner: pending_owner_id.to_string(),
         }
         .emit();
     }
 
     #[payable]
-    pub fn set_btc_light_client_id(&mut self, new_contract_id: AccountId) {
-        self.assert_owner();
-        self.btc_light_client_id = new_contract_id;
+    pub fn set_pending_sign_psbt_expiry(&mut self, ms: u64) {
+        self.assert_role(Role::ConfigManager);
+        self.apply_config_patch(ConfigPatch {
+            pending_sign_psbt_expiry_ms: Some(ms),
+            ..Default::default()
+        });
     }
 
+    /// set to None to disable the withdrawal rate limit
+    /// amounts cosigned within the current window are kept when the limit is changed
     #[payable]
-    pub fn set_bip322_verifier_id(&mut self, new_contract_id: Option<AccountId>) {
-        self.assert_owner();
-        self.bip322_verifier_id = new_contract_id;
+    pub fn set_withdrawal_rate_limit(&mut self, max_amount: Option<u64>, window_ms: u64) {
+        self.assert_role(Role::ConfigManager);
+        self.withdrawal_rate_limit = max_amount.map(|max_amount| {
+            require!(window_ms > 0, "window_ms must be greater than 0");
+            let mut limit = self
+                .withdrawal_rate_limit
+                .take()
+                .unwrap_or_else(|| WithdrawalRateLimit::new(max_amount, window_ms));
+            limit.max_amount = max_amount;
+            limit.window_ms = window_ms;
+            limit
+        });
+
+        Event::SetWithdrawalRateLimit {
+            max_amount: max_amount.map(Into::into),
+            window_ms: window_ms.into(),
+        }
+        .emit();
     }
 
+    /// forget the amounts cosigned within the current window
     #[payable]
-    pub fn set_chain_signatures_id(&mut self, new_contract_id: AccountId) {
-        self.assert_owner();
-        self.chain_signatures_id = new_contract_id;
+    pub fn reset_withdrawal_rate_limit(&mut self) {
+        self.assert_role(Role::ConfigManager);
+        let limit = self
+            .withdrawal_rate_limit
+            .as_mut()
+            .expect("Withdrawal rate limit not set");
+        let used_amount = limit.view(current_timestamp_ms()).used_amount;
+        limit.reset();
+
+        Event::ResetWithdrawalRateLimit {
+            used_amount: used_amount.into(),
+        }
+        .emit();
     }
 
     #[payable]
-    pub fn set_n_confirmation(&mut self, n: u64) {
-        self.assert_owner();
-        require!(n > 0, "n_confirmation must be greater than 0");
-        self.n_confirmation = n;
+    pub fn set_min_deposit_satoshi(&mut self, min_deposit_satoshi: u64) {
+        self.assert_role(Role::ConfigManager);
+        self.apply_config_patch(ConfigPatch {
+            min_deposit_satoshi: Some(min_deposit_satoshi),
+            ..Default::default()
+        });
     }
 
+    /// set to None to remove the cap of a single deposit
     #[payable]
-    pub fn set_withdrawal_waiting_time(&mut self, ms: u64) {
-        self.assert_owner();
-        require!(ms > 0, "withdrawal_waiting_time_ms must be greater than 0");
-        self.withdrawal_waiting_time_ms = ms;
+    pub fn set_max_deposit_satoshi(&mut self, max_deposit_satoshi: Option<u64>) {
+        self.assert_role(Role::ConfigManager);
+        self.apply_config_patch(ConfigPatch {
+            max_deposit_satoshi: Some(max_deposit_satoshi),
+            ..Default::default()
+        });
     }
 
+    /// set to None to remove the cap of the total deposit of a user
     #[payable]
-    pub fn set_min_deposit_satoshi(&mut self, min_deposit_satoshi: u64) {
-        self.assert_owner();
-        self.min_deposit_satoshi = min_deposit_satoshi;
+    pub fn set_max_user_deposit_satoshi(&mut self, max_user_deposit_satoshi: Option<u64>) {
+        self.assert_role(Role::ConfigManager);
+        self.apply_config_patch(ConfigPatch {
+            max_user_deposit_satoshi: Some(max_user_deposit_satoshi),
+            ..Default::default()
+        });
+    }
+
+    /// set to None to remove the cap of the total deposit of all users
+    #[payable]
+    pub fn set_max_total_deposit_satoshi(&mut self, max_total_deposit_satoshi: Option<u64>) {
+        self.assert_role(Role::ConfigManager);
+        self.apply_config_patch(ConfigPatch {
+            max_total_deposit_satoshi: Some(max_total_deposit_satoshi),
+            ..Default::default()
+        });
+    }
+
+    /// set limits to None to use the default ones for the sequence height
+    #[payable]
+    pub fn set_sequence_height_deposit_limits(
+        &mut self,
+        sequence_height: u16,
+        limits: Option<DepositLimits>,
+    ) {
+        self.assert_role(Role::ConfigManager);
+        let mut seq_height_deposit_limits = self.seq_height_deposit_limits.clone();
+        seq_height_deposit_limits.retain(|l| l.sequence_height != sequence_height);
+        if let Some(limits) = limits {
+            seq_height_deposit_limits.push(SequenceHeightDepositLimits {
+                sequence_height,
+                limits,
+            });
+        }
+        self.apply_config_patch(ConfigPatch {
+            sequence_height_deposit_limits: Some(seq_height_deposit_limits),
+            ..Default::default()
+        });
     }
 
     #[payable]
     pub fn set_earliest_deposit_block_height(&mut self, height: u32) {
-        self.assert_owner();
-        self.earliest_deposit_block_height = height;
+        self.assert_role(Role::ConfigManager);
+        self.apply_config_patch(ConfigPatch {
+            earliest_deposit_block_height: Some(height),
+            ..Default::default()
+        });
     }
 
     #[payable]
     pub fn set_solo_withdrawal_sequence_heights(&mut self, values: Vec<u16>) {
-        self.assert_owner();
-        require!(!values.is_empty(), "values must be non-empty");
-        self.solo_withdrawal_seq_heights = values;
+        self.assert_role(Role::ConfigManager);
+        self.apply_config_patch(ConfigPatch {
+            solo_withdrawal_sequence_heights: Some(values),
+            ..Default::default()
+        });
+    }
+
+    /// set to empty to stop accepting deposits with guardian recovery
+    #[payable]
+    pub fn set_guardian_recovery_sequence_heights(&mut self, values: Vec<u16>) {
+        self.assert_role(Role::ConfigManager);
+        self.apply_config_patch(ConfigPatch {
+            guardian_recovery_sequence_heights: Some(values),
+            ..Default::default()
+        });
+    }
+
+    /// set to empty to stop accepting fixed-term deposits.
+    /// Changing the terms does not affect the stake weight of existing deposits
+    #[payable]
+    pub fn set_deposit_terms(&mut self, terms: Vec<DepositTerm>) {
+        self.assert_role(Role::ConfigManager);
+        self.apply_config_patch(ConfigPatch {
+            deposit_terms: Some(terms),
+            ..Default::default()
+        });
+    }
+
+    /// stop emitting v1 events that have v2 counterparts, once indexers have switched to v2
+    #[payable]
+    pub fn set_emit_legacy_events(&mut self, enabled: bool) {
+        self.assert_role(Role::ConfigManager);
+        require!(self.emit_legacy_events != enabled, "Invalid operation");
+        let old_value = self.emit_legacy_events;
+        self.emit_legacy_events = enabled;
+
+        Event::ConfigChanged {
+            changes: vec![ConfigFieldChange {
+                field: "emit_legacy_events".to_string(),
+                old_value: json!(old_value),
+                new_value: json!(enabled),
+            }],
+        }
+        .emit();
     }
 
     #[payable]
     pub fn set_paused(&mut self, paused: bool) {
-        self.assert_owner();
+        self.assert_role(Role::Pauser);
         require!(self.paused != paused, "Invalid operation");
         self.paused = paused;
 
         Event::Paused { paused }.emit();
     }
+
+    #[payable]
+    pub fn set_deposit_mode(&mut self, mode: DepositMode) {
+        self.assert_role(Role::ConfigManager);
+        require!(self.deposit_mode != mode, "Invalid operation");
+        self.deposit_mode = mode;
+
+        Event::SetDepositMode { mode: mode.into() }.emit();
+    }
+
+    /// pubkeys already in the allowlist are skipped
+    #[payable]
+    pub fn add_to_deposit_allowlist(&mut self, user_pubkeys: Vec<String>) {
+        self.assert_role(Role::ConfigManager);
+        let mut added = vec![];
+        for user_pubkey in user_pubkeys {
+            PublicKey::from_str(&user_pubkey).expect("Invalid pubkey hex");
+            if self.deposit_allowlist.insert(&user_pubkey.clone().into()) {
+                added.push(user_pubkey.to_lowercase());
+            }
+        }
+
+        Event::AddToDepositAllowlist {
+            user_pubkeys: added.clone(),
+        }
+        .emit();
+    }
+
+    /// pubkeys not in the allowlist are skipped
+    #[payable]
+    pub fn remove_from_deposit_allowlist(&mut self, user_pubkeys: Vec<String>) {
+        self.assert_role(Role::ConfigManager);
+        let mut removed = vec![];
+        for user_pubkey in user_pubkeys {
+            if self.deposit_allowlist.remove(&user_pubkey.clone().into()) {
+                removed.push(user_pubkey.to_lowercase());
+            }
+        }
+
+        Event::RemoveFromDepositAllowlist {
+            user_pubkeys: removed.clone(),
+        }
+        .emit();
+    }
 }
 
 impl Contract {
@@ -98,3 +272,52 @@ impl Contract {
         require!(env::predecessor_account_id() == self.owner_id, "Not owner");
     }
 }
+
+/// A larger withdrawal must never wait shorter than a smaller one
+pub(crate) fn assert_monotonic_waiting_tiers(
+    base_waiting_time_ms: u64,
+    tiers: &[WithdrawalWaitingTier],
+) {
+    let mut prev: Option<&WithdrawalWaitingTier> = None;
+    for tier in tiers {
+        require!(
+            tier.min_amount > prev.map(|p| p.min_amount).unwrap_or(0),
+            "tier amounts must be strictly increasing"
+        );
+        require!(
+            tier.waiting_time_ms
+                >= prev
+                    .map(|p| p.waiting_time_ms)
+                    .unwrap_or(base_waiting_time_ms),
+            "tier waiting times must be non-decreasing"
+        );
+        prev = Some(tier);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_contract_instance;
+    use near_sdk::{
+        test_utils::{get_logs, VMContextBuilder},
+        testing_env,
+    };
+
+    #[test]
+    fn test_set_emit_legacy_events_emits_config_changed() {
+        let mut contract = test_contract_instance();
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(AccountId::new_unchecked("owner".to_string()))
+            .attached_deposit(1)
+            .build());
+
+        contract.set_emit_legacy_events(false);
+        assert!(!contract.emit_legacy_events);
+        let logs = get_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains("config_changed"));
+        assert!(logs[0]
+            .contains(r#"{"field":"emit_legacy_events","old_value":true,"new_value":false}"#));
+    }
+}
@@ -0,0 +1,176 @@
+use std::str::FromStr;
+
+use crate::*;
+use account::{AccountFreeze, Deposit};
+use bitcoin::{PublicKey, Transaction};
+use deposit::ERR_BAD_PUBKEY_HEX;
+use events::Event;
+use types::{output_id, parse_output_id, Role};
+use utils::current_timestamp_ms;
+
+const ERR_BAD_OUTPOINT: &str = "Invalid outpoint";
+const ERR_PUBKEY_DENIED: &str = "User pubkey is denied";
+const ERR_OUTPOINT_DENIED: &str = "Outpoint is denied";
+const ERR_ALREADY_FROZEN: &str = "Account already frozen";
+const ERR_NOT_FROZEN: &str = "Account not frozen";
+
+#[near_bindgen]
+impl Contract {
+    /// Add pubkeys and outpoints in the form of `{tx_id}:{vout}` to the denylist,
+    /// those already in the denylist are skipped
+    #[payable]
+    pub fn add_to_denylist(&mut self, user_pubkeys: Vec<String>, outpoints: Vec<String>) {
+        self.assert_role(Role::Compliance);
+        let mut added_pubkeys = vec![];
+        for user_pubkey in user_pubkeys {
+            PublicKey::from_str(&user_pubkey).expect(ERR_BAD_PUBKEY_HEX);
+            if self.denied_pubkeys.insert(&user_pubkey.clone().into()) {
+                added_pubkeys.push(user_pubkey.to_lowercase());
+            }
+        }
+        let mut added_outpoints = vec![];
+        for outpoint in outpoints {
+            let (tx_id, vout) = parse_output_id(&outpoint).expect(ERR_BAD_OUTPOINT);
+            if self.denied_outpoints.insert(&output_id(&tx_id, vout)) {
+                added_outpoints.push(outpoint.to_lowercase());
+            }
+        }
+
+        Event::AddToDenylist {
+            user_pubkeys: added_pubkeys.clone(),
+            outpoints: added_outpoints.clone(),
+        }
+        .emit();
+    }
+
+    /// Remove pubkeys and outpoints from the denylist, those not in the denylist are skipped
+    #[payable]
+    pub fn remove_from_denylist(&mut self, user_pubkeys: Vec<String>, outpoints: Vec<String>) {
+        self.assert_role(Role::Compliance);
+        let mut removed_pubkeys = vec![];
+        for user_pubkey in user_pubkeys {
+            if self.denied_pubkeys.remove(&user_pubkey.clone().into()) {
+                removed_pubkeys.push(user_pubkey.to_lowercase());
+            }
+        }
+        let mut removed_outpoints = vec![];
+        for outpoint in outpoints {
+            let (tx_id, vout) = parse_output_id(&outpoint).expect(ERR_BAD_OUTPOINT);
+            if self.denied_outpoints.remove(&output_id(&tx_id, vout)) {
+                removed_outpoints.push(outpoint.to_lowercase());
+            }
+        }
+
+        Event::RemoveFromDenylist {
+            user_pubkeys: removed_pubkeys.clone(),
+            outpoints: removed_outpoints.clone(),
+        }
+        .emit();
+    }
+
+    /// Freeze an account so that it cannot queue withdrawals or get multisig signatures.
+    /// Solo withdrawals of a frozen account are still recorded
+    /// ### Arguments
+    /// * `user_pubkey` - user pubkey
+    /// * `reason_code` - reason code defined off-chain
+    #[payable]
+    pub fn freeze_account(&mut self, user_pubkey: String, reason_code: u16) {
+        self.assert_role(Role::Compliance);
+        let mut account = self.get_account(&user_pubkey.clone().into());
+        require!(account.freeze().is_none(), ERR_ALREADY_FROZEN);
+        account.set_freeze(Some(AccountFreeze {
+            reason_code,
+            frozen_ts: current_timestamp_ms(),
+        }));
+        self.set_account(account);
+
+        Event::FreezeAccount {
+            user_pubkey: user_pubkey.clone(),
+            reason_code,
+        }
+        .emit();
+    }
+
+    #[payable]
+    pub fn unfreeze_account(&mut self, user_pubkey: String) {
+        self.assert_role(Role::Compliance);
+        let mut account = self.get_account(&user_pubkey.clone().into());
+        require!(account.freeze().is_some(), ERR_NOT_FROZEN);
+        account.set_freeze(None);
+        self.set_account(account);
+
+        Event::UnfreezeAccount {
+            user_pubkey: user_pubkey.clone(),
+        }
+        .emit();
+    }
+
+    pub fn is_pubkey_denied(&self, user_pubkey: String) -> bool {
+        self.denied_pubkeys.contains(&user_pubkey.into())
+    }
+
+    pub fn is_outpoint_denied(&self, outpoint: String) -> bool {
+        let (tx_id, vout) = parse_output_id(&outpoint).expect(ERR_BAD_OUTPOINT);
+        self.denied_outpoints.contains(&output_id(&tx_id, vout))
+    }
+}
+
+impl Contract {
+    /// Reject deposits made by denied pubkeys, or from and to denied outpoints
+    pub(crate) fn verify_deposit_not_denied(
+        &self,
+        tx: &Transaction,
+        deposit: &Deposit,
+    ) -> Result<(), &'static str> {
+        if self.denied_pubkeys.contains(&deposit.user_pubkey) {
+            return Err(ERR_PUBKEY_DENIED);
+        }
+        if self.denied_outpoints.contains(&deposit.id()) {
+            return Err(ERR_OUTPOINT_DENIED);
+        }
+        let input_denied = tx.input.iter().any(|input| {
+            self.denied_outpoints.contains(&output_id(
+                &input.previous_output.txid.to_string().into(),
+                input.previous_output.vout.into(),
+            ))
+        });
+        if input_denied {
+            return Err(ERR_OUTPOINT_DENIED);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_contract_instance;
+    use near_sdk::{test_utils::VMContextBuilder, testing_env};
+
+    fn set_caller(account_id: &str) {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(AccountId::new_unchecked(account_id.to_string()))
+            .attached_deposit(1)
+            .build());
+    }
+
+    #[test]
+    fn test_denylist_outpoint_normalized() {
+        let mut contract = test_contract_instance();
+        set_caller("owner");
+        contract.grant_role(
+            AccountId::new_unchecked("compliance".to_string()),
+            Role::Compliance,
+        );
+        set_caller("compliance");
+
+        let outpoint = "AB:01".to_string();
+        contract.add_to_denylist(vec![], vec![outpoint.clone()]);
+        assert!(contract.is_outpoint_denied(outpoint.clone()));
+        assert!(contract.is_outpoint_denied("ab:1".to_string()));
+
+        contract.remove_from_denylist(vec![], vec![outpoint.clone()]);
+        assert!(!contract.is_outpoint_denied(outpoint));
+        assert!(!contract.is_outpoint_denied("ab:1".to_string()));
+    }
+}
@@ -0,0 +1,308 @@
+use crate::*;
+use account::{BASE_STAKE_WEIGHT_BPS, MAX_STAKE_WEIGHT_BPS};
+use admin::assert_monotonic_waiting_tiers;
+use bitcoin::absolute::LOCK_TIME_THRESHOLD;
+use events::Event;
+use near_sdk::{
+    serde::{Deserialize, Deserializer, Serialize},
+    serde_json::json,
+};
+use types::{DepositLimits, DepositTerm, Role, SequenceHeightDepositLimits};
+
+pub use bithive_types::config::ConfigFieldChange;
+
+/// Config fields to update, those not set are kept unchanged.
+/// Optional fields are unset with `null`.
+/// Fields that affect withdrawal waiting times are changed via `ConfigChange` instead
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "near_sdk::serde", deny_unknown_fields)]
+pub struct ConfigPatch {
+    pub pending_sign_psbt_expiry_ms: Option<u64>,
+    pub min_deposit_satoshi: Option<u64>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_deposit_satoshi: Option<Option<u64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_user_deposit_satoshi: Option<Option<u64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_total_deposit_satoshi: Option<Option<u64>>,
+    pub sequence_height_deposit_limits: Option<Vec<SequenceHeightDepositLimits>>,
+    pub earliest_deposit_block_height: Option<u32>,
+    pub solo_withdrawal_sequence_heights: Option<Vec<u16>>,
+    pub guardian_recovery_sequence_heights: Option<Vec<u16>>,
+    pub deposit_terms: Option<Vec<DepositTerm>>,
+}
+
+/// distinguish a `null` value from a missing field
+fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Set the contract field from the patch if present, recording its old and new values
+macro_rules! apply_field {
+    ($self:ident, $changes:ident, $field:ident, $value:expr, $name:literal) => {
+        if let Some(value) = $value {
+            $changes.push(ConfigFieldChange {
+                field: $name.to_string(),
+                old_value: json!($self.$field),
+                new_value: json!(value),
+            });
+            $self.$field = value;
+        }
+    };
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Update several config fields atomically.
+    /// The whole config is validated after the update, so related fields could be changed together
+    #[payable]
+    pub fn update_config(&mut self, patch: ConfigPatch) {
+        self.assert_role(Role::ConfigManager);
+        self.apply_config_patch(patch);
+    }
+}
+
+impl Contract {
+    pub(crate) fn apply_config_patch(&mut self, patch: ConfigPatch) {
+        validate_config_patch(&patch);
+
+        let mut changes = vec![];
+        apply_field!(
+            self,
+            changes,
+            pending_sign_psbt_expiry_ms,
+            patch.pending_sign_psbt_expiry_ms,
+            "pending_sign_psbt_expiry_ms"
+        );
+        apply_field!(
+            self,
+            changes,
+            min_deposit_satoshi,
+            patch.min_deposit_satoshi,
+            "min_deposit_satoshi"
+        );
+        apply_field!(
+            self,
+            changes,
+            max_deposit_satoshi,
+            patch.max_deposit_satoshi,
+            "max_deposit_satoshi"
+        );
+        apply_field!(
+            self,
+            changes,
+            max_user_deposit_satoshi,
+            patch.max_user_deposit_satoshi,
+            "max_user_deposit_satoshi"
+        );
+        apply_field!(
+            self,
+            changes,
+            max_total_deposit_satoshi,
+            patch.max_total_deposit_satoshi,
+            "max_total_deposit_satoshi"
+        );
+        apply_field!(
+            self,
+            changes,
+            seq_height_deposit_limits,
+            patch.sequence_height_deposit_limits,
+            "sequence_height_deposit_limits"
+        );
+        apply_field!(
+            self,
+            changes,
+            earliest_deposit_block_height,
+            patch.earliest_deposit_block_height,
+            "earliest_deposit_block_height"
+        );
+        apply_field!(
+            self,
+            changes,
+            solo_withdrawal_seq_heights,
+            patch.solo_withdrawal_sequence_heights,
+            "solo_withdrawal_sequence_heights"
+        );
+        apply_field!(
+            self,
+            changes,
+            guardian_recovery_seq_heights,
+            patch.guardian_recovery_sequence_heights,
+            "guardian_recovery_sequence_heights"
+        );
+        apply_field!(
+            self,
+            changes,
+            deposit_terms,
+            patch.deposit_terms,
+            "deposit_terms"
+        );
+
+        self.validate_config();
+
+        Event::ConfigChanged { changes }.emit();
+    }
+
+    /// Check invariants across config fields, which must hold after any config update
+    pub(crate) fn validate_config(&self) {
+        assert_monotonic_waiting_tiers(
+            self.withdrawal_waiting_time_ms,
+            &self.withdrawal_waiting_tiers,
+        );
+
+        validate_deposit_limits(&DepositLimits {
+            min_deposit_satoshi: self.min_deposit_satoshi,
+            max_deposit_satoshi: self.max_deposit_satoshi,
+            max_user_deposit_satoshi: self.max_user_deposit_satoshi,
+        });
+        for (i, l) in self.seq_height_deposit_limits.iter().enumerate() {
+            validate_deposit_limits(&l.limits);
+            require!(
+                self.seq_height_deposit_limits[..i]
+                    .iter()
+                    .all(|p| p.sequence_height != l.sequence_height),
+                "duplicate sequence height"
+            );
+            // limits of a sequence height that is not accepted would never apply
+            require!(
+                self.solo_withdrawal_seq_heights
+                    .contains(&l.sequence_height),
+                "sequence height of deposit limits is not a solo withdrawal sequence height"
+            );
+        }
+
+        // fixed-term deposits could only mature after deposits are accepted
+        require!(
+            self.deposit_terms
+                .iter()
+                .all(|t| t.maturity_height > self.earliest_deposit_block_height),
+            "maturity height must be greater than the earliest deposit block height"
+        );
+    }
+}
+
+/// Check the new value of each field to update on its own
+fn validate_config_patch(patch: &ConfigPatch) {
+    require!(
+        patch.pending_sign_psbt_expiry_ms != Some(0),
+        "pending_sign_psbt_expiry_ms must be greater than 0"
+    );
+    // heights are used as absolute locktime, which would otherwise be interpreted as timestamps
+    if let Some(height) = patch.earliest_deposit_block_height {
+        require!(
+            height < LOCK_TIME_THRESHOLD,
+            "earliest_deposit_block_height must be a block height"
+        );
+    }
+    if let Some(values) = patch.solo_withdrawal_sequence_heights.as_ref() {
+        require!(!values.is_empty(), "values must be non-empty");
+    }
+    // a relative locktime of 0 does not lock the deposit at all
+    require!(
+        patch
+            .solo_withdrawal_sequence_heights
+            .iter()
+            .chain(patch.guardian_recovery_sequence_heights.iter())
+            .flatten()
+            .all(|h| *h > 0),
+        "sequence height must be greater than 0"
+    );
+    if let Some(terms) = patch.deposit_terms.as_ref() {
+        for (i, term) in terms.iter().enumerate() {
+            require!(
+                term.stake_weight_bps >= BASE_STAKE_WEIGHT_BPS,
+                "stake weight must not be less than the base weight"
+            );
+            require!(
+                term.stake_weight_bps <= MAX_STAKE_WEIGHT_BPS,
+                "stake weight must not be more than the max weight"
+            );
+            require!(
+                terms[..i]
+                    .iter()
+                    .all(|t| t.maturity_height != term.maturity_height),
+                "duplicate maturity height"
+            );
+            require!(
+                term.maturity_height < LOCK_TIME_THRESHOLD,
+                "maturity height must be a block height"
+            );
+        }
+    }
+}
+
+fn validate_deposit_limits(limits: &DepositLimits) {
+    if let Some(max) = limits.max_deposit_satoshi {
+        require!(
+            max >= limits.min_deposit_satoshi,
+            "max deposit must not be less than min deposit"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_contract_instance;
+
+    #[test]
+    fn test_apply_config_patch() {
+        let mut contract = test_contract_instance();
+        contract.max_deposit_satoshi = Some(1000);
+        let patch: ConfigPatch = near_sdk::serde_json::from_str(
+            r#"{"min_deposit_satoshi": 2000, "max_deposit_satoshi": null}"#,
+        )
+        .unwrap();
+        assert_eq!(patch.max_deposit_satoshi, Some(None));
+        assert_eq!(patch.max_user_deposit_satoshi, None);
+
+        contract.apply_config_patch(patch);
+        assert_eq!(contract.min_deposit_satoshi, 2000);
+        assert_eq!(contract.max_deposit_satoshi, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "max deposit must not be less than min deposit")]
+    fn test_invalid_config_patch() {
+        let mut contract = test_contract_instance();
+        contract.apply_config_patch(ConfigPatch {
+            min_deposit_satoshi: Some(2000),
+            max_deposit_satoshi: Some(Some(1000)),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "sequence height of deposit limits is not a solo withdrawal sequence height"
+    )]
+    fn test_deposit_limits_of_unknown_sequence_height() {
+        let mut contract = test_contract_instance();
+        let height = contract.solo_withdrawal_seq_heights.iter().max().unwrap() + 1;
+        contract.apply_config_patch(ConfigPatch {
+            sequence_height_deposit_limits: Some(vec![SequenceHeightDepositLimits {
+                sequence_height: height,
+                limits: DepositLimits {
+                    min_deposit_satoshi: 0,
+                    max_deposit_satoshi: None,
+                    max_user_deposit_satoshi: None,
+                },
+            }]),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "sequence height must be greater than 0")]
+    fn test_zero_sequence_height() {
+        let mut contract = test_contract_instance();
+        contract.apply_config_patch(ConfigPatch {
+            guardian_recovery_sequence_heights: Some(vec![100, 0]),
+            ..Default::default()
+        });
+    }
+}
@@ -3,3 +3,9 @@
 
 pub const CHAIN_SIGNATURES_PATH_V1: &str = "/bithive/v1";
 pub const CHAIN_SIGNATURES_KEY_VERSION_V1: u32 = 0;
+
+pub const CHAIN_SIGNATURES_PATH_V2: &str = "/bithive/v2";
+pub const CHAIN_SIGNATURES_KEY_VERSION_V2: u32 = 0;
+
+pub const CHAIN_SIGNATURES_PATH_V3: &str = "/bithive/v3";
+pub const CHAIN_SIGNATURES_KEY_VERSION_V3: u32 = 0;
@@ -1,30 +1,43 @@
 use std::str::FromStr;
 
-use account::Deposit;
+use account::{Deposit, BASE_STAKE_WEIGHT_BPS};
 use bitcoin::{
     absolute::LockTime,
     consensus::encode::deserialize_hex,
     opcodes::all::{
-        OP_CHECKMULTISIG, OP_CHECKSIG, OP_CSV, OP_DROP, OP_ELSE, OP_ENDIF, OP_IF, OP_PUSHNUM_2,
+        OP_CHECKMULTISIG, OP_CHECKSIG, OP_CLTV, OP_CSV, OP_DROP, OP_ELSE, OP_ENDIF, OP_IF,
+        OP_PUSHNUM_2,
     },
     script::Builder,
     PublicKey, ScriptBuf, Sequence, Transaction, TxOut,
 };
-use consts::CHAIN_SIGNATURES_PATH_V1;
+use consts::{CHAIN_SIGNATURES_PATH_V1, CHAIN_SIGNATURES_PATH_V2, CHAIN_SIGNATURES_PATH_V3};
+use events::{Event, EventV2};
 use ext::{ext_btc_light_client, ProofArgs, GAS_LIGHT_CLIENT_VERIFY};
 use near_sdk::{
     json_types::U128, near_bindgen, require, Balance, Gas, Promise, PromiseError, ONE_NEAR,
 };
-use types::{output_id, DepositEmbedMsg, RedeemVersion, SubmitDepositTxArgs, TxId};
+use types::{
+    output_id, DepositEmbedMsg, DepositLimits, DepositMode, PubKey, RedeemVersion,
+    SubmitDepositTxArgs, TxId,
+};
 use utils::{assert_gas, get_embed_message};
 
 use crate::*;
 
 const ERR_NOT_ENOUGH_STORAGE_DEPOSIT: &str = "Not enough NEAR attached.";
 const ERR_INVALID_TX_HEX: &str = "Invalid hex transaction";
-const ERR_BAD_PUBKEY_HEX: &str = "Invalid pubkey hex";
+pub(crate) const ERR_BAD_PUBKEY_HEX: &str = "Invalid pubkey hex";
+const ERR_BAD_GUARDIAN_PUBKEY: &str = "Invalid guardian pubkey";
 const ERR_BAD_DEPOSIT_AMOUNT: &str = "Deposit amount is less than minimum deposit amount";
+const ERR_DEPOSIT_TOO_LARGE: &str = "Deposit amount is more than maximum deposit amount";
+const ERR_USER_DEPOSIT_CAP_EXCEEDED: &str = "User deposit cap exceeded";
+const ERR_TOTAL_DEPOSIT_CAP_EXCEEDED: &str = "Total deposit cap exceeded";
+const ERR_DEPOSIT_CLOSED: &str = "Deposit is closed";
+const ERR_DEPOSIT_NOT_ALLOWED: &str = "User is not allowed to deposit";
 const ERR_NOT_ABS_TIMELOCK: &str = "Transaction absolute timelock not enabled";
+const ERR_BAD_MATURITY_HEIGHT: &str = "Invalid maturity height";
+const ERR_NO_SOLO_WITHDRAWAL_SEQ_HEIGHT: &str = "No solo withdrawal seq height configured";
 
 const ERR_BAD_DEPOSIT_IDX: &str = "Bad deposit output index";
 const ERR_BAD_EMBED_IDX: &str = "Bad embed output index";
@@ -33,6 +46,7 @@ const ERR_DEPOSIT_NOT_P2WSH: &str = "Deposit output is not P2WSH";
 const ERR_DEPOSIT_BAD_SCRIPT_HASH: &str = "Deposit output bad script hash";
 
 const ERR_DEPOSIT_ALREADY_SAVED: &str = "Deposit already saved";
+const ERR_TX_NOT_CONFIRMED: &str = "Transaction not confirmed";
 
 const GAS_DEPOSIT_VERIFY_CB: Gas = Gas(30 * Gas::ONE_TERA.0);
 
@@ -61,9 +75,18 @@ impl Contract {
 
         let tx = deserialize_hex::<Transaction>(&args.tx_hex).expect(ERR_INVALID_TX_HEX);
         let txid = tx.compute_txid();
-        let deposit_vout = match self.verify_embed_output(&tx, args.embed_vout) {
-            DepositEmbedMsg::V1 { deposit_vout, .. } => deposit_vout,
-        };
+        // reject the deposit before verifying confirmation, so that the storage deposit is refunded
+        let deposit = self.verify_deposit_txn(&tx, args.embed_vout);
+        if let Err(err) = self.verify_deposit_allowed(&deposit.user_pubkey) {
+            env::panic_str(err);
+        }
+        if let Err(err) = self.verify_deposit_not_denied(&tx, &deposit) {
+            env::panic_str(err);
+        }
+        if let Err(err) = self.verify_deposit_limits(&deposit) {
+            env::panic_str(err);
+        }
+        let deposit_vout = deposit.deposit_vout;
 
         // set deposit transaction(output) as confirmed now to prevent duplicate verification
         // NOTE that deposit vout should be used instead of embed vout!
@@ -74,7 +97,7 @@ impl Contract {
             .with_static_gas(GAS_LIGHT_CLIENT_VERIFY)
             .verify_transaction_inclusion(ProofArgs::new(
                 txid.to_string(),
-                args.tx_block_hash,
+                args.tx_block_hash.clone(),
                 args.tx_index,
                 args.merkle_proof,
                 self.n_confirmation,
@@ -84,21 +107,21 @@ impl Contract {
                     .with_static_gas(GAS_DEPOSIT_VERIFY_CB)
                     .on_verify_deposit_tx(
                         args.tx_hex,
-                        args.embed_vout,
-                        deposit_vout,
+                        deposit,
+                        args.tx_block_hash,
                         env::predecessor_account_id(),
                         env::attached_deposit().into(),
                     ),
             )
     }
 
-    #[allow(clippy::too_many_arguments)]
+    /// `deposit` has been verified against the txn and the deposit config when it is submitted
     #[private]
     pub fn on_verify_deposit_tx(
         &mut self,
         tx_hex: String,
-        embed_vout: u64,
-        deposit_vout: u64,
+        deposit: Deposit,
+        tx_block_hash: String,
         caller_id: AccountId,
         refund_amount: U128,
         #[callback_result] result: Result<bool, PromiseError>,
@@ -106,15 +129,35 @@ impl Contract {
         let valid = result.unwrap_or(false);
         let tx = deserialize_hex::<Transaction>(&tx_hex).expect(ERR_INVALID_TX_HEX);
         let txid = tx.compute_txid();
-        if !valid {
+        let deposit_vout = deposit.deposit_vout;
+
+        // deposits could have been made by others, the deposit mode or the denylist could have
+        // been changed, or a state migration could have started before the confirmation is
+        // verified. These are checked without panicking, since the deposit has been set as
+        // confirmed and the storage deposit needs to be refunded
+        let verified = if valid {
+            self.verify_not_migrating()
+                .and_then(|_| self.verify_deposit_allowed(&deposit.user_pubkey))
+                .and_then(|_| self.verify_deposit_not_denied(&tx, &deposit))
+                .and_then(|_| self.verify_deposit_limits(&deposit))
+        } else {
+            Err(ERR_TX_NOT_CONFIRMED)
+        };
+        if let Err(err) = verified {
             self.unset_deposit_confirmed(&txid.to_string().into(), deposit_vout);
             // refund storage deposit
             Promise::new(caller_id).transfer(refund_amount.into());
 
+            Event::DepositVerificationFailed {
+                tx_id: txid.to_string(),
+                deposit_vout: deposit_vout.into(),
+                reason: err.to_string(),
+            }
+            .emit();
             return false;
         }
 
-        self.save_deposit_txn(&tx, embed_vout);
+        self.save_deposit(deposit, &tx_block_hash, &caller_id);
 
         true
     }
@@ -131,6 +174,22 @@ impl Contract {
                 user_pubkey,
                 sequence_height,
             } => (deposit_vout, hex::encode(user_pubkey), sequence_height),
+            DepositEmbedMsg::V2 {
+                deposit_vout,
+                user_pubkey,
+                sequence_height,
+                ..
+            } => (
+                deposit_vout.into(),
+                hex::encode(user_pubkey),
+                sequence_height,
+            ),
+            DepositEmbedMsg::V3 {
+                deposit_vout,
+                user_pubkey,
+                sequence_height,
+                ..
+            } => (deposit_vout, hex::encode(user_pubkey), sequence_height),
         };
 
         require!(
@@ -152,36 +211,166 @@ impl Contract {
             .expect(ERR_BAD_DEPOSIT_IDX);
         let user_pubkey = PublicKey::from_str(&user_pubkey_hex).expect(ERR_BAD_PUBKEY_HEX);
         let sequence = Sequence::from_height(sequence_height);
-        let redeem_version = match embed_msg {
-            DepositEmbedMsg::V1 { .. } => {
-                self.verify_deposit_output_v1(deposit_output, &user_pubkey, sequence);
-                RedeemVersion::V1
+        let (redeem_version, stake_weight_bps) = match embed_msg {
+            DepositEmbedMsg::V1 { .. } => (RedeemVersion::V1, BASE_STAKE_WEIGHT_BPS),
+            DepositEmbedMsg::V2 {
+                guardian_pubkey,
+                recovery_sequence_height,
+                ..
+            } => {
+                require!(
+                    self.guardian_recovery_seq_heights
+                        .contains(&recovery_sequence_height),
+                    format!(
+                        "Invalid guardian recovery seq height. Available values are: {:?}",
+                        self.guardian_recovery_seq_heights
+                    )
+                );
+                let guardian_pubkey =
+                    PublicKey::from_slice(&guardian_pubkey).expect(ERR_BAD_GUARDIAN_PUBKEY);
+                (
+                    RedeemVersion::V2 {
+                        guardian_pubkey: guardian_pubkey.to_string(),
+                        recovery_sequence: recovery_sequence_height.into(),
+                    },
+                    BASE_STAKE_WEIGHT_BPS,
+                )
+            }
+            DepositEmbedMsg::V3 {
+                maturity_height, ..
+            } => {
+                let term = self
+                    .deposit_terms
+                    .iter()
+                    .find(|term| term.maturity_height == maturity_height)
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "Invalid deposit maturity height. Available values are: {:?}",
+                            self.deposit_terms
+                                .iter()
+                                .map(|term| term.maturity_height)
+                                .collect::<Vec<_>>()
+                        )
+                    });
+                (RedeemVersion::V3 { maturity_height }, term.stake_weight_bps)
             }
         };
+        self.verify_deposit_output(deposit_output, &user_pubkey, sequence, &redeem_version);
 
         let value = deposit_output.value;
-        require!(
-            value.to_sat() >= self.min_deposit_satoshi,
-            ERR_BAD_DEPOSIT_AMOUNT
-        );
 
-        Deposit::new(
+        let mut deposit = Deposit::new(
             user_pubkey_hex.clone().into(),
             redeem_version,
             txid.to_string().into(),
             deposit_vout,
             value.to_sat(),
             sequence_height.into(),
-        )
+        );
+        deposit.stake_weight_bps = stake_weight_bps;
+        deposit
     }
 
-    pub(crate) fn save_deposit_txn(&mut self, tx: &Transaction, embed_vout: u64) {
-        let deposit = self.verify_deposit_txn(tx, embed_vout);
+    pub(crate) fn save_deposit(
+        &mut self,
+        deposit: Deposit,
+        tx_block_hash: &str,
+        caller_id: &AccountId,
+    ) {
         let mut account = self.get_account(&deposit.user_pubkey.clone());
+        let user_pubkey = deposit.user_pubkey.to_string();
+        let tx_id = deposit.deposit_tx_id.to_string();
+        let (vout, value) = (deposit.deposit_vout, deposit.value);
+        let sequence_height = deposit.sequence as u16;
+        let redeem_version = deposit.redeem_version.clone();
+
+        self.total_deposit += value;
         account.create_deposit(deposit);
+
+        if self.emit_legacy_events {
+            Event::Deposit {
+                user_pubkey: user_pubkey.clone(),
+                tx_id: tx_id.clone(),
+                deposit_vout: vout.into(),
+                value: value.into(),
+            }
+            .emit();
+        }
+        EventV2::Deposit {
+            user_pubkey: user_pubkey.clone(),
+            tx_id: tx_id.clone(),
+            deposit_vout: vout.into(),
+            value: value.into(),
+            sequence_height,
+            redeem_version: redeem_version.clone().into(),
+            tx_block_hash: tx_block_hash.to_string(),
+            caller_id: caller_id.to_string(),
+            total_deposit: account.total_deposit.into(),
+        }
+        .emit();
+
         self.set_account(account);
     }
 
+    /// Solo withdrawal sequence height of new deposits, the first item is the current active one
+    pub(crate) fn active_solo_withdrawal_seq_height(&self) -> u16 {
+        *self
+            .solo_withdrawal_seq_heights
+            .first()
+            .expect(ERR_NO_SOLO_WITHDRAWAL_SEQ_HEIGHT)
+    }
+
+    /// Verify if the user is allowed to deposit under the current deposit mode
+    pub(crate) fn verify_deposit_allowed(&self, user_pubkey: &PubKey) -> Result<(), &'static str> {
+        match self.deposit_mode {
+            DepositMode::Closed => Err(ERR_DEPOSIT_CLOSED),
+            DepositMode::Allowlist if !self.deposit_allowlist.contains(user_pubkey) => {
+                Err(ERR_DEPOSIT_NOT_ALLOWED)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Deposit limits of the given sequence height, or the default ones if not overridden
+    pub(crate) fn deposit_limits(&self, sequence_height: u16) -> DepositLimits {
+        self.seq_height_deposit_limits
+            .iter()
+            .find(|l| l.sequence_height == sequence_height)
+            .map(|l| l.limits.clone())
+            .unwrap_or(DepositLimits {
+                min_deposit_satoshi: self.min_deposit_satoshi,
+                max_deposit_satoshi: self.max_deposit_satoshi,
+                max_user_deposit_satoshi: self.max_user_deposit_satoshi,
+            })
+    }
+
+    /// Verify if the deposit amount is within the limits and the deposit caps are not hit
+    pub(crate) fn verify_deposit_limits(&self, deposit: &Deposit) -> Result<(), &'static str> {
+        let limits = self.deposit_limits(deposit.sequence as u16);
+        if deposit.value < limits.min_deposit_satoshi {
+            return Err(ERR_BAD_DEPOSIT_AMOUNT);
+        }
+        if limits
+            .max_deposit_satoshi
+            .is_some_and(|max| deposit.value > max)
+        {
+            return Err(ERR_DEPOSIT_TOO_LARGE);
+        }
+        if let Some(max) = limits.max_user_deposit_satoshi {
+            let account = self.get_account(&deposit.user_pubkey);
+            if account.total_deposit + deposit.value > max {
+                return Err(ERR_USER_DEPOSIT_CAP_EXCEEDED);
+            }
+        }
+        if self
+            .max_total_deposit_satoshi
+            .is_some_and(|max| self.total_deposit + deposit.value > max)
+        {
+            return Err(ERR_TOTAL_DEPOSIT_CAP_EXCEEDED);
+        }
+        Ok(())
+    }
+
     pub(crate) fn verify_embed_output(&self, tx: &Transaction, embed_vout: u64) -> DepositEmbedMsg {
         let embed_output = tx.output.get(embed_vout as usize).expect(ERR_BAD_EMBED_IDX);
         let msg = get_embed_message(embed_output);
@@ -208,23 +397,20 @@ impl Contract {
         );
     }
 
-    /// Verify if output is a valid deposit output.
+    /// Verify if output is a valid deposit output of the given redeem version.
     /// Note that this function should **NEVER** be changed once goes online!
-    fn verify_deposit_output_v1(
+    fn verify_deposit_output(
         &self,
         output: &TxOut,
         user_pubkey: &PublicKey,
         sequence: Sequence,
+        redeem_version: &RedeemVersion,
     ) {
         require!(output.script_pubkey.is_p2wsh(), ERR_DEPOSIT_NOT_P2WSH);
         // first 2 bytes are OP_0 OP_PUSHBYTES_32, so we take from the 3rd byte (4th in hex)
         let p2wsh_script_hash = &output.script_pubkey.to_hex_string()[4..];
 
-        // derived pubkey from chain signatures
-        // if path is changed, a new deposit output version MUST be used
-        let bithive_pubkey = &self.generate_btc_pubkey(CHAIN_SIGNATURES_PATH_V1);
-
-        let script = Self::deposit_script_v1(user_pubkey, bithive_pubkey, sequence);
+        let script = self.redeem_script(user_pubkey, sequence, redeem_version);
         let expected_script_hash = env::sha256_array(script.as_bytes());
 
         // check if script hash == p2wsh
@@ -234,6 +420,51 @@ impl Contract {
         );
     }
 
+    /// Returns the redeem script of a saved deposit according to its redeem version
+    pub(crate) fn deposit_redeem_script(&self, deposit: &Deposit) -> ScriptBuf {
+        let user_pubkey =
+            PublicKey::from_str(&deposit.user_pubkey.to_string()).expect(ERR_BAD_PUBKEY_HEX);
+        self.redeem_script(
+            &user_pubkey,
+            Sequence::from_consensus(deposit.sequence),
+            &deposit.redeem_version,
+        )
+    }
+
+    /// Returns the redeem script of the given redeem version
+    fn redeem_script(
+        &self,
+        user_pubkey: &PublicKey,
+        sequence: Sequence,
+        redeem_version: &RedeemVersion,
+    ) -> ScriptBuf {
+        // derived pubkey from chain signatures
+        // if path is changed, a new deposit output version MUST be used
+        match redeem_version {
+            RedeemVersion::V1 => Self::deposit_script_v1(
+                user_pubkey,
+                &self.generate_btc_pubkey(CHAIN_SIGNATURES_PATH_V1),
+                sequence,
+            ),
+            RedeemVersion::V2 {
+                guardian_pubkey,
+                recovery_sequence,
+            } => Self::deposit_script_v2(
+                user_pubkey,
+                &self.generate_btc_pubkey(CHAIN_SIGNATURES_PATH_V2),
+                sequence,
+                &PublicKey::from_str(guardian_pubkey).expect(ERR_BAD_GUARDIAN_PUBKEY),
+                Sequence::from_consensus(*recovery_sequence),
+            ),
+            RedeemVersion::V3 { maturity_height } => Self::deposit_script_v3(
+                user_pubkey,
+                &self.generate_btc_pubkey(CHAIN_SIGNATURES_PATH_V3),
+                sequence,
+                LockTime::from_height(*maturity_height).expect(ERR_BAD_MATURITY_HEIGHT),
+            ),
+        }
+    }
+
     pub(crate) fn deposit_script_v1(
         user_pubkey: &PublicKey,
         bithive_pubkey: &PublicKey,
@@ -269,6 +500,107 @@ impl Contract {
             .into_script()
     }
 
+    pub(crate) fn deposit_script_v2(
+        user_pubkey: &PublicKey,
+        bithive_pubkey: &PublicKey,
+        sequence: Sequence,
+        guardian_pubkey: &PublicKey,
+        recovery_sequence: Sequence,
+    ) -> ScriptBuf {
+        // OP_IF
+        //     {{sequence}}
+        //     OP_CHECKSEQUENCEVERIFY
+        //     OP_DROP
+        //     {{user pubkey}}
+        //     OP_CHECKSIG
+        // OP_ELSE
+        //     OP_IF
+        //         OP_2
+        //         {{user pubkey}}
+        //         {{bithive pubkey}}
+        //         OP_2
+        //         OP_CHECKMULTISIG
+        //     OP_ELSE
+        //         {{recovery sequence}}
+        //         OP_CHECKSEQUENCEVERIFY
+        //         OP_DROP
+        //         OP_2
+        //         {{guardian pubkey}}
+        //         {{bithive pubkey}}
+        //         OP_2
+        //         OP_CHECKMULTISIG
+        //     OP_ENDIF
+        // OP_ENDIF
+        Builder::new()
+            .push_opcode(OP_IF)
+            .push_sequence(sequence)
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP)
+            .push_key(user_pubkey)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ELSE)
+            .push_opcode(OP_IF)
+            .push_opcode(OP_PUSHNUM_2)
+            .push_key(user_pubkey)
+            .push_key(bithive_pubkey)
+            .push_opcode(OP_PUSHNUM_2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .push_opcode(OP_ELSE)
+            .push_sequence(recovery_sequence)
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP)
+            .push_opcode(OP_PUSHNUM_2)
+            .push_key(guardian_pubkey)
+            .push_key(bithive_pubkey)
+            .push_opcode(OP_PUSHNUM_2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .push_opcode(OP_ENDIF)
+            .push_opcode(OP_ENDIF)
+            .into_script()
+    }
+
+    pub(crate) fn deposit_script_v3(
+        user_pubkey: &PublicKey,
+        bithive_pubkey: &PublicKey,
+        sequence: Sequence,
+        maturity: LockTime,
+    ) -> ScriptBuf {
+        // OP_IF
+        //     {{maturity}}
+        //     OP_CHECKLOCKTIMEVERIFY
+        //     OP_DROP
+        //     {{sequence}}
+        //     OP_CHECKSEQUENCEVERIFY
+        //     OP_DROP
+        //     {{user pubkey}}
+        //     OP_CHECKSIG
+        // OP_ELSE
+        //     OP_2
+        //     {{user pubkey}}
+        //     {{bithive pubkey}}
+        //     OP_2
+        //     OP_CHECKMULTISIG
+        // OP_ENDIF
+        Builder::new()
+            .push_opcode(OP_IF)
+            .push_lock_time(maturity)
+            .push_opcode(OP_CLTV)
+            .push_opcode(OP_DROP)
+            .push_sequence(sequence)
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP)
+            .push_key(user_pubkey)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ELSE)
+            .push_opcode(OP_PUSHNUM_2)
+            .push_key(user_pubkey)
+            .push_key(bithive_pubkey)
+            .push_opcode(OP_PUSHNUM_2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .push_opcode(OP_ENDIF)
+            .into_script()
+    }
+
     fn set_deposit_confirmed(&mut self, tx_id: &TxId, vout: u64) {
         let output_id = output_id(tx_id, vout);
         require!(
@@ -289,10 +621,14 @@ mod tests {
     use bitcoin::{
         consensus::encode::serialize_hex, opcodes::OP_0, transaction::Version, Amount, TxIn,
     };
-    use near_sdk::{test_utils::VMContextBuilder, testing_env};
+    use near_sdk::{
+        test_utils::{get_logs, VMContextBuilder},
+        testing_env,
+    };
 
     use super::*;
     use crate::tests::*;
+    use types::{DepositTerm, SequenceHeightDepositLimits};
 
     fn sequence_height() -> Sequence {
         Sequence::from_height(5)
@@ -378,6 +714,96 @@ mod tests {
         serialize_hex(&tx)
     }
 
+    fn guardian_pubkey() -> PublicKey {
+        PublicKey::from_str("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+            .unwrap()
+    }
+
+    fn build_tx_v2(contract: &Contract, recovery_sequence: Sequence) -> String {
+        let mut tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::from_height(0).unwrap(),
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+
+        // Add the deposit output
+        let bithive_pubkey = contract.generate_btc_pubkey(CHAIN_SIGNATURES_PATH_V2);
+        let deposit_script = Contract::deposit_script_v2(
+            &user_pubkey(),
+            &bithive_pubkey,
+            sequence_height(),
+            &guardian_pubkey(),
+            recovery_sequence,
+        );
+        tx.output.push(TxOut {
+            value: Amount::from_sat(100),
+            script_pubkey: ScriptBuf::new_p2wsh(&deposit_script.wscript_hash()),
+        });
+
+        // Add the embed output
+        let embed_msg = DepositEmbedMsg::V2 {
+            deposit_vout: 0,
+            user_pubkey: hex::decode(user_pubkey().to_string())
+                .unwrap()
+                .try_into()
+                .unwrap(),
+            sequence_height: sequence_height().to_consensus_u32() as u16,
+            guardian_pubkey: hex::decode(guardian_pubkey().to_string())
+                .unwrap()
+                .try_into()
+                .unwrap(),
+            recovery_sequence_height: recovery_sequence.to_consensus_u32() as u16,
+        };
+        let msg = bitcoin::script::PushBytesBuf::try_from(embed_msg.encode()).unwrap();
+        tx.output.push(TxOut {
+            value: Amount::from_sat(0),
+            script_pubkey: ScriptBuf::new_op_return(msg),
+        });
+
+        serialize_hex(&tx)
+    }
+
+    fn build_tx_v3(contract: &Contract, maturity_height: u32) -> String {
+        let mut tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::from_height(0).unwrap(),
+            input: vec![TxIn::default()],
+            output: vec![],
+        };
+
+        // Add the deposit output
+        let bithive_pubkey = contract.generate_btc_pubkey(CHAIN_SIGNATURES_PATH_V3);
+        let deposit_script = Contract::deposit_script_v3(
+            &user_pubkey(),
+            &bithive_pubkey,
+            sequence_height(),
+            LockTime::from_height(maturity_height).unwrap(),
+        );
+        tx.output.push(TxOut {
+            value: Amount::from_sat(1000),
+            script_pubkey: ScriptBuf::new_p2wsh(&deposit_script.wscript_hash()),
+        });
+
+        // Add the embed output
+        let embed_msg = DepositEmbedMsg::V3 {
+            deposit_vout: 0,
+            user_pubkey: hex::decode(user_pubkey().to_string())
+                .unwrap()
+                .try_into()
+                .unwrap(),
+            sequence_height: sequence_height().to_consensus_u32() as u16,
+            maturity_height,
+        };
+        let msg = bitcoin::script::PushBytesBuf::try_from(embed_msg.encode()).unwrap();
+        tx.output.push(TxOut {
+            value: Amount::from_sat(0),
+            script_pubkey: ScriptBuf::new_op_return(msg),
+        });
+
+        serialize_hex(&tx)
+    }
+
     #[test]
     #[should_panic(expected = "Invalid hex transaction")]
     fn test_invalid_tx_hex() {
@@ -472,4 +898,396 @@ mod tests {
         );
         verify_deposit_tx(&mut contract, tx_hex.to_string(), 1);
     }
+
+    #[test]
+    fn test_valid_v2_deposit_output() {
+        let mut contract = test_contract_instance();
+        contract.guardian_recovery_seq_heights = vec![100];
+        let tx_hex = build_tx_v2(&contract, Sequence::from_height(100));
+        let tx = deserialize_hex::<Transaction>(&tx_hex).unwrap();
+        let deposit = contract.verify_deposit_txn(&tx, 1);
+        assert!(matches!(
+            &deposit.redeem_version,
+            RedeemVersion::V2 {
+                guardian_pubkey: pubkey,
+                recovery_sequence: 100,
+            } if *pubkey == guardian_pubkey().to_string()
+        ));
+        assert_eq!(
+            contract.deposit_redeem_script(&deposit),
+            Contract::deposit_script_v2(
+                &user_pubkey(),
+                &contract.generate_btc_pubkey(CHAIN_SIGNATURES_PATH_V2),
+                sequence_height(),
+                &guardian_pubkey(),
+                Sequence::from_height(100),
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid guardian recovery seq height. Available values are: [100]")]
+    fn test_wrong_guardian_recovery_seq_height() {
+        let mut contract = test_contract_instance();
+        contract.guardian_recovery_seq_heights = vec![100];
+        let tx_hex = build_tx_v2(&contract, Sequence::from_height(50));
+        verify_deposit_tx(&mut contract, tx_hex, 1);
+    }
+
+    fn contract_with_deposit_terms() -> Contract {
+        let mut contract = test_contract_instance();
+        contract.deposit_terms = vec![
+            DepositTerm {
+                maturity_height: 900_000,
+                stake_weight_bps: 12_000,
+            },
+            DepositTerm {
+                maturity_height: 950_000,
+                stake_weight_bps: 15_000,
+            },
+        ];
+        contract
+    }
+
+    #[test]
+    fn test_valid_v3_deposit_output() {
+        let contract = contract_with_deposit_terms();
+        let tx_hex = build_tx_v3(&contract, 950_000);
+        let tx = deserialize_hex::<Transaction>(&tx_hex).unwrap();
+        let deposit = contract.verify_deposit_txn(&tx, 1);
+        assert_eq!(deposit.maturity_height(), Some(950_000));
+        assert_eq!(deposit.stake_weight_bps, 15_000);
+        assert_eq!(deposit.weighted_value(), 1500);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Invalid deposit maturity height. Available values are: [900000, 950000]"
+    )]
+    fn test_wrong_deposit_maturity_height() {
+        let mut contract = contract_with_deposit_terms();
+        let tx_hex = build_tx_v3(&contract, 920_000);
+        verify_deposit_tx(&mut contract, tx_hex, 1);
+    }
+
+    fn test_deposit(value: u64, sequence_height: u16) -> Deposit {
+        Deposit::new(
+            user_pubkey().to_string().into(),
+            RedeemVersion::V1,
+            "253b73f1450d6be67a16e46d05f62235f1728d737d9540f12b69f84f4cc5b595"
+                .to_string()
+                .into(),
+            0,
+            value,
+            sequence_height.into(),
+        )
+    }
+
+    fn block_hash() -> String {
+        "00000000000000000002a7c4c1e48d76c5a37902165a270156b7a8d72728a054".to_string()
+    }
+
+    fn caller_id() -> AccountId {
+        AccountId::new_unchecked("alice".to_string())
+    }
+
+    #[test]
+    fn test_deposit_limits() {
+        let mut contract = test_contract_instance();
+        contract.min_deposit_satoshi = 100;
+        contract.max_deposit_satoshi = Some(1000);
+        contract.seq_height_deposit_limits = vec![SequenceHeightDepositLimits {
+            sequence_height: 10,
+            limits: DepositLimits {
+                min_deposit_satoshi: 500,
+                max_deposit_satoshi: None,
+                max_user_deposit_satoshi: Some(3000),
+            },
+        }];
+
+        assert_eq!(
+            contract.verify_deposit_limits(&test_deposit(99, 5)),
+            Err(ERR_BAD_DEPOSIT_AMOUNT)
+        );
+        assert_eq!(
+            contract.verify_deposit_limits(&test_deposit(1001, 5)),
+            Err(ERR_DEPOSIT_TOO_LARGE)
+        );
+        assert_eq!(
+            contract.verify_deposit_limits(&test_deposit(1000, 5)),
+            Ok(())
+        );
+
+        // limits overridden for the sequence height
+        assert_eq!(
+            contract.verify_deposit_limits(&test_deposit(499, 10)),
+            Err(ERR_BAD_DEPOSIT_AMOUNT)
+        );
+        assert_eq!(
+            contract.verify_deposit_limits(&test_deposit(2000, 10)),
+            Ok(())
+        );
+        contract.save_deposit(test_deposit(2000, 10), &block_hash(), &caller_id());
+        assert_eq!(
+            contract.verify_deposit_limits(&test_deposit(1001, 10)),
+            Err(ERR_USER_DEPOSIT_CAP_EXCEEDED)
+        );
+    }
+
+    #[test]
+    fn test_deposit_events() {
+        let mut contract = test_contract_instance();
+        testing_env!(VMContextBuilder::new().build());
+        contract.save_deposit(test_deposit(600, 5), &block_hash(), &caller_id());
+        let logs = get_logs();
+        assert_eq!(logs.len(), 2);
+        assert!(logs[0].contains(r#""version":"1.1.0","event":"deposit""#));
+        assert!(logs[1].contains(r#""version":"2.0.0","event":"deposit""#));
+        assert!(logs[1].contains(r#""sequence_height":5"#));
+        assert!(logs[1].contains(r#""caller_id":"alice""#));
+
+        // only v2 events after legacy events are disabled
+        contract.emit_legacy_events = false;
+        let mut deposit = test_deposit(400, 5);
+        deposit.deposit_vout = 1;
+        testing_env!(VMContextBuilder::new().build());
+        contract.save_deposit(deposit, &block_hash(), &caller_id());
+        let logs = get_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains(r#""total_deposit":"1000""#));
+    }
+
+    #[test]
+    fn test_total_deposit_cap() {
+        let mut contract = test_contract_instance();
+        contract.max_total_deposit_satoshi = Some(1000);
+
+        contract.save_deposit(test_deposit(600, 5), &block_hash(), &caller_id());
+        assert_eq!(contract.total_deposit, 600);
+        assert_eq!(
+            contract.verify_deposit_limits(&test_deposit(401, 5)),
+            Err(ERR_TOTAL_DEPOSIT_CAP_EXCEEDED)
+        );
+        assert_eq!(
+            contract.verify_deposit_limits(&test_deposit(400, 5)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_deposit_allowlist_mode() {
+        let mut contract = test_contract_instance();
+        let allowed: PubKey = user_pubkey().to_string().into();
+        contract.deposit_allowlist.insert(&allowed);
+
+        contract.deposit_mode = DepositMode::Allowlist;
+        assert_eq!(contract.verify_deposit_allowed(&allowed), Ok(()));
+        assert_eq!(
+            contract.verify_deposit_allowed(
+                &"0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+                    .to_string()
+                    .into(),
+            ),
+            Err(ERR_DEPOSIT_NOT_ALLOWED)
+        );
+    }
+
+    #[test]
+    fn test_deposit_closed_mode() {
+        let mut contract = test_contract_instance();
+        let allowed: PubKey = user_pubkey().to_string().into();
+        contract.deposit_allowlist.insert(&allowed);
+
+        contract.deposit_mode = DepositMode::Closed;
+        assert_eq!(
+            contract.verify_deposit_allowed(&allowed),
+            Err(ERR_DEPOSIT_CLOSED)
+        );
+    }
+
+    #[test]
+    fn test_deposit_closed_before_confirmation() {
+        let mut contract = test_contract_instance();
+        let tx_hex = build_tx(
+            &contract,
+            &user_pubkey(),
+            sequence_height(),
+            0,
+            Some(LockTime::from_height(100).unwrap()),
+        );
+        submit_deposit_tx(&mut contract, tx_hex.clone(), 1);
+        let tx = deserialize_hex::<Transaction>(&tx_hex).unwrap();
+        let txid = tx.compute_txid().to_string();
+        let deposit = contract.verify_deposit_txn(&tx, 1);
+
+        // deposit is closed while the confirmation is being verified
+        contract.deposit_mode = DepositMode::Closed;
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(env::current_account_id());
+        testing_env!(builder.build());
+        assert!(!contract.on_verify_deposit_tx(
+            tx_hex,
+            deposit,
+            block_hash(),
+            caller_id(),
+            STORAGE_DEPOSIT_ACCOUNT.into(),
+            Ok(true),
+        ));
+
+        assert!(!contract
+            .confirmed_deposit_txns
+            .contains(&output_id(&txid.into(), 0)));
+        assert_eq!(contract.total_deposit, 0);
+    }
+
+    #[test]
+    fn test_deposit_config_changed_before_confirmation() {
+        let mut contract = test_contract_instance();
+        let tx_hex = build_tx(
+            &contract,
+            &user_pubkey(),
+            sequence_height(),
+            0,
+            Some(LockTime::from_height(100).unwrap()),
+        );
+        submit_deposit_tx(&mut contract, tx_hex.clone(), 1);
+        let tx = deserialize_hex::<Transaction>(&tx_hex).unwrap();
+        let deposit = contract.verify_deposit_txn(&tx, 1);
+
+        // the seq height of the deposit is removed while the confirmation is being verified
+        contract.solo_withdrawal_seq_heights = vec![10];
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(env::current_account_id());
+        testing_env!(builder.build());
+        assert!(contract.on_verify_deposit_tx(
+            tx_hex,
+            deposit,
+            block_hash(),
+            caller_id(),
+            STORAGE_DEPOSIT_ACCOUNT.into(),
+            Ok(true),
+        ));
+        assert_eq!(contract.total_deposit, 100);
+    }
+
+    fn valid_deposit_tx(contract: &Contract) -> (Transaction, Deposit) {
+        let tx_hex = build_tx(
+            contract,
+            &user_pubkey(),
+            sequence_height(),
+            0,
+            Some(LockTime::from_height(100).unwrap()),
+        );
+        let tx = deserialize_hex::<Transaction>(&tx_hex).unwrap();
+        let deposit = contract.verify_deposit_txn(&tx, 1);
+        (tx, deposit)
+    }
+
+    #[test]
+    fn test_denied_pubkey() {
+        let mut contract = test_contract_instance();
+        let (tx, deposit) = valid_deposit_tx(&contract);
+        assert_eq!(contract.verify_deposit_not_denied(&tx, &deposit), Ok(()));
+
+        contract
+            .denied_pubkeys
+            .insert(&user_pubkey().to_string().into());
+        assert_eq!(
+            contract.verify_deposit_not_denied(&tx, &deposit),
+            Err("User pubkey is denied")
+        );
+    }
+
+    #[test]
+    fn test_denied_input_outpoint() {
+        let mut contract = test_contract_instance();
+        let (tx, deposit) = valid_deposit_tx(&contract);
+        let prev_output = tx.input[0].previous_output;
+        contract.denied_outpoints.insert(&output_id(
+            &prev_output.txid.to_string().into(),
+            prev_output.vout.into(),
+        ));
+        assert_eq!(
+            contract.verify_deposit_not_denied(&tx, &deposit),
+            Err("Outpoint is denied")
+        );
+    }
+
+    #[test]
+    fn test_pubkey_denied_before_confirmation() {
+        let mut contract = test_contract_instance();
+        let tx_hex = build_tx(
+            &contract,
+            &user_pubkey(),
+            sequence_height(),
+            0,
+            Some(LockTime::from_height(100).unwrap()),
+        );
+        submit_deposit_tx(&mut contract, tx_hex.clone(), 1);
+        let tx = deserialize_hex::<Transaction>(&tx_hex).unwrap();
+        let txid = tx.compute_txid().to_string();
+        let deposit = contract.verify_deposit_txn(&tx, 1);
+
+        // the pubkey is denied while the confirmation is being verified
+        contract
+            .denied_pubkeys
+            .insert(&user_pubkey().to_string().into());
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(env::current_account_id());
+        testing_env!(builder.build());
+        assert!(!contract.on_verify_deposit_tx(
+            tx_hex,
+            deposit,
+            block_hash(),
+            caller_id(),
+            STORAGE_DEPOSIT_ACCOUNT.into(),
+            Ok(true),
+        ));
+
+        assert!(!contract
+            .confirmed_deposit_txns
+            .contains(&output_id(&txid.into(), 0)));
+        assert_eq!(contract.total_deposit, 0);
+    }
+
+    #[test]
+    fn test_state_migration_started_before_confirmation() {
+        let mut contract = test_contract_instance();
+        let tx_hex = build_tx(
+            &contract,
+            &user_pubkey(),
+            sequence_height(),
+            0,
+            Some(LockTime::from_height(100).unwrap()),
+        );
+        submit_deposit_tx(&mut contract, tx_hex.clone(), 1);
+        let tx = deserialize_hex::<Transaction>(&tx_hex).unwrap();
+        let txid = tx.compute_txid().to_string();
+        let deposit = contract.verify_deposit_txn(&tx, 1);
+
+        // the total deposit is being summed up from the accounts
+        contract.state_migration = Some(StateMigration {
+            from_version: 1,
+            migrated_accounts: 0,
+        });
+        let mut builder = VMContextBuilder::new();
+        builder.predecessor_account_id(env::current_account_id());
+        testing_env!(builder.build());
+        assert!(!contract.on_verify_deposit_tx(
+            tx_hex,
+            deposit,
+            block_hash(),
+            caller_id(),
+            STORAGE_DEPOSIT_ACCOUNT.into(),
+            Ok(true),
+        ));
+
+        assert!(!contract
+            .confirmed_deposit_txns
+            .contains(&output_id(&txid.into(), 0)));
+        assert_eq!(contract.total_deposit, 0);
+        assert!(get_logs()
+            .iter()
+            .any(|log| log.contains("State migration in progress")));
+    }
 }
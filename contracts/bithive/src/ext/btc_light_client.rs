@@ -10,11 +10,20 @@ use serde::{
 };
 
 pub const GAS_LIGHT_CLIENT_VERIFY: Gas = Gas(30 * Gas::ONE_TERA.0);
+pub const GAS_LIGHT_CLIENT_GET_TIP: Gas = Gas(10 * Gas::ONE_TERA.0);
 
 #[ext_contract(ext_btc_light_client)]
 #[allow(dead_code)]
 pub trait BtcLightClient {
     fn verify_transaction_inclusion(&self, #[serializer(borsh)] args: ProofArgs) -> bool;
+    fn get_last_block_header(&self) -> BtcBlockHeader;
+}
+
+/// Header of the BTC main chain tip, only the fields needed are kept
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BtcBlockHeader {
+    pub block_height: u64,
 }
 
 #[derive(BorshDeserialize, BorshSerialize)]
@@ -1,18 +1,21 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::{
-    collections::{LookupMap, LookupSet},
-    AccountId,
+    collections::{LookupSet, UnorderedMap},
+    AccountId, Balance, Timestamp,
 };
 
 use crate::{
-    account::VersionedAccount,
-    types::{OutputId, PubKey},
+    account::{DepositStatus, VersionedAccount, VersionedDeposit},
+    types::{BorshPsbt, OutputId, PubKey, RedeemVersion, TxId},
 };
 
+/// Contract layout deployed before the state version is tracked
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct ContractV1 {
     /// contract owner ID
     pub owner_id: AccountId,
+    /// pending owner ID
+    pub pending_owner_id: Option<AccountId>,
     /// btc light client contract ID
     pub btc_light_client_id: AccountId,
     /// bip322 verifier contract ID
@@ -36,7 +39,42 @@ pub struct ContractV1 {
     /// set of all confirmed deposit txns
     pub confirmed_deposit_txns: LookupSet<OutputId>,
     /// user accounts: pubkey -> account
-    pub accounts: LookupMap<PubKey, VersionedAccount>,
+    pub accounts: UnorderedMap<PubKey, VersionedAccount>,
     /// whether the contract is paused
     pub paused: bool,
 }
+
+/// Account layout before withdrawal tickets and multiple pending sign PSBTs
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct AccountV1 {
+    pub pubkey: PubKey,
+    pub total_deposit: u64,
+    pub active_deposits: UnorderedMap<OutputId, VersionedDeposit>,
+    pub withdrawn_deposits: UnorderedMap<OutputId, VersionedDeposit>,
+    pub queue_withdrawal_amount: u64,
+    pub queue_withdrawal_start_ts: Timestamp,
+    pub nonce: u64,
+    pub pending_sign_psbt: Option<PendingSignPsbtV1>,
+    pub pending_sign_deposit: Balance,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct PendingSignPsbtV1 {
+    pub psbt: BorshPsbt,
+    pub reinvest_deposit_vout: Option<u64>,
+    pub reinvest_embed_vout: Option<u64>,
+}
+
+/// Deposit layout before stake weight
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct DepositV1 {
+    pub user_pubkey: PubKey,
+    pub status: DepositStatus,
+    pub redeem_version: RedeemVersion,
+    pub deposit_tx_id: TxId,
+    pub deposit_vout: u64,
+    pub value: u64,
+    pub sequence: u32,
+    pub complete_withdrawal_ts: Timestamp,
+    pub withdrawal_tx_id: Option<TxId>,
+}
@@ -1,18 +1,34 @@
 use account::{Account, VersionedAccount};
-use ext::ext_chain_signatures;
+use events::Event;
+use ext::{ext_btc_light_client, ext_chain_signatures, BtcBlockHeader, GAS_LIGHT_CLIENT_GET_TIP};
+use migration::{write_state_version, StateMigration, CURRENT_STATE_VERSION};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupSet, UnorderedMap};
+use near_sdk::collections::{LookupSet, UnorderedMap, UnorderedSet};
 use near_sdk::{env, near_bindgen, require, AccountId, Gas, PanicOnDefault, Promise, PromiseError};
-use types::{InitArgs, OutputId, PubKey, StorageKey};
+use rate_limit::WithdrawalRateLimit;
+use timelock::PendingConfigChange;
+use types::{
+    DepositMode, DepositTerm, InitArgs, OutputId, PubKey, Role, SequenceHeightDepositLimits,
+    StorageKey, WithdrawalWaitingTier,
+};
+use upgrade::StagedUpgrade;
 
 mod account;
 mod admin;
+mod compliance;
+mod config;
 mod consts;
 mod deposit;
 mod events;
 mod ext;
 mod kdf;
 mod legacy;
+mod metadata;
+mod migration;
+mod rate_limit;
+mod recovery;
+mod roles;
+mod timelock;
 mod types;
 mod upgrade;
 mod utils;
@@ -21,9 +37,16 @@ mod withdraw;
 
 const ERR_ROOT_PK_ALREADY_SYNCED: &str = "Root pubkey already synced";
 const ERR_FAILED_TO_SYNC_KEY: &str = "Failed to sync root pubkey from chain sig";
+const ERR_FAILED_TO_SYNC_BTC_TIP: &str = "Failed to sync BTC tip height from light client";
+const ERR_STATE_MIGRATION_IN_PROGRESS: &str = "State migration in progress";
 
 const GAS_GET_ROOT_PUBKEY: Gas = Gas(30 * Gas::ONE_TERA.0);
 const GAS_GET_ROOT_PUBKEY_CB: Gas = Gas(10 * Gas::ONE_TERA.0);
+const GAS_SYNC_BTC_TIP_CB: Gas = Gas(10 * Gas::ONE_TERA.0);
+
+const DEFAULT_GUARDIAN_RECOVERY_WAITING_TIME_MS: u64 = 30 * 24 * 3600 * 1000; // 30 days
+const DEFAULT_CONFIG_TIMELOCK_MS: u64 = 2 * 24 * 3600 * 1000; // 2 days
+const DEFAULT_UPGRADE_DELAY_MS: u64 = 2 * 24 * 3600 * 1000; // 2 days
 
 #[near_bindgen]
 #[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
@@ -32,6 +55,18 @@ pub struct Contract {
     owner_id: AccountId,
     /// pending owner ID
     pending_owner_id: Option<AccountId>,
+    /// roles granted by the owner: account ID -> roles
+    roles: UnorderedMap<AccountId, Vec<Role>>,
+    /// how long a sensitive config change needs to wait before it could be executed
+    config_timelock_ms: u64,
+    /// sensitive config changes that are waiting to be executed
+    pending_config_changes: Vec<PendingConfigChange>,
+    /// ID of the next proposed config change
+    next_config_change_id: u64,
+    /// how long a staged upgrade needs to wait before it could be deployed
+    upgrade_delay_ms: u64,
+    /// contract code that is waiting to be deployed
+    staged_upgrade: Option<StagedUpgrade>,
     /// btc light client contract ID
     btc_light_client_id: AccountId,
     /// bip322 verifier contract ID
@@ -46,16 +81,62 @@ pub struct Contract {
     n_confirmation: u64,
     /// for multisig withdrawal, how long the withdrawal request needs to be queued
     withdrawal_waiting_time_ms: u64,
+    /// for multisig withdrawal, larger amounts could wait longer than `withdrawal_waiting_time_ms`
+    /// sorted by amount, both amount and waiting time are increasing
+    withdrawal_waiting_tiers: Vec<WithdrawalWaitingTier>,
+    /// for multisig withdrawal, if set, how many BTC blocks the withdrawal request needs to be queued
+    /// instead of `withdrawal_waiting_time_ms`
+    withdrawal_waiting_btc_blocks: Option<u64>,
+    /// BTC tip height last synced from btc light client
+    btc_tip_height: u64,
+    /// for multisig withdrawal, how long a pending sign PSBT stays valid since it's first signed
+    pending_sign_psbt_expiry_ms: u64,
+    /// for multisig withdrawal, if set, limits the total amount cosigned by the contract
+    /// within a sliding window, acting as a circuit breaker
+    withdrawal_rate_limit: Option<WithdrawalRateLimit>,
     /// minimum deposit amount in satoshi
     min_deposit_satoshi: u64,
+    /// maximum amount of a single deposit in satoshi
+    max_deposit_satoshi: Option<u64>,
+    /// maximum total amount of active deposits of a single user in satoshi
+    max_user_deposit_satoshi: Option<u64>,
+    /// deposit limits overriding the default ones above for specific sequence heights
+    seq_height_deposit_limits: Vec<SequenceHeightDepositLimits>,
+    /// maximum total amount of active deposits of all users in satoshi
+    max_total_deposit_satoshi: Option<u64>,
+    /// total amount of active deposits of all users in satoshi
+    total_deposit: u64,
     /// earliest block height acceptable for deposit
     earliest_deposit_block_height: u32,
     /// list of available solo withdrawal sequence heights, used by redeem script
     solo_withdrawal_seq_heights: Vec<u16>,
+    /// list of available guardian recovery sequence heights, used by redeem script v2
+    /// deposits with guardian recovery are not accepted if empty
+    guardian_recovery_seq_heights: Vec<u16>,
+    /// how long a guardian recovery request needs to wait before bithive cosigns it
+    guardian_recovery_waiting_time_ms: u64,
+    /// list of available fixed deposit terms, used by redeem script v3
+    /// fixed-term deposits are not accepted if empty
+    deposit_terms: Vec<DepositTerm>,
     /// set of all confirmed deposit txns
     confirmed_deposit_txns: LookupSet<OutputId>,
     /// user accounts: pubkey -> account
     accounts: UnorderedMap<PubKey, VersionedAccount>,
+    /// who could make deposits
+    deposit_mode: DepositMode,
+    /// users allowed to deposit in allowlist mode
+    deposit_allowlist: UnorderedSet<PubKey>,
+    /// users whose deposits are rejected
+    denied_pubkeys: UnorderedSet<PubKey>,
+    /// outpoints that cannot be deposited or spent by deposit txns
+    denied_outpoints: UnorderedSet<OutputId>,
+    /// set if the state is being migrated from a legacy version,
+    /// the contract would not operate until it's complete
+    state_migration: Option<StateMigration>,
+    /// number of accounts still stored in a legacy layout
+    unmigrated_accounts: u64,
+    /// whether v1 events are emitted along with their v2 counterparts
+    emit_legacy_events: bool,
     /// whether the contract is paused
     paused: bool,
 }
@@ -65,20 +146,51 @@ impl Contract {
     #[init]
     #[private]
     pub fn init(args: InitArgs) -> Self {
+        require!(
+            args.pending_sign_psbt_expiry_ms > 0,
+            "pending_sign_psbt_expiry_ms must be greater than 0"
+        );
+        write_state_version(CURRENT_STATE_VERSION);
         Self {
             owner_id: args.owner_id,
             pending_owner_id: None,
+            roles: UnorderedMap::new(StorageKey::Roles),
+            config_timelock_ms: DEFAULT_CONFIG_TIMELOCK_MS,
+            pending_config_changes: vec![],
+            next_config_change_id: 0,
+            upgrade_delay_ms: DEFAULT_UPGRADE_DELAY_MS,
+            staged_upgrade: None,
             btc_light_client_id: args.btc_light_client_id,
             bip322_verifier_id: args.bip322_verifier_id,
             chain_signatures_id: args.chain_signatures_id,
             chain_signatures_root_pubkey: None,
             n_confirmation: args.n_confirmation,
             withdrawal_waiting_time_ms: args.withdrawal_waiting_time_ms,
+            withdrawal_waiting_tiers: vec![],
+            withdrawal_waiting_btc_blocks: None,
+            btc_tip_height: 0,
+            pending_sign_psbt_expiry_ms: args.pending_sign_psbt_expiry_ms,
+            withdrawal_rate_limit: None,
             min_deposit_satoshi: args.min_deposit_satoshi,
+            max_deposit_satoshi: None,
+            max_user_deposit_satoshi: None,
+            seq_height_deposit_limits: vec![],
+            max_total_deposit_satoshi: None,
+            total_deposit: 0,
             earliest_deposit_block_height: args.earliest_deposit_block_height,
             solo_withdrawal_seq_heights: args.solo_withdrawal_seq_heights,
+            guardian_recovery_seq_heights: vec![],
+            guardian_recovery_waiting_time_ms: DEFAULT_GUARDIAN_RECOVERY_WAITING_TIME_MS,
+            deposit_terms: vec![],
             confirmed_deposit_txns: LookupSet::new(StorageKey::ConfirmedDeposits),
             accounts: UnorderedMap::new(StorageKey::Accounts),
+            deposit_mode: DepositMode::Open,
+            deposit_allowlist: UnorderedSet::new(StorageKey::DepositAllowlist),
+            denied_pubkeys: UnorderedSet::new(StorageKey::DeniedPubkeys),
+            denied_outpoints: UnorderedSet::new(StorageKey::DeniedOutpoints),
+            state_migration: None,
+            unmigrated_accounts: 0,
+            emit_legacy_events: true,
             paused: false,
         }
     }
@@ -109,6 +221,30 @@ impl Contract {
         self.set_chain_signatures_root_pubkey(pk.clone());
         pk
     }
+
+    /// sync BTC tip height from btc light client, which is used to check
+    /// if withdrawal requests have waited for enough BTC blocks and if fixed-term deposits have matured
+    /// this could be called by anyone
+    pub fn sync_btc_tip_height(&self) -> Promise {
+        ext_btc_light_client::ext(self.btc_light_client_id.clone())
+            .with_static_gas(GAS_LIGHT_CLIENT_GET_TIP)
+            .get_last_block_header()
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_SYNC_BTC_TIP_CB)
+                    .on_sync_btc_tip_height(),
+            )
+    }
+
+    #[private]
+    pub fn on_sync_btc_tip_height(
+        &mut self,
+        #[callback_result] result: Result<BtcBlockHeader, PromiseError>,
+    ) -> u64 {
+        let header = result.expect(ERR_FAILED_TO_SYNC_BTC_TIP);
+        self.update_btc_tip_height(header.block_height);
+        self.btc_tip_height
+    }
 }
 
 impl Contract {
@@ -118,7 +254,19 @@ impl Contract {
             self.chain_signatures_root_pubkey.is_none(),
             ERR_ROOT_PK_ALREADY_SYNCED
         );
-        self.chain_signatures_root_pubkey = Some(pk);
+        self.chain_signatures_root_pubkey = Some(pk.clone());
+
+        Event::SyncChainSignaturesRootPubkey {
+            root_pubkey: String::from(&pk),
+        }
+        .emit();
+    }
+
+    /// BTC tip height should never go backwards
+    pub(crate) fn update_btc_tip_height(&mut self, height: u64) {
+        if height > self.btc_tip_height {
+            self.btc_tip_height = height;
+        }
     }
 
     fn get_account(&self, pubkey: &PubKey) -> Account {
@@ -129,13 +277,30 @@ impl Contract {
     }
 
     fn set_account(&mut self, account: Account) {
-        self.accounts
+        let prev = self
+            .accounts
             .insert(&account.pubkey.clone(), &account.into());
+        if prev.is_some_and(|a| !a.is_current()) {
+            self.unmigrated_accounts = self.unmigrated_accounts.saturating_sub(1);
+        }
     }
 
     pub(crate) fn assert_running(&self) {
+        if let Err(err) = self.verify_not_migrating() {
+            env::panic_str(err);
+        }
         require!(!self.paused, "Contract is paused");
     }
+
+    /// Callbacks of requests made before a state migration must not change the total deposit
+    /// while it is being summed up from the accounts
+    pub(crate) fn verify_not_migrating(&self) -> Result<(), &'static str> {
+        if self.state_migration.is_some() {
+            Err(ERR_STATE_MIGRATION_IN_PROGRESS)
+        } else {
+            Ok(())
+        }
+    }
 }
 
 #[cfg(test)]
@@ -152,6 +317,7 @@ mod tests {
             chain_signatures_id: AccountId::new_unchecked("cs".to_string()),
             n_confirmation: 6,
             withdrawal_waiting_time_ms: 0,
+            pending_sign_psbt_expiry_ms: 1000,
             min_deposit_satoshi: 0,
             earliest_deposit_block_height: 0,
             solo_withdrawal_seq_heights: vec![5],
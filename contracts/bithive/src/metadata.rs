@@ -0,0 +1,102 @@
+use crate::*;
+use bithive_types::events::{EVENT_STANDARD, EVENT_STANDARD_VERSION, EVENT_STANDARD_VERSION_V2};
+use view::{BuildInfo, ContractSourceMetadata, Standard};
+
+const NEP330_STANDARD: &str = "nep330";
+const NEP330_VERSION: &str = "1.2.0";
+
+const CONTRACT_PATH: &str = "contracts/bithive";
+const GIT_COMMIT: &str = env!("BITHIVE_GIT_COMMIT");
+const GIT_REPOSITORY: &str = env!("BITHIVE_GIT_REPOSITORY");
+const RUSTC_VERSION: &str = env!("BITHIVE_RUSTC_VERSION");
+
+#[near_bindgen]
+impl Contract {
+    /// Return the source metadata of the deployed code as defined in NEP-330,
+    /// which tells the crate version and the git commit it's built from
+    pub fn contract_source_metadata(&self) -> ContractSourceMetadata {
+        ContractSourceMetadata {
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            link: non_empty(GIT_REPOSITORY),
+            standards: vec![
+                standard(NEP330_STANDARD, NEP330_VERSION),
+                standard(EVENT_STANDARD, EVENT_STANDARD_VERSION),
+                standard(EVENT_STANDARD, EVENT_STANDARD_VERSION_V2),
+            ],
+            build_info: build_info(GIT_REPOSITORY, GIT_COMMIT, RUSTC_VERSION),
+        }
+    }
+}
+
+/// Build info is omitted if the source repository, commit or toolchain is not set
+fn build_info(repository: &str, commit: &str, rustc_version: &str) -> Option<BuildInfo> {
+    if repository.is_empty() || commit.is_empty() || rustc_version.is_empty() {
+        return None;
+    }
+    Some(BuildInfo {
+        build_environment: rustc_version.to_string(),
+        build_command: build_command(),
+        contract_path: CONTRACT_PATH.to_string(),
+        source_code_snapshot: format!("git+{}?rev={}", repository, commit),
+    })
+}
+
+fn standard(standard: &str, version: &str) -> Standard {
+    Standard {
+        standard: standard.to_string(),
+        version: version.to_string(),
+    }
+}
+
+/// Make target the code is built with, see `Makefile`
+fn build_command() -> Vec<String> {
+    let target = if cfg!(feature = "test") {
+        "bithive-test"
+    } else {
+        "bithive"
+    };
+    vec!["make".to_string(), target.to_string()]
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    Some(value.to_string()).filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_info;
+    use crate::{migration::CURRENT_STATE_VERSION, tests::test_contract_instance};
+
+    #[test]
+    fn test_contract_source_metadata() {
+        let contract = test_contract_instance();
+        let metadata = contract.contract_source_metadata();
+        assert_eq!(metadata.version.unwrap(), env!("CARGO_PKG_VERSION"));
+        assert!(metadata
+            .standards
+            .iter()
+            .any(|s| s.standard == "nep330" && s.version == "1.2.0"));
+        assert_eq!(
+            metadata.build_info.is_some(),
+            !env!("BITHIVE_GIT_REPOSITORY").is_empty()
+                && !env!("BITHIVE_GIT_COMMIT").is_empty()
+                && !env!("BITHIVE_RUSTC_VERSION").is_empty()
+        );
+
+        let repository = "https://github.com/bithive/bithive";
+        let build_info = build_info(repository, "abc", "rustc 1.78.0").unwrap();
+        assert_eq!(
+            build_info.source_code_snapshot,
+            "git+https://github.com/bithive/bithive?rev=abc"
+        );
+        assert_eq!(build_info.build_environment, "rustc 1.78.0");
+        assert_eq!(build_info.contract_path, "contracts/bithive");
+        assert!(super::build_info("", "abc", "rustc 1.78.0").is_none());
+        assert!(super::build_info(repository, "", "rustc 1.78.0").is_none());
+        assert!(super::build_info(repository, "abc", "").is_none());
+
+        let summary = contract.get_summary();
+        assert_eq!(summary.state_version, CURRENT_STATE_VERSION);
+        assert_eq!(summary.supported_redeem_versions, vec!["V1", "V2", "V3"]);
+    }
+}
@@ -0,0 +1,481 @@
+use crate::*;
+use events::Event;
+use legacy::ContractV1;
+use near_sdk::{assert_one_yocto, require, serde::Serialize};
+use std::cmp::min;
+
+/// state version is kept out of the contract state so that it could be read before the state itself
+const STATE_VERSION_KEY: &[u8] = b"__STATE_VERSION__";
+
+/// contract state written before the state version is tracked is `ContractV1`,
+/// i.e. the layout of the last release. Layouts in between were never deployed,
+/// so the current state is migrated from it in one step
+pub const STATE_VERSION_V1: u32 = 1;
+pub const CURRENT_STATE_VERSION: u32 = 2;
+
+/// `ContractV1` has no expiry for pending sign PSBTs
+const V1_PENDING_SIGN_PSBT_EXPIRY_MS: u64 = 7 * 24 * 3600 * 1000; // 7 days
+
+const ERR_NO_STATE_MIGRATION: &str = "No state migration in progress";
+const ERR_STATE_MIGRATION_INCOMPLETE: &str = "Not all accounts are migrated";
+
+/// Accounts of a legacy state that are visited in batches across multiple calls
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct StateMigration {
+    /// state version migrated from
+    pub from_version: u32,
+    /// number of accounts visited so far
+    pub migrated_accounts: u64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StateVersionView {
+    pub version: u32,
+    /// state version being migrated from, if a migration is incomplete
+    pub migrating_from: Option<u32>,
+    pub migrated_accounts: u64,
+    /// number of accounts still stored in a legacy layout
+    pub unmigrated_accounts: u64,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Visit the next `limit` accounts of the legacy state.
+    /// The account layout is kept as is and upgraded lazily,
+    /// those stored in a legacy layout are counted into `unmigrated_accounts`,
+    /// except the ones with a pending sign PSBT which are rewritten right away,
+    /// so that the PSBT does not have to be upgraded on every read.
+    /// `total_deposit` is not tracked by the legacy state, so it's summed up from the accounts
+    /// ### Returns
+    /// number of accounts visited so far
+    #[payable]
+    pub fn migrate_accounts(&mut self, limit: u64) -> u64 {
+        self.assert_role(Role::Upgrader);
+        let migration = self.state_migration.as_mut().expect(ERR_NO_STATE_MIGRATION);
+        let end = min(
+            migration.migrated_accounts.saturating_add(limit),
+            self.accounts.len(),
+        );
+        for idx in migration.migrated_accounts..end {
+            let pubkey = self.accounts.keys_as_vector().get(idx).unwrap();
+            let total_deposit = match self.accounts.get(&pubkey).unwrap() {
+                VersionedAccount::V1(legacy) if legacy.pending_sign_psbt.is_some() => {
+                    let account: Account = legacy.into();
+                    let total_deposit = account.total_deposit;
+                    self.accounts.insert(&pubkey, &account.into());
+                    total_deposit
+                }
+                VersionedAccount::V1(legacy) => {
+                    self.unmigrated_accounts += 1;
+                    legacy.total_deposit
+                }
+                VersionedAccount::Current(account) => account.total_deposit,
+            };
+            self.total_deposit += total_deposit;
+            migration.migrated_accounts += 1;
+        }
+        migration.migrated_accounts
+    }
+
+    /// Mark the state migration as complete once all legacy accounts are visited,
+    /// so that the contract could operate again
+    #[payable]
+    pub fn finish_state_migration(&mut self) {
+        self.assert_role(Role::Upgrader);
+        let migration = self.state_migration.take().expect(ERR_NO_STATE_MIGRATION);
+        require!(
+            migration.migrated_accounts == self.accounts.len(),
+            ERR_STATE_MIGRATION_INCOMPLETE
+        );
+
+        Event::FinishStateMigration {
+            migrated_accounts: migration.migrated_accounts.into(),
+        }
+        .emit();
+    }
+
+    /// Rewrite a page of accounts and their deposits stored in legacy layouts,
+    /// instead of waiting for them to be rewritten on next write
+    /// ### Returns
+    /// number of accounts still stored in a legacy layout
+    #[payable]
+    pub fn migrate_account_records(&mut self, offset: u64, limit: u64) -> u64 {
+        self.assert_role(Role::Upgrader);
+        let end = min(offset.saturating_add(limit), self.accounts.len());
+        for idx in offset..end {
+            let pubkey = self.accounts.keys_as_vector().get(idx).unwrap();
+            let versioned = self.accounts.get(&pubkey).unwrap();
+            let is_current = versioned.is_current();
+            let mut account: Account = versioned.into();
+            if account.migrate_deposits() > 0 || !is_current {
+                self.set_account(account);
+            }
+        }
+        self.unmigrated_accounts
+    }
+
+    pub fn get_state_version(&self) -> StateVersionView {
+        StateVersionView {
+            version: read_state_version(),
+            migrating_from: self.state_migration.as_ref().map(|m| m.from_version),
+            migrated_accounts: self
+                .state_migration
+                .as_ref()
+                .map(|m| m.migrated_accounts)
+                .unwrap_or_default(),
+            unmigrated_accounts: self.unmigrated_accounts,
+        }
+    }
+}
+
+impl Contract {
+    /// Read the contract state of any known version and convert it to the current one
+    pub(crate) fn migrate_state() -> Self {
+        assert_one_yocto();
+        let from_version = read_state_version();
+        let contract = match from_version {
+            STATE_VERSION_V1 => Self::from_v1(
+                env::state_read::<ContractV1>().expect("Failed to read contract state"),
+            ),
+            CURRENT_STATE_VERSION => {
+                env::state_read::<Self>().expect("Failed to read contract state")
+            }
+            version => env::panic_str(&format!("Unknown state version {}", version)),
+        };
+        write_state_version(CURRENT_STATE_VERSION);
+
+        Event::MigrateState {
+            from_version,
+            to_version: CURRENT_STATE_VERSION,
+        }
+        .emit();
+        contract
+    }
+
+    /// Fields not in `ContractV1` keep the behavior of the legacy contract,
+    /// i.e. no roles, limits, tiers or fixed deposit terms
+    fn from_v1(v1: ContractV1) -> Self {
+        Self {
+            owner_id: v1.owner_id,
+            pending_owner_id: v1.pending_owner_id,
+            roles: UnorderedMap::new(StorageKey::Roles),
+            config_timelock_ms: DEFAULT_CONFIG_TIMELOCK_MS,
+            pending_config_changes: vec![],
+            next_config_change_id: 0,
+            upgrade_delay_ms: DEFAULT_UPGRADE_DELAY_MS,
+            staged_upgrade: None,
+            btc_light_client_id: v1.btc_light_client_id,
+            bip322_verifier_id: v1.bip322_verifier_id,
+            chain_signatures_id: v1.chain_signatures_id,
+            chain_signatures_root_pubkey: v1.chain_signatures_root_pubkey,
+            n_confirmation: v1.n_confirmation,
+            withdrawal_waiting_time_ms: v1.withdrawal_waiting_time_ms,
+            withdrawal_waiting_tiers: vec![],
+            withdrawal_waiting_btc_blocks: None,
+            btc_tip_height: 0,
+            pending_sign_psbt_expiry_ms: V1_PENDING_SIGN_PSBT_EXPIRY_MS,
+            withdrawal_rate_limit: None,
+            min_deposit_satoshi: v1.min_deposit_satoshi,
+            max_deposit_satoshi: None,
+            max_user_deposit_satoshi: None,
+            seq_height_deposit_limits: vec![],
+            max_total_deposit_satoshi: None,
+            // summed up from the accounts by `migrate_accounts`
+            total_deposit: 0,
+            earliest_deposit_block_height: v1.earliest_deposit_block_height,
+            solo_withdrawal_seq_heights: v1.solo_withdrawal_seq_heights,
+            guardian_recovery_seq_heights: vec![],
+            guardian_recovery_waiting_time_ms: DEFAULT_GUARDIAN_RECOVERY_WAITING_TIME_MS,
+            deposit_terms: vec![],
+            confirmed_deposit_txns: v1.confirmed_deposit_txns,
+            accounts: v1.accounts,
+            deposit_mode: DepositMode::Open,
+            deposit_allowlist: UnorderedSet::new(StorageKey::DepositAllowlist),
+            denied_pubkeys: UnorderedSet::new(StorageKey::DeniedPubkeys),
+            denied_outpoints: UnorderedSet::new(StorageKey::DeniedOutpoints),
+            state_migration: Some(StateMigration {
+                from_version: STATE_VERSION_V1,
+                migrated_accounts: 0,
+            }),
+            unmigrated_accounts: 0,
+            emit_legacy_events: true,
+            paused: v1.paused,
+        }
+    }
+}
+
+pub(crate) fn read_state_version() -> u32 {
+    env::storage_read(STATE_VERSION_KEY)
+        .map(|v| u32::try_from_slice(&v).unwrap())
+        .unwrap_or(STATE_VERSION_V1)
+}
+
+pub(crate) fn write_state_version(version: u32) {
+    env::storage_write(STATE_VERSION_KEY, &version.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use account::{DepositStatus, VersionedDeposit};
+    use bitcoin::{
+        absolute::LockTime, transaction::Version, Amount, OutPoint, Psbt, ScriptBuf, Transaction,
+        TxIn, TxOut,
+    };
+    use legacy::{AccountV1, DepositV1, PendingSignPsbtV1};
+    use near_sdk::{collections::UnorderedMap, test_utils::VMContextBuilder, testing_env};
+    use types::{output_id, RedeemVersion, TxId};
+
+    fn user_pubkey() -> PubKey {
+        "02f6b15f899fac9c7dc60dcac795291c70e50c3a2ee1d5070dee0d8020781584e5"
+            .to_string()
+            .into()
+    }
+
+    fn tx_id() -> TxId {
+        "253b73f1450d6be67a16e46d05f62235f1728d737d9540f12b69f84f4cc5b595"
+            .to_string()
+            .into()
+    }
+
+    /// account written by the baseline contract, whose layout is `AccountV1`
+    fn legacy_account() -> AccountV1 {
+        let mut active_deposits = UnorderedMap::new(StorageKey::ActiveDeposits(user_pubkey()));
+        active_deposits.insert(
+            &output_id(&tx_id(), 0),
+            &VersionedDeposit::V1(DepositV1 {
+                user_pubkey: user_pubkey(),
+                status: DepositStatus::Active,
+                redeem_version: RedeemVersion::V1,
+                deposit_tx_id: tx_id(),
+                deposit_vout: 0,
+                value: 1000,
+                sequence: 5,
+                complete_withdrawal_ts: 0,
+                withdrawal_tx_id: None,
+            }),
+        );
+        AccountV1 {
+            pubkey: user_pubkey(),
+            total_deposit: 1000,
+            active_deposits,
+            withdrawn_deposits: UnorderedMap::new(StorageKey::WithdrawnDeposits(user_pubkey())),
+            queue_withdrawal_amount: 400,
+            queue_withdrawal_start_ts: 100,
+            nonce: 1,
+            pending_sign_psbt: None,
+            pending_sign_deposit: 0,
+        }
+    }
+
+    /// legacy account that has signed a PSBT withdrawing 600 out of its deposit of 1000,
+    /// which took the whole queued amount
+    fn legacy_account_with_pending_sign_psbt() -> AccountV1 {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(tx_id().to_string().parse().unwrap(), 0),
+                ..Default::default()
+            }],
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(600),
+                    script_pubkey: ScriptBuf::new(),
+                },
+                TxOut {
+                    value: Amount::from_sat(390),
+                    script_pubkey: ScriptBuf::new(),
+                },
+            ],
+        };
+        let mut account = legacy_account();
+        account.queue_withdrawal_amount = 0;
+        account.queue_withdrawal_start_ts = 0;
+        account.pending_sign_psbt = Some(PendingSignPsbtV1 {
+            psbt: Psbt::from_unsigned_tx(tx).unwrap().into(),
+            reinvest_deposit_vout: Some(1),
+            reinvest_embed_vout: Some(2),
+        });
+        account
+    }
+
+    fn migrate_v1_state() -> Contract {
+        migrate_v1_state_with(legacy_account())
+    }
+
+    fn migrate_v1_state_with(account: AccountV1) -> Contract {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(AccountId::new_unchecked("owner".to_string()))
+            .attached_deposit(1)
+            .build());
+
+        let mut accounts = UnorderedMap::new(StorageKey::Accounts);
+        accounts.insert(&user_pubkey(), &VersionedAccount::V1(account));
+        env::state_write(&ContractV1 {
+            owner_id: AccountId::new_unchecked("owner".to_string()),
+            pending_owner_id: Some(AccountId::new_unchecked("new_owner".to_string())),
+            btc_light_client_id: AccountId::new_unchecked("lc".to_string()),
+            bip322_verifier_id: None,
+            chain_signatures_id: AccountId::new_unchecked("cs".to_string()),
+            chain_signatures_root_pubkey: None,
+            n_confirmation: 6,
+            withdrawal_waiting_time_ms: 0,
+            min_deposit_satoshi: 0,
+            earliest_deposit_block_height: 0,
+            solo_withdrawal_seq_heights: vec![5],
+            confirmed_deposit_txns: LookupSet::new(StorageKey::ConfirmedDeposits),
+            accounts,
+            paused: false,
+        });
+
+        Contract::migrate_state()
+    }
+
+    #[test]
+    fn test_migrate_from_v1() {
+        let mut contract = migrate_v1_state();
+        let version = contract.get_state_version();
+        assert_eq!(version.version, CURRENT_STATE_VERSION);
+        assert_eq!(version.migrating_from, Some(STATE_VERSION_V1));
+        assert_eq!(
+            contract.pending_owner_id,
+            Some(AccountId::new_unchecked("new_owner".to_string()))
+        );
+
+        // legacy accounts are readable as is
+        let account = contract.get_account(&user_pubkey());
+        assert_eq!(account.total_deposit, 1000);
+        assert_eq!(account.queue_withdrawal_amount(), 400);
+        assert_eq!(account.get_active_deposit(&tx_id(), 0).value, 1000);
+
+        assert_eq!(contract.total_deposit, 0);
+        assert_eq!(contract.migrate_accounts(10), 1);
+        assert_eq!(contract.get_state_version().unmigrated_accounts, 1);
+        assert_eq!(contract.total_deposit, 1000);
+
+        contract.finish_state_migration();
+        assert_eq!(contract.get_state_version().migrating_from, None);
+        contract.assert_running();
+    }
+
+    #[test]
+    fn test_migrate_v1_pending_sign_psbt() {
+        let mut contract = migrate_v1_state_with(legacy_account_with_pending_sign_psbt());
+        assert_eq!(contract.migrate_accounts(10), 1);
+        // rewritten right away with the PSBT carried over
+        assert!(contract.accounts.get(&user_pubkey()).unwrap().is_current());
+        assert_eq!(contract.get_state_version().unmigrated_accounts, 0);
+        assert_eq!(contract.total_deposit, 1000);
+
+        let account = contract.get_account(&user_pubkey());
+        assert_eq!(account.pending_sign_psbts_len(), 1);
+        let pending_sign_psbt = account.get_pending_sign_psbt_by_index(0).unwrap();
+        assert_eq!(pending_sign_psbt.withdraw_amount, 610);
+        assert_eq!(pending_sign_psbt.reinvest_deposit_vout, Some(1));
+        assert_eq!(account.queue_withdrawal_amount(), 0);
+    }
+
+    #[test]
+    fn test_read_unmigrated_v1_pending_sign_psbt() {
+        let contract = migrate_v1_state_with(legacy_account_with_pending_sign_psbt());
+
+        // upgraded on read without writing the PSBT
+        let account = contract.get_account(&user_pubkey());
+        assert_eq!(account.pending_sign_psbts_len(), 1);
+        let pending_sign_psbt = account.get_pending_sign_psbt_by_index(0).unwrap();
+        assert_eq!(pending_sign_psbt.withdraw_amount, 610);
+        assert!(account
+            .get_pending_sign_psbt(&pending_sign_psbt.tx_id())
+            .is_some());
+        assert_eq!(account.pending_sign_withdraw_amount(), 610);
+        assert!(!contract.accounts.get(&user_pubkey()).unwrap().is_current());
+        let view = contract.view_account(user_pubkey().to_string());
+        assert_eq!(view.pending_sign_psbts_len, 1);
+    }
+
+    #[test]
+    fn test_withdrawal_confirmed_during_state_migration() {
+        let mut contract = migrate_v1_state();
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(tx_id().to_string().parse().unwrap(), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(990),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+
+        // submitted before the migration, confirmed before the accounts are visited
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(env::current_account_id())
+            .build());
+        assert!(!contract.on_verify_withdrawal_tx(
+            user_pubkey().to_string(),
+            bitcoin::consensus::encode::serialize_hex(&tx),
+            Ok(true),
+        ));
+        let account = contract.get_account(&user_pubkey());
+        assert_eq!(account.get_active_deposit(&tx_id(), 0).value, 1000);
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(AccountId::new_unchecked("owner".to_string()))
+            .attached_deposit(1)
+            .build());
+        contract.migrate_accounts(10);
+        assert_eq!(contract.total_deposit, 1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not all accounts are migrated")]
+    fn test_finish_incomplete_state_migration() {
+        let mut contract = migrate_v1_state();
+        contract.finish_state_migration();
+    }
+
+    #[test]
+    fn test_migrate_legacy_account_records() {
+        let mut contract = crate::tests::test_contract_instance();
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(AccountId::new_unchecked("owner".to_string()))
+            .attached_deposit(1)
+            .build());
+
+        let tx_id = tx_id();
+        contract
+            .accounts
+            .insert(&user_pubkey(), &VersionedAccount::V1(legacy_account()));
+        contract.unmigrated_accounts = 1;
+
+        // upgraded on read
+        let account = contract.get_account(&user_pubkey());
+        assert_eq!(account.total_weighted_deposit, 1000);
+        assert_eq!(account.queue_withdrawal_amount(), 400);
+        assert_eq!(account.withdrawal_tickets()[0].start_ts, 100);
+        assert_eq!(
+            account.get_active_deposit(&tx_id, 0).stake_weight_bps,
+            10_000
+        );
+        assert_eq!(account.unmigrated_deposits(), 1);
+
+        // rewritten eagerly
+        assert_eq!(contract.migrate_account_records(0, 10), 0);
+        assert!(contract.accounts.get(&user_pubkey()).unwrap().is_current());
+        assert_eq!(
+            contract.get_account(&user_pubkey()).unmigrated_deposits(),
+            0
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "State migration in progress")]
+    fn test_not_running_during_migration() {
+        let contract = migrate_v1_state();
+        contract.assert_running();
+    }
+}
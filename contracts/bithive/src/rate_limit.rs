@@ -0,0 +1,150 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::Timestamp;
+
+pub use bithive_types::view::WithdrawalRateLimitView;
+
+/// Sliding window limit on the total amount of BTC cosigned by the contract
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct WithdrawalRateLimit {
+    /// max amount in satoshi that could be cosigned within the window
+    pub max_amount: u64,
+    /// length of the sliding window in ms
+    pub window_ms: u64,
+    /// (timestamp in ms, amount in satoshi) of cosigned withdrawals within the window
+    records: Vec<(Timestamp, u64)>,
+}
+
+impl WithdrawalRateLimit {
+    pub fn new(max_amount: u64, window_ms: u64) -> Self {
+        Self {
+            max_amount,
+            window_ms,
+            records: vec![],
+        }
+    }
+
+    /// total amount cosigned within the window ending at `now_ms`
+    pub fn used_amount(&self, now_ms: Timestamp) -> u64 {
+        self.records
+            .iter()
+            .filter(|(ts, _)| self.in_window(*ts, now_ms))
+            .map(|(_, amount)| amount)
+            .sum()
+    }
+
+    /// whether the amount fits in the window ending at `now_ms`
+    pub fn can_consume(&self, amount: u64, now_ms: Timestamp) -> bool {
+        self.used_amount(now_ms).saturating_add(amount) <= self.max_amount
+    }
+
+    /// record the amount if it fits in the window and return the timestamp of the record,
+    /// return None if the limit would be exceeded
+    pub fn try_consume(&mut self, amount: u64, now_ms: Timestamp) -> Option<Timestamp> {
+        let window_ms = self.window_ms;
+        self.records
+            .retain(|(ts, _)| ts.saturating_add(window_ms) > now_ms);
+        if !self.can_consume(amount, now_ms) {
+            return None;
+        }
+        self.records.push((now_ms, amount));
+        Some(now_ms)
+    }
+
+    /// forget the record of the amount made at `ts` by `try_consume`,
+    /// if the withdrawal ends up not being cosigned
+    pub fn release(&mut self, ts: Timestamp, amount: u64) {
+        if let Some(idx) = self
+            .records
+            .iter()
+            .position(|record| *record == (ts, amount))
+        {
+            self.records.remove(idx);
+        }
+    }
+
+    /// forget all amounts cosigned so far
+    pub fn reset(&mut self) {
+        self.records.clear();
+    }
+
+    pub fn view(&self, now_ms: Timestamp) -> WithdrawalRateLimitView {
+        WithdrawalRateLimitView {
+            max_amount: self.max_amount,
+            window_ms: self.window_ms,
+            used_amount: self.used_amount(now_ms),
+        }
+    }
+
+    fn in_window(&self, ts: Timestamp, now_ms: Timestamp) -> bool {
+        ts.saturating_add(self.window_ms) > now_ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sliding_window() {
+        let mut limit = WithdrawalRateLimit::new(1000, 100);
+
+        assert_eq!(limit.try_consume(600, 0), Some(0));
+        assert_eq!(limit.try_consume(400, 50), Some(50));
+        assert_eq!(limit.try_consume(1, 99), None);
+        assert_eq!(limit.used_amount(99), 1000);
+
+        // the first record slides out of the window
+        assert_eq!(limit.used_amount(100), 400);
+        assert!(limit.try_consume(600, 100).is_some());
+        assert!(limit.try_consume(1, 149).is_none());
+
+        // all records slide out of the window
+        assert_eq!(limit.used_amount(200), 0);
+    }
+
+    #[test]
+    fn test_can_consume() {
+        let mut limit = WithdrawalRateLimit::new(1000, 100);
+
+        assert!(limit.try_consume(600, 0).is_some());
+        assert!(limit.can_consume(400, 50));
+        assert!(!limit.can_consume(401, 50));
+        // checking does not record anything
+        assert_eq!(limit.used_amount(50), 600);
+        assert!(limit.can_consume(1000, 100));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut limit = WithdrawalRateLimit::new(1000, 100);
+
+        assert!(limit.try_consume(1000, 0).is_some());
+        assert!(limit.try_consume(1, 10).is_none());
+
+        limit.reset();
+        assert!(limit.try_consume(1000, 10).is_some());
+    }
+
+    #[test]
+    fn test_release() {
+        let mut limit = WithdrawalRateLimit::new(1000, 100);
+
+        let ts = limit.try_consume(600, 0).unwrap();
+        assert!(limit.try_consume(400, 10).is_some());
+        limit.release(ts, 600);
+        assert_eq!(limit.used_amount(10), 400);
+        assert!(limit.try_consume(600, 20).is_some());
+
+        // unknown records are ignored, even if the amount matches
+        limit.release(10, 600);
+        limit.release(20, 1);
+        assert_eq!(limit.used_amount(20), 1000);
+    }
+
+    #[test]
+    fn test_single_withdrawal_over_limit() {
+        let mut limit = WithdrawalRateLimit::new(1000, 100);
+        assert!(limit.try_consume(1001, 0).is_none());
+        assert_eq!(limit.used_amount(0), 0);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::*;
+use account::{Account, Deposit, ERR_RECOVERY_NOT_REQUESTED};
+use bitcoin::Psbt;
+use events::Event;
+use ext::{ext_chain_signatures, SignRequest, SignatureResponse};
+use near_sdk::{
+    json_types::{U128, U64},
+    near_bindgen, require, Gas, Promise, PromiseError, PromiseOrValue,
+};
+use types::{parse_output_id, OutputId, RedeemVersion};
+use utils::{assert_gas, current_timestamp_ms, get_hash_to_sign};
+use withdraw::{
+    chain_signatures_path, verify_pending_sign_partial_sig, SigType, UserAction,
+    ERR_BAD_DEPOSIT_ID, ERR_INVALID_PSBT_HEX, GAS_CHAIN_SIG_SIGN, GAS_CHAIN_SIG_SIGN_CB,
+    REFUND_THRESHOLD,
+};
+
+const ERR_NO_GUARDIAN: &str = "Deposit has no guardian";
+const ERR_RECOVERY_NOT_READY: &str = "Not ready to recover now";
+const ERR_BAD_VIN: &str = "Invalid vin to sign";
+
+#[near_bindgen]
+impl Contract {
+    /// Submit a recovery request for a deposit with guardian recovery path,
+    /// which is signed by the guardian instead of the user.
+    /// The user could cancel it during the recovery waiting time if the primary key is not lost
+    /// ### Arguments
+    /// * `user_pubkey` - hex encoded user pub key
+    /// * `deposit_id` - ID of the deposit to recover, in the form of `{tx_id}:{vout}`
+    /// * `msg_sig` - hex encoded signature of recovery message that should match the guardian pubkey
+    /// * `sig_type` - signature type
+    pub fn request_recovery(
+        &mut self,
+        user_pubkey: String,
+        deposit_id: String,
+        msg_sig: String,
+        sig_type: SigType,
+    ) -> PromiseOrValue<bool> {
+        self.assert_running();
+
+        let account = self.get_account(&user_pubkey.clone().into());
+        deposit_guardian_pubkey(&account, &deposit_id);
+
+        let action = UserAction::RequestRecovery { deposit_id };
+        assert_gas(Gas(40 * Gas::ONE_TERA.0) + self.user_action_gas(&action)); // 80 Tgas
+        self.verify_user_action(user_pubkey, action, msg_sig, sig_type)
+    }
+
+    /// Cancel a recovery request of a deposit, which is signed by the user
+    /// ### Arguments
+    /// * `user_pubkey` - hex encoded user pub key
+    /// * `deposit_id` - ID of the deposit whose recovery request should be cancelled
+    /// * `msg_sig` - hex encoded signature of cancel message that should match `user_pubkey`
+    /// * `sig_type` - signature type
+    pub fn cancel_recovery(
+        &mut self,
+        user_pubkey: String,
+        deposit_id: String,
+        msg_sig: String,
+        sig_type: SigType,
+    ) -> PromiseOrValue<bool> {
+        self.assert_running();
+
+        let account = self.get_account(&user_pubkey.clone().into());
+        require!(
+            account
+                .get_recovery_request(&deposit_id.clone().into())
+                .is_some(),
+            ERR_RECOVERY_NOT_REQUESTED
+        );
+
+        let action = UserAction::CancelRecovery { deposit_id };
+        assert_gas(Gas(40 * Gas::ONE_TERA.0) + self.user_action_gas(&action)); // 80 Tgas
+        self.verify_user_action(user_pubkey, action, msg_sig, sig_type)
+    }
+
+    /// Sign a PSBT that spends a deposit via guardian recovery path.
+    /// The recovery must have been requested by the guardian and waited long enough.
+    /// Like multisig withdrawals, it is subject to the global withdrawal rate limit
+    /// ### Arguments
+    /// * `psbt_hex` - hex encoded PSBT to sign, must be partially signed by the guardian first
+    /// * `user_pubkey` - user public key
+    /// * `vin_to_sign` - vin to sign, must be an active deposit UTXO with a recovery request
+    #[payable]
+    pub fn sign_recovery(
+        &mut self,
+        psbt_hex: String,
+        user_pubkey: String,
+        vin_to_sign: u64,
+    ) -> PromiseOrValue<Option<SignatureResponse>> {
+        self.assert_running();
+
+        assert_gas(Gas(40 * Gas::ONE_TERA.0) + GAS_CHAIN_SIG_SIGN + GAS_CHAIN_SIG_SIGN_CB); // 300 Tgas
+
+        let psbt = Psbt::deserialize(&hex::decode(psbt_hex).unwrap()).expect(ERR_INVALID_PSBT_HEX);
+        let account = self.get_account(&user_pubkey.clone().into());
+        account.assert_not_frozen();
+
+        let input = psbt
+            .unsigned_tx
+            .input
+            .get(vin_to_sign as usize)
+            .expect(ERR_BAD_VIN);
+        let deposit = account.get_active_deposit(
+            &input.previous_output.txid.to_string().into(),
+            input.previous_output.vout.into(),
+        );
+        let guardian_pubkey = guardian_pubkey(&deposit);
+        let request = account
+            .get_recovery_request(&deposit.id())
+            .expect(ERR_RECOVERY_NOT_REQUESTED);
+        require!(
+            current_timestamp_ms() >= request.start_ts + self.guardian_recovery_waiting_time_ms,
+            ERR_RECOVERY_NOT_READY
+        );
+
+        // make sure the hash to sign is computed from the saved deposit data
+        self.verify_psbt_deposit_inputs(&account, &psbt);
+        // make sure the PSBT is submitted by the guardian
+        verify_pending_sign_partial_sig(&psbt, vin_to_sign, &guardian_pubkey);
+        // stop cosigning if too much has been withdrawn recently
+        if !self.is_within_withdrawal_rate_limit(deposit.value) {
+            self.reject_withdrawal_over_rate_limit(
+                &user_pubkey,
+                deposit.value,
+                env::predecessor_account_id(),
+                env::attached_deposit(),
+            );
+            return PromiseOrValue::Value(None);
+        }
+        let rate_limit_ts = self.record_withdrawal_rate_limit(deposit.value);
+
+        // request signature from chain signatures
+        let (path, key_version) = chain_signatures_path(&deposit.redeem_version);
+        let req = SignRequest {
+            payload: get_hash_to_sign(&psbt, vin_to_sign),
+            path: path.to_string(),
+            key_version,
+        };
+        ext_chain_signatures::ext(self.chain_signatures_id.clone())
+            .with_static_gas(GAS_CHAIN_SIG_SIGN)
+            .with_attached_deposit(env::attached_deposit())
+            .sign(req)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_CHAIN_SIG_SIGN_CB)
+                    .on_sign_recovery(
+                        user_pubkey,
+                        deposit.id().to_string(),
+                        deposit.value.into(),
+                        rate_limit_ts.map(U64),
+                        env::predecessor_account_id(),
+                        env::attached_deposit().into(),
+                    ),
+            )
+            .into()
+    }
+
+    /// `rate_limit_ts` is the timestamp of the record of `amount` in the withdrawal rate limit
+    #[allow(clippy::too_many_arguments)]
+    #[private]
+    pub fn on_sign_recovery(
+        &mut self,
+        user_pubkey: String,
+        deposit_id: String,
+        amount: U64,
+        rate_limit_ts: Option<U64>,
+        caller_id: AccountId,
+        attached_deposit: U128,
+        #[callback_result] result: Result<SignatureResponse, PromiseError>,
+    ) -> Option<SignatureResponse> {
+        if let Ok(sig) = result {
+            Event::SignRecovery {
+                user_pubkey: user_pubkey.clone(),
+                deposit_id: deposit_id.clone(),
+            }
+            .emit();
+
+            Some(sig)
+        } else {
+            // refund
+            if attached_deposit.0 >= REFUND_THRESHOLD {
+                Promise::new(caller_id).transfer(attached_deposit.into());
+            }
+            // nothing is cosigned, so the deposit is not counted against the withdrawal rate limit
+            if let (Some(ts), Some(limit)) = (rate_limit_ts, self.withdrawal_rate_limit.as_mut()) {
+                limit.release(ts.0, amount.0);
+            }
+            None
+        }
+    }
+}
+
+/// Hex encoded guardian pubkey of an active deposit with guardian recovery path
+pub(crate) fn deposit_guardian_pubkey(account: &Account, deposit_id: &str) -> String {
+    let (tx_id, vout) = parse_output_id(deposit_id).expect(ERR_BAD_DEPOSIT_ID);
+    guardian_pubkey(&account.get_active_deposit(&tx_id, vout))
+}
+
+fn guardian_pubkey(deposit: &Deposit) -> String {
+    match &deposit.redeem_version {
+        RedeemVersion::V2 {
+            guardian_pubkey, ..
+        } => guardian_pubkey.clone(),
+        _ => env::panic_str(ERR_NO_GUARDIAN),
+    }
+}
+
+pub(crate) fn request_recovery_message(nonce: u64, deposit_id: &OutputId) -> String {
+    format!("bithive.request_recovery:{}:{}", nonce, deposit_id)
+}
+
+pub(crate) fn cancel_recovery_message(nonce: u64, deposit_id: &OutputId) -> String {
+    format!("bithive.cancel_recovery:{}:{}", nonce, deposit_id)
+}
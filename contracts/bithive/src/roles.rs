@@ -0,0 +1,162 @@
+use crate::*;
+use events::Event;
+use near_sdk::{assert_one_yocto, serde::Serialize};
+use types::Role;
+
+const ERR_MISSING_ROLE: &str = "Missing role";
+const ERR_ROLE_ALREADY_GRANTED: &str = "Role already granted";
+const ERR_ROLE_NOT_GRANTED: &str = "Role not granted";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountRoles {
+    pub account_id: AccountId,
+    pub roles: Vec<Role>,
+}
+
+#[near_bindgen]
+impl Contract {
+    #[payable]
+    pub fn grant_role(&mut self, account_id: AccountId, role: Role) {
+        self.assert_owner();
+        let mut roles = self.roles.get(&account_id).unwrap_or_default();
+        require!(!roles.contains(&role), ERR_ROLE_ALREADY_GRANTED);
+        roles.push(role);
+        self.roles.insert(&account_id, &roles);
+
+        Event::RoleGranted {
+            account_id: account_id.to_string(),
+            role: role.into(),
+        }
+        .emit();
+    }
+
+    #[payable]
+    pub fn revoke_role(&mut self, account_id: AccountId, role: Role) {
+        self.assert_owner();
+        let mut roles = self.roles.get(&account_id).unwrap_or_default();
+        let idx = roles
+            .iter()
+            .position(|r| *r == role)
+            .expect(ERR_ROLE_NOT_GRANTED);
+        roles.remove(idx);
+        if roles.is_empty() {
+            self.roles.remove(&account_id);
+        } else {
+            self.roles.insert(&account_id, &roles);
+        }
+
+        Event::RoleRevoked {
+            account_id: account_id.to_string(),
+            role: role.into(),
+        }
+        .emit();
+    }
+
+    pub fn roles_len(&self) -> u64 {
+        self.roles.len()
+    }
+
+    /// List accounts that have been granted roles, the owner is not included
+    pub fn get_roles(&self, offset: u64, limit: u64) -> Vec<AccountRoles> {
+        self.roles
+            .iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|(account_id, roles)| AccountRoles { account_id, roles })
+            .collect()
+    }
+
+    /// The owner has all roles
+    pub fn has_role(&self, account_id: AccountId, role: Role) -> bool {
+        account_id == self.owner_id
+            || self
+                .roles
+                .get(&account_id)
+                .is_some_and(|roles| roles.contains(&role))
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_role(&self, role: Role) {
+        assert_one_yocto();
+        require!(
+            self.has_role(env::predecessor_account_id(), role),
+            ERR_MISSING_ROLE
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_contract_instance;
+    use near_sdk::{test_utils::VMContextBuilder, testing_env};
+
+    fn set_caller(account_id: &str) {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(AccountId::new_unchecked(account_id.to_string()))
+            .attached_deposit(1)
+            .build());
+    }
+
+    #[test]
+    fn test_grant_and_revoke_role() {
+        let mut contract = test_contract_instance();
+        let pauser = AccountId::new_unchecked("pauser".to_string());
+
+        set_caller("owner");
+        contract.assert_role(Role::Upgrader);
+        contract.grant_role(pauser.clone(), Role::Pauser);
+        assert!(contract.has_role(pauser.clone(), Role::Pauser));
+        assert!(!contract.has_role(pauser.clone(), Role::Upgrader));
+
+        set_caller("pauser");
+        contract.assert_role(Role::Pauser);
+
+        set_caller("owner");
+        contract.revoke_role(pauser.clone(), Role::Pauser);
+        assert!(contract.get_roles(0, 10).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing role")]
+    fn test_missing_role() {
+        let mut contract = test_contract_instance();
+        set_caller("owner");
+        contract.grant_role(AccountId::new_unchecked("pauser".to_string()), Role::Pauser);
+
+        set_caller("pauser");
+        contract.assert_role(Role::Upgrader);
+    }
+
+    #[test]
+    fn test_get_roles_paginated() {
+        let mut contract = test_contract_instance();
+        set_caller("owner");
+        contract.grant_role(AccountId::new_unchecked("pauser".to_string()), Role::Pauser);
+        contract.grant_role(
+            AccountId::new_unchecked("manager".to_string()),
+            Role::ConfigManager,
+        );
+
+        assert_eq!(contract.roles_len(), 2);
+        let roles = contract.get_roles(1, 10);
+        assert_eq!(roles.len(), 1);
+        assert_eq!(roles[0].account_id.as_str(), "manager");
+    }
+
+    #[test]
+    #[should_panic(expected = "Not owner")]
+    fn test_role_cannot_propose_owner() {
+        let mut contract = test_contract_instance();
+        set_caller("owner");
+        contract.grant_role(
+            AccountId::new_unchecked("manager".to_string()),
+            Role::ConfigManager,
+        );
+
+        set_caller("manager");
+        contract.propose_change_owner(AccountId::new_unchecked("manager".to_string()));
+    }
+}
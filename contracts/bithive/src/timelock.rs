@@ -0,0 +1,282 @@
+use crate::*;
+use admin::assert_monotonic_waiting_tiers;
+use events::Event;
+use near_sdk::{
+    serde::{Deserialize, Serialize},
+    Timestamp,
+};
+use utils::current_timestamp_ms;
+
+const ERR_CONFIG_CHANGE_NOT_FOUND: &str = "Config change not found";
+const ERR_CONFIG_CHANGE_NOT_READY: &str = "Config change not ready";
+
+/// Sensitive config changes that could only be executed after the config timelock
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ConfigChange {
+    BtcLightClientId(AccountId),
+    Bip322VerifierId(Option<AccountId>),
+    ChainSignaturesId(AccountId),
+    NConfirmation(u64),
+    WithdrawalWaitingTime(u64),
+    WithdrawalWaitingTiers(Vec<WithdrawalWaitingTier>),
+    /// set to None to measure withdrawal waiting time in ms again
+    WithdrawalWaitingBtcBlocks(Option<u64>),
+    GuardianRecoveryWaitingTime(u64),
+    ConfigTimelock(u64),
+    UpgradeDelay(u64),
+}
+
+impl ConfigChange {
+    /// Role that could propose, execute and cancel the change, None means owner only
+    fn role(&self) -> Option<Role> {
+        match self {
+            ConfigChange::NConfirmation(_)
+            | ConfigChange::WithdrawalWaitingTime(_)
+            | ConfigChange::WithdrawalWaitingTiers(_)
+            | ConfigChange::WithdrawalWaitingBtcBlocks(_)
+            | ConfigChange::GuardianRecoveryWaitingTime(_) => Some(Role::ConfigManager),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConfigChange> for bithive_types::config::ConfigChange {
+    fn from(value: ConfigChange) -> Self {
+        match value {
+            ConfigChange::BtcLightClientId(id) => Self::BtcLightClientId(id.into()),
+            ConfigChange::Bip322VerifierId(id) => Self::Bip322VerifierId(id.map(Into::into)),
+            ConfigChange::ChainSignaturesId(id) => Self::ChainSignaturesId(id.into()),
+            ConfigChange::NConfirmation(n) => Self::NConfirmation(n),
+            ConfigChange::WithdrawalWaitingTime(ms) => Self::WithdrawalWaitingTime(ms),
+            ConfigChange::WithdrawalWaitingTiers(tiers) => {
+                Self::WithdrawalWaitingTiers(tiers.into_iter().map(Into::into).collect())
+            }
+            ConfigChange::WithdrawalWaitingBtcBlocks(blocks) => {
+                Self::WithdrawalWaitingBtcBlocks(blocks)
+            }
+            ConfigChange::GuardianRecoveryWaitingTime(ms) => Self::GuardianRecoveryWaitingTime(ms),
+            ConfigChange::ConfigTimelock(ms) => Self::ConfigTimelock(ms),
+            ConfigChange::UpgradeDelay(ms) => Self::UpgradeDelay(ms),
+        }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingConfigChange {
+    pub id: u64,
+    pub change: ConfigChange,
+    /// timestamp after which the change could be executed in ms
+    pub eta_ms: Timestamp,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Propose a sensitive config change, which could be executed after `config_timelock_ms`
+    /// ### Arguments
+    /// * `change` - config change to make
+    /// ### Returns
+    /// ID of the proposed change
+    #[payable]
+    pub fn propose_config_change(&mut self, change: ConfigChange) -> u64 {
+        self.assert_config_change_role(&change);
+        self.validate_config_change(&change);
+
+        let id = self.next_config_change_id;
+        self.next_config_change_id += 1;
+        let eta_ms = current_timestamp_ms() + self.config_timelock_ms;
+
+        Event::ProposeConfigChange {
+            id: id.into(),
+            change: change.clone().into(),
+            eta_ms: eta_ms.into(),
+        }
+        .emit();
+
+        self.pending_config_changes
+            .push(PendingConfigChange { id, change, eta_ms });
+        id
+    }
+
+    /// Execute a proposed config change whose timelock has passed
+    #[payable]
+    pub fn execute_config_change(&mut self, id: u64) {
+        let pending = self.take_pending_config_change(id);
+        require!(
+            current_timestamp_ms() >= pending.eta_ms,
+            ERR_CONFIG_CHANGE_NOT_READY
+        );
+        // other configs might have changed since the proposal
+        self.validate_config_change(&pending.change);
+
+        match pending.change.clone() {
+            ConfigChange::BtcLightClientId(id) => self.btc_light_client_id = id,
+            ConfigChange::Bip322VerifierId(id) => self.bip322_verifier_id = id,
+            ConfigChange::ChainSignaturesId(id) => self.chain_signatures_id = id,
+            ConfigChange::NConfirmation(n) => self.n_confirmation = n,
+            ConfigChange::WithdrawalWaitingTime(ms) => self.withdrawal_waiting_time_ms = ms,
+            ConfigChange::WithdrawalWaitingTiers(tiers) => self.withdrawal_waiting_tiers = tiers,
+            ConfigChange::WithdrawalWaitingBtcBlocks(blocks) => {
+                self.withdrawal_waiting_btc_blocks = blocks
+            }
+            ConfigChange::GuardianRecoveryWaitingTime(ms) => {
+                self.guardian_recovery_waiting_time_ms = ms
+            }
+            ConfigChange::ConfigTimelock(ms) => self.config_timelock_ms = ms,
+            ConfigChange::UpgradeDelay(ms) => self.upgrade_delay_ms = ms,
+        }
+        self.validate_config();
+
+        Event::ExecuteConfigChange {
+            id: id.into(),
+            change: pending.change.clone().into(),
+        }
+        .emit();
+    }
+
+    #[payable]
+    pub fn cancel_config_change(&mut self, id: u64) {
+        let pending = self.take_pending_config_change(id);
+
+        Event::CancelConfigChange {
+            id: id.into(),
+            change: pending.change.clone().into(),
+        }
+        .emit();
+    }
+
+    pub fn list_pending_config_changes(&self) -> Vec<PendingConfigChange> {
+        self.pending_config_changes.clone()
+    }
+}
+
+impl Contract {
+    fn assert_config_change_role(&self, change: &ConfigChange) {
+        match change.role() {
+            Some(role) => self.assert_role(role),
+            None => self.assert_owner(),
+        }
+    }
+
+    fn validate_config_change(&self, change: &ConfigChange) {
+        match change {
+            ConfigChange::NConfirmation(n) => {
+                require!(*n > 0, "n_confirmation must be greater than 0");
+            }
+            ConfigChange::WithdrawalWaitingTime(ms) => {
+                require!(*ms > 0, "withdrawal_waiting_time_ms must be greater than 0");
+                assert_monotonic_waiting_tiers(*ms, &self.withdrawal_waiting_tiers);
+            }
+            ConfigChange::WithdrawalWaitingTiers(tiers) => {
+                assert_monotonic_waiting_tiers(self.withdrawal_waiting_time_ms, tiers);
+            }
+            ConfigChange::WithdrawalWaitingBtcBlocks(blocks) => {
+                require!(
+                    *blocks != Some(0),
+                    "withdrawal_waiting_btc_blocks must be greater than 0"
+                );
+            }
+            ConfigChange::GuardianRecoveryWaitingTime(ms) => {
+                require!(
+                    *ms > 0,
+                    "guardian_recovery_waiting_time_ms must be greater than 0"
+                );
+            }
+            // a zero delay would let the change itself bypass the timelock
+            ConfigChange::ConfigTimelock(ms) => {
+                require!(*ms > 0, "config_timelock_ms must be greater than 0");
+            }
+            ConfigChange::UpgradeDelay(ms) => {
+                require!(*ms > 0, "upgrade_delay_ms must be greater than 0");
+            }
+            _ => {}
+        }
+    }
+
+    /// Remove a pending config change after checking the caller's role
+    fn take_pending_config_change(&mut self, id: u64) -> PendingConfigChange {
+        let idx = self
+            .pending_config_changes
+            .iter()
+            .position(|c| c.id == id)
+            .expect(ERR_CONFIG_CHANGE_NOT_FOUND);
+        self.assert_config_change_role(&self.pending_config_changes[idx].change);
+        self.pending_config_changes.remove(idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::test_contract_instance;
+    use near_sdk::{test_utils::VMContextBuilder, testing_env};
+
+    fn set_context(caller: &str, timestamp_ms: u64) {
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(AccountId::new_unchecked(caller.to_string()))
+            .attached_deposit(1)
+            .block_timestamp(timestamp_ms * 1_000_000)
+            .build());
+    }
+
+    #[test]
+    fn test_execute_config_change_after_timelock() {
+        let mut contract = test_contract_instance();
+        set_context("owner", 0);
+        let id = contract.propose_config_change(ConfigChange::NConfirmation(3));
+        assert_eq!(
+            contract.list_pending_config_changes()[0].eta_ms,
+            DEFAULT_CONFIG_TIMELOCK_MS
+        );
+
+        set_context("owner", DEFAULT_CONFIG_TIMELOCK_MS);
+        contract.execute_config_change(id);
+        assert_eq!(contract.n_confirmation, 3);
+        assert!(contract.list_pending_config_changes().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Config change not ready")]
+    fn test_execute_config_change_before_timelock() {
+        let mut contract = test_contract_instance();
+        set_context("owner", 0);
+        let id = contract.propose_config_change(ConfigChange::NConfirmation(3));
+
+        set_context("owner", DEFAULT_CONFIG_TIMELOCK_MS - 1);
+        contract.execute_config_change(id);
+    }
+
+    #[test]
+    fn test_withdrawal_waiting_config_change() {
+        let mut contract = test_contract_instance();
+        set_context("owner", 0);
+        let id = contract.propose_config_change(ConfigChange::WithdrawalWaitingBtcBlocks(Some(6)));
+        assert_eq!(contract.withdrawal_waiting_btc_blocks, None);
+
+        set_context("owner", DEFAULT_CONFIG_TIMELOCK_MS);
+        contract.execute_config_change(id);
+        assert_eq!(contract.withdrawal_waiting_btc_blocks, Some(6));
+    }
+
+    #[test]
+    #[should_panic(expected = "config_timelock_ms must be greater than 0")]
+    fn test_zero_config_timelock() {
+        let mut contract = test_contract_instance();
+        set_context("owner", 0);
+        contract.propose_config_change(ConfigChange::ConfigTimelock(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Not owner")]
+    fn test_config_manager_cannot_change_contract_ids() {
+        let mut contract = test_contract_instance();
+        let manager = AccountId::new_unchecked("manager".to_string());
+        set_context("owner", 0);
+        contract.grant_role(manager.clone(), Role::ConfigManager);
+
+        set_context("manager", 0);
+        contract.propose_config_change(ConfigChange::NConfirmation(3));
+        contract.propose_config_change(ConfigChange::ChainSignaturesId(manager));
+    }
+}
@@ -2,15 +2,61 @@ use std::fmt::Display;
 
 use near_sdk::{
     borsh::{self, BorshDeserialize, BorshSerialize},
-    AccountId, BorshStorageKey,
+    env,
+    json_types::U128,
+    AccountId, BorshStorageKey, Timestamp,
 };
 
+use crate::utils::current_timestamp_ms;
+
 #[derive(BorshSerialize, BorshStorageKey)]
 pub enum StorageKey {
     ConfirmedDeposits,
     Accounts,
     ActiveDeposits(PubKey),
     WithdrawnDeposits(PubKey),
+    PendingSignPsbts(PubKey),
+    DepositAllowlist,
+    DeniedPubkeys,
+    DeniedOutpoints,
+    Roles,
+}
+
+/// Roles granted by the owner to call admin methods, the owner could call all of them
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    serde::Serialize,
+    serde::Deserialize,
+    Clone,
+    Copy,
+    PartialEq,
+    Debug,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Role {
+    /// could pause and unpause the contract
+    Pauser,
+    /// could update contract configs and deposit allowlist
+    ConfigManager,
+    /// could manage the denylist and freeze accounts
+    Compliance,
+    /// could upgrade the contract code
+    Upgrader,
+}
+
+/// Who could make deposits
+#[derive(
+    BorshSerialize, BorshDeserialize, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum DepositMode {
+    /// anyone could deposit
+    Open,
+    /// only users in the deposit allowlist could deposit
+    Allowlist,
+    /// no one could deposit
+    Closed,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -22,11 +68,41 @@ pub struct InitArgs {
     pub chain_signatures_id: AccountId,
     pub n_confirmation: u64,
     pub withdrawal_waiting_time_ms: u64,
+    pub pending_sign_psbt_expiry_ms: u64,
     pub min_deposit_satoshi: u64,
     pub earliest_deposit_block_height: u32,
     pub solo_withdrawal_seq_heights: Vec<u16>,
 }
 
+/// Withdrawals of at least `min_amount` need to wait for `waiting_time_ms`
+#[derive(BorshSerialize, BorshDeserialize, serde::Serialize, serde::Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawalWaitingTier {
+    /// minimum withdrawal amount in satoshi
+    pub min_amount: u64,
+    pub waiting_time_ms: u64,
+}
+
+/// Amount limits of deposits in satoshi
+#[derive(BorshSerialize, BorshDeserialize, serde::Serialize, serde::Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DepositLimits {
+    /// minimum amount of a single deposit
+    pub min_deposit_satoshi: u64,
+    /// maximum amount of a single deposit
+    pub max_deposit_satoshi: Option<u64>,
+    /// maximum total amount of active deposits of a single user
+    pub max_user_deposit_satoshi: Option<u64>,
+}
+
+/// Deposit limits that override the default ones for deposits with the sequence height
+#[derive(BorshSerialize, BorshDeserialize, serde::Serialize, serde::Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SequenceHeightDepositLimits {
+    pub sequence_height: u16,
+    pub limits: DepositLimits,
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct SubmitDepositTxArgs {
@@ -54,6 +130,22 @@ pub enum DepositEmbedMsg {
         user_pubkey: [u8; 33],
         sequence_height: u16,
     },
+    /// deposit with a guardian recovery path
+    /// deposit vout is shortened so that the message fits in a standard OP_RETURN output
+    V2 {
+        deposit_vout: u16,
+        user_pubkey: [u8; 33],
+        sequence_height: u16,
+        guardian_pubkey: [u8; 33],
+        recovery_sequence_height: u16,
+    },
+    /// fixed-term deposit that could only be withdrawn solo after the maturity height
+    V3 {
+        deposit_vout: u64,
+        user_pubkey: [u8; 33],
+        sequence_height: u16,
+        maturity_height: u32,
+    },
 }
 
 impl DepositEmbedMsg {
@@ -81,6 +173,28 @@ impl DepositEmbedMsg {
 #[serde(crate = "near_sdk::serde")]
 pub enum RedeemVersion {
     V1,
+    /// guardian key could spend with bithive key after a long timelock
+    V2 {
+        guardian_pubkey: String,
+        recovery_sequence: u32,
+    },
+    /// fixed-term deposit locked until an absolute BTC block height
+    V3 {
+        maturity_height: u32,
+    },
+}
+
+/// Names of the redeem script versions accepted for new deposits, as serialized in JSON
+pub const SUPPORTED_REDEEM_VERSIONS: &[&str] = &["V1", "V2", "V3"];
+
+/// Fixed deposit term configured by the owner
+#[derive(BorshSerialize, BorshDeserialize, serde::Serialize, serde::Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DepositTerm {
+    /// BTC block height after which the deposit could be withdrawn
+    pub maturity_height: u32,
+    /// stake weight multiplier of deposits with this term in basis points
+    pub stake_weight_bps: u32,
 }
 
 /// public key (either compressed or not) in lower case
@@ -94,6 +208,11 @@ pub type OutputId = LowercaseString;
 pub fn output_id(tx_id: &TxId, vout: u64) -> LowercaseString {
     format!("{}:{}", tx_id, vout).into()
 }
+/// split an output ID into tx ID and vout
+pub fn parse_output_id(id: &str) -> Option<(TxId, u64)> {
+    let (tx_id, vout) = id.split_once(':')?;
+    Some((tx_id.to_string().into(), vout.parse().ok()?))
+}
 
 /// helper type which enforces lowercase strings
 #[derive(serde::Serialize, PartialEq, Debug)]
@@ -123,6 +242,12 @@ impl Display for LowercaseString {
     }
 }
 
+impl<'de> serde::Deserialize<'de> for LowercaseString {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <String as serde::Deserialize>::deserialize(deserializer).map(Into::into)
+    }
+}
+
 impl Clone for LowercaseString {
     fn clone(&self) -> Self {
         Self::new(&self.0)
@@ -145,8 +270,91 @@ impl borsh::BorshSerialize for LowercaseString {
 #[serde(crate = "near_sdk::serde")]
 pub struct PendingSignPsbt {
     pub psbt: BorshPsbt,
+    /// amount taken from the queued withdrawal amount by this PSBT
+    pub withdraw_amount: u64,
     pub reinvest_deposit_vout: Option<u64>,
     pub reinvest_embed_vout: Option<u64>,
+    /// timestamp when the PSBT was first requested to be signed in ms
+    pub sign_start_ts: Timestamp,
+    /// storage deposit attached when the PSBT was first requested to be signed
+    pub storage_deposit: U128,
+    /// account that attached the storage deposit, which gets refunded if the PSBT is discarded
+    pub storage_payer: AccountId,
+    /// whether bithive has signed any version of the txn, which could be broadcasted
+    /// even if it's replaced later
+    pub bithive_signed: bool,
+    /// timestamps of the records of `withdraw_amount` against the daily limit of the user and
+    /// the global withdrawal rate limit, which are released if nothing is cosigned
+    pub daily_withdrawal_ts: Option<Timestamp>,
+    pub rate_limit_ts: Option<Timestamp>,
+}
+
+impl PendingSignPsbt {
+    /// Create a pending sign PSBT, only partial signatures of the user are kept
+    pub fn new(
+        psbt: bitcoin::Psbt,
+        user_pubkey: &bitcoin::PublicKey,
+        withdraw_amount: u64,
+        reinvest_deposit_vout: Option<u64>,
+        reinvest_embed_vout: Option<u64>,
+    ) -> Self {
+        let mut pending_sign_psbt = PendingSignPsbt {
+            psbt: psbt.clone().into(),
+            withdraw_amount,
+            reinvest_deposit_vout,
+            reinvest_embed_vout,
+            sign_start_ts: current_timestamp_ms(),
+            storage_deposit: 0.into(),
+            storage_payer: env::predecessor_account_id(),
+            bithive_signed: false,
+            daily_withdrawal_ts: None,
+            rate_limit_ts: None,
+        };
+        for input in pending_sign_psbt.psbt.0.inputs.iter_mut() {
+            input.partial_sigs.clear();
+        }
+        pending_sign_psbt.update_psbt(&psbt, user_pubkey);
+        pending_sign_psbt
+    }
+
+    pub fn tx_id(&self) -> TxId {
+        self.psbt.0.unsigned_tx.compute_txid().to_string().into()
+    }
+
+    /// Whether the PSBT spends any of the given inputs
+    pub fn spends_any(&self, inputs: &[bitcoin::TxIn]) -> bool {
+        self.psbt.0.unsigned_tx.input.iter().any(|input| {
+            inputs
+                .iter()
+                .any(|other| other.previous_output == input.previous_output)
+        })
+    }
+
+    /// Update with the requested PSBT, which should be the same txn or an RBF of the pending one.
+    /// Only partial signatures of the user are taken from the request, and
+    /// signatures collected so far are dropped if the txn is replaced
+    pub fn update_psbt(&mut self, request_psbt: &bitcoin::Psbt, user_pubkey: &bitcoin::PublicKey) {
+        if self.psbt.0.unsigned_tx != request_psbt.unsigned_tx {
+            let mut psbt = request_psbt.clone();
+            for input in psbt.inputs.iter_mut() {
+                input.partial_sigs.clear();
+            }
+            self.psbt = psbt.into();
+        }
+
+        for (input, request_input) in self.psbt.0.inputs.iter_mut().zip(&request_psbt.inputs) {
+            if let Some(sig) = request_input.partial_sigs.get(user_pubkey) {
+                input.partial_sigs.insert(*user_pubkey, *sig);
+            }
+            // inputs that are not deposits should be finalized by the user
+            if request_input.final_script_sig.is_some() {
+                input.final_script_sig = request_input.final_script_sig.clone();
+            }
+            if request_input.final_script_witness.is_some() {
+                input.final_script_witness = request_input.final_script_witness.clone();
+            }
+        }
+    }
 }
 
 /// helper type to wrap PSBT so that it can be serialized by Borsh
@@ -173,6 +381,18 @@ impl BorshDeserialize for BorshPsbt {
     }
 }
 
+impl AsRef<bitcoin::Psbt> for BorshPsbt {
+    fn as_ref(&self) -> &bitcoin::Psbt {
+        &self.0
+    }
+}
+
+impl AsMut<bitcoin::Psbt> for BorshPsbt {
+    fn as_mut(&mut self) -> &mut bitcoin::Psbt {
+        &mut self.0
+    }
+}
+
 impl From<BorshPsbt> for bitcoin::Psbt {
     fn from(psbt: BorshPsbt) -> Self {
         psbt.0
@@ -185,6 +405,82 @@ impl From<bitcoin::Psbt> for BorshPsbt {
     }
 }
 
+// conversions to the JSON types shared with off-chain consumers
+
+impl From<Role> for bithive_types::types::Role {
+    fn from(value: Role) -> Self {
+        match value {
+            Role::Pauser => Self::Pauser,
+            Role::ConfigManager => Self::ConfigManager,
+            Role::Compliance => Self::Compliance,
+            Role::Upgrader => Self::Upgrader,
+        }
+    }
+}
+
+impl From<DepositMode> for bithive_types::types::DepositMode {
+    fn from(value: DepositMode) -> Self {
+        match value {
+            DepositMode::Open => Self::Open,
+            DepositMode::Allowlist => Self::Allowlist,
+            DepositMode::Closed => Self::Closed,
+        }
+    }
+}
+
+impl From<WithdrawalWaitingTier> for bithive_types::types::WithdrawalWaitingTier {
+    fn from(value: WithdrawalWaitingTier) -> Self {
+        Self {
+            min_amount: value.min_amount,
+            waiting_time_ms: value.waiting_time_ms,
+        }
+    }
+}
+
+impl From<DepositLimits> for bithive_types::types::DepositLimits {
+    fn from(value: DepositLimits) -> Self {
+        Self {
+            min_deposit_satoshi: value.min_deposit_satoshi,
+            max_deposit_satoshi: value.max_deposit_satoshi,
+            max_user_deposit_satoshi: value.max_user_deposit_satoshi,
+        }
+    }
+}
+
+impl From<SequenceHeightDepositLimits> for bithive_types::types::SequenceHeightDepositLimits {
+    fn from(value: SequenceHeightDepositLimits) -> Self {
+        Self {
+            sequence_height: value.sequence_height,
+            limits: value.limits.into(),
+        }
+    }
+}
+
+impl From<RedeemVersion> for bithive_types::types::RedeemVersion {
+    fn from(value: RedeemVersion) -> Self {
+        match value {
+            RedeemVersion::V1 => Self::V1,
+            RedeemVersion::V2 {
+                guardian_pubkey,
+                recovery_sequence,
+            } => Self::V2 {
+                guardian_pubkey,
+                recovery_sequence,
+            },
+            RedeemVersion::V3 { maturity_height } => Self::V3 { maturity_height },
+        }
+    }
+}
+
+impl From<DepositTerm> for bithive_types::types::DepositTerm {
+    fn from(value: DepositTerm) -> Self {
+        Self {
+            maturity_height: value.maturity_height,
+            stake_weight_bps: value.stake_weight_bps,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -227,4 +523,43 @@ mod tests {
         let decoded = DepositEmbedMsg::decode_hex(&hex_encoded).unwrap();
         assert_eq!(msg, decoded);
     }
+
+    #[test]
+    fn test_embed_msg_v2_encode_decode() {
+        let pubkey = test_pubkey();
+        let msg = DepositEmbedMsg::V2 {
+            deposit_vout: 1,
+            user_pubkey: pubkey.clone().try_into().unwrap(),
+            sequence_height: 1,
+            guardian_pubkey: pubkey.try_into().unwrap(),
+            recovery_sequence_height: 1000,
+        };
+        let encoded = msg.encode();
+        // standard OP_RETURN output carries at most 80 bytes
+        assert_eq!(encoded.len(), 80);
+        let decoded = DepositEmbedMsg::decode_hex(&hex::encode(encoded)).unwrap();
+        assert_eq!(msg, decoded);
+    }
+
+    #[test]
+    fn test_json_types() {
+        // stored types are returned as is by some views, they should decode as the shared JSON types
+        let mut deposit = crate::account::Deposit::new(
+            hex::encode(test_pubkey()).into(),
+            RedeemVersion::V2 {
+                guardian_pubkey: "02aa".to_string(),
+                recovery_sequence: 100,
+            },
+            "AB".to_string().into(),
+            1,
+            1000,
+            5,
+        );
+        deposit.stake_weight_bps = 12_000;
+        let json = serde_json::to_value(&deposit).unwrap();
+        let decoded: bithive_types::account::Deposit =
+            serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(decoded.deposit_tx_id, "ab");
+        assert_eq!(serde_json::to_value(decoded).unwrap(), json);
+    }
 }
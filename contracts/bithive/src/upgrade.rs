@@ -1,22 +1,95 @@
-use crate::{view::ContractSummary, Contract, ContractExt};
+use crate::{
+    events::Event, types::Role, utils::current_timestamp_ms, view::ContractSummary, Contract,
+    ContractExt,
+};
 use near_sdk::{
-    assert_one_yocto, env, near_bindgen, Gas, GasWeight, Promise, PromiseOrValue, ONE_YOCTO,
+    borsh::{self, BorshDeserialize, BorshSerialize},
+    env, log, near_bindgen, require,
+    serde::Serialize,
+    Gas, GasWeight, Promise, PromiseError, Timestamp, ONE_YOCTO,
 };
 
+const GAS_DEPLOY_UPGRADE_CB: Gas = Gas(10 * Gas::ONE_TERA.0);
+
+/// staged code is kept out of the contract state so that it's not loaded on every call
+const STAGED_CODE_KEY: &[u8] = b"__STAGED_CODE__";
+
+const ERR_NO_STAGED_UPGRADE: &str = "No staged upgrade";
+const ERR_STAGED_UPGRADE_NOT_READY: &str = "Staged upgrade not ready";
+const ERR_CODE_HASH_MISMATCH: &str = "Code hash mismatch";
+const ERR_DEPLOY_UPGRADE_FAILED: &str = "Failed to deploy staged upgrade";
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StagedUpgrade {
+    /// hex encoded sha256 hash of the staged code
+    pub code_hash: String,
+    /// timestamp after which the staged code could be deployed in ms
+    pub eta_ms: Timestamp,
+}
+
 #[near_bindgen]
 impl Contract {
     #[init(ignore_state)]
     #[payable]
     #[private]
     pub fn migrate() -> Self {
-        assert_one_yocto();
-        env::state_read::<Self>().expect("Failed to read contract state")
+        Self::migrate_state()
     }
 
+    /// Stage the new contract code passed as raw input, which could be deployed after `upgrade_delay_ms`.
+    /// A previously staged upgrade is replaced
     #[payable]
-    pub fn upgrade(&mut self) -> PromiseOrValue<ContractSummary> {
-        self.assert_owner();
+    pub fn stage_upgrade(&mut self) -> StagedUpgrade {
+        self.assert_role(Role::Upgrader);
         let code = env::input().expect("Code not found");
+        let staged_upgrade = StagedUpgrade {
+            code_hash: hex::encode(env::sha256_array(&code)),
+            eta_ms: current_timestamp_ms() + self.upgrade_delay_ms,
+        };
+        env::storage_write(STAGED_CODE_KEY, &code);
+        self.staged_upgrade = Some(staged_upgrade.clone());
+
+        Event::StageUpgrade {
+            code_hash: staged_upgrade.code_hash.clone(),
+            eta_ms: staged_upgrade.eta_ms.into(),
+        }
+        .emit();
+        staged_upgrade
+    }
+
+    #[payable]
+    pub fn cancel_staged_upgrade(&mut self) {
+        self.assert_role(Role::Upgrader);
+        let staged_upgrade = self.staged_upgrade.take().expect(ERR_NO_STAGED_UPGRADE);
+        env::storage_remove(STAGED_CODE_KEY);
+
+        Event::CancelStagedUpgrade {
+            code_hash: staged_upgrade.code_hash.clone(),
+        }
+        .emit();
+    }
+
+    /// Deploy the staged code after the upgrade delay.
+    /// The staged code is kept until the deployment and migration succeed, so it could be retried
+    /// ### Arguments
+    /// * `expected_code_hash` - if set, must match the hex encoded sha256 hash of the staged code
+    #[payable]
+    pub fn deploy_staged_upgrade(&mut self, expected_code_hash: Option<String>) -> Promise {
+        self.assert_role(Role::Upgrader);
+        let staged_upgrade = self.staged_upgrade.clone().expect(ERR_NO_STAGED_UPGRADE);
+        require!(
+            current_timestamp_ms() >= staged_upgrade.eta_ms,
+            ERR_STAGED_UPGRADE_NOT_READY
+        );
+        if let Some(expected_code_hash) = expected_code_hash {
+            require!(
+                expected_code_hash.to_lowercase() == staged_upgrade.code_hash,
+                ERR_CODE_HASH_MISMATCH
+            );
+        }
+        let code = env::storage_read(STAGED_CODE_KEY).expect(ERR_NO_STAGED_UPGRADE);
+
         Promise::new(env::current_account_id())
             .deploy_contract(code)
             .function_call_weight("migrate".into(), vec![], ONE_YOCTO, Gas(0), GasWeight(1))
@@ -27,6 +100,118 @@ impl Contract {
                 Gas(10 * Gas::ONE_TERA.0),
                 GasWeight(0),
             )
-            .into()
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_DEPLOY_UPGRADE_CB)
+                    .on_deploy_staged_upgrade(staged_upgrade.code_hash),
+            )
+    }
+
+    /// Runs on the newly deployed code, the staged code is only removed
+    /// and the upgrade event emitted once the upgrade succeeds
+    #[private]
+    pub fn on_deploy_staged_upgrade(
+        &mut self,
+        code_hash: String,
+        #[callback_result] result: Result<ContractSummary, PromiseError>,
+    ) -> Option<ContractSummary> {
+        let summary = match result {
+            Ok(summary) => summary,
+            Err(_) => {
+                log!(ERR_DEPLOY_UPGRADE_FAILED);
+                return None;
+            }
+        };
+        // the staged upgrade might have been replaced in the meantime
+        if self
+            .staged_upgrade
+            .as_ref()
+            .is_some_and(|staged_upgrade| staged_upgrade.code_hash == code_hash)
+        {
+            self.staged_upgrade = None;
+            env::storage_remove(STAGED_CODE_KEY);
+        }
+
+        Event::DeployUpgrade { code_hash }.emit();
+        Some(summary)
+    }
+
+    pub fn get_staged_upgrade(&self) -> Option<StagedUpgrade> {
+        self.staged_upgrade.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::test_contract_instance;
+    use near_sdk::{
+        test_utils::{get_logs, VMContextBuilder},
+        testing_env, AccountId, PromiseError,
+    };
+
+    fn set_context(input: Vec<u8>, timestamp_ms: u64) {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .predecessor_account_id(AccountId::new_unchecked("owner".to_string()))
+            .attached_deposit(1)
+            .block_timestamp(timestamp_ms * 1_000_000);
+        builder.context.input = input;
+        testing_env!(builder.build());
+    }
+
+    #[test]
+    fn test_stage_upgrade() {
+        let mut contract = test_contract_instance();
+        contract.upgrade_delay_ms = 1000;
+        set_context(b"code".to_vec(), 0);
+        contract.stage_upgrade();
+
+        let staged_upgrade = contract.get_staged_upgrade().unwrap();
+        assert_eq!(
+            staged_upgrade.code_hash,
+            "5694d08a2e53ffcae0c3103e5ad6f6076abd960eb1f8a56577040bc1028f702b"
+        );
+        assert_eq!(staged_upgrade.eta_ms, 1000);
+        assert!(get_logs()[0].contains(&staged_upgrade.code_hash));
+
+        set_context(vec![], 1000);
+        contract.deploy_staged_upgrade(Some(staged_upgrade.code_hash.clone()));
+        // kept until the upgrade succeeds
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(near_sdk::env::current_account_id())
+            .build());
+        contract
+            .on_deploy_staged_upgrade(staged_upgrade.code_hash.clone(), Err(PromiseError::Failed));
+        assert!(contract.get_staged_upgrade().is_some());
+        assert!(!get_logs().iter().any(|log| log.contains("deploy_upgrade")));
+
+        let summary = contract.get_summary();
+        contract.on_deploy_staged_upgrade(staged_upgrade.code_hash, Ok(summary));
+        assert!(contract.get_staged_upgrade().is_none());
+        assert!(near_sdk::env::storage_read(super::STAGED_CODE_KEY).is_none());
+        assert!(get_logs().iter().any(|log| log.contains("deploy_upgrade")));
+    }
+
+    #[test]
+    #[should_panic(expected = "Staged upgrade not ready")]
+    fn test_deploy_staged_upgrade_before_delay() {
+        let mut contract = test_contract_instance();
+        contract.upgrade_delay_ms = 1000;
+        set_context(b"code".to_vec(), 0);
+        contract.stage_upgrade();
+
+        set_context(vec![], 999);
+        contract.deploy_staged_upgrade(None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Code hash mismatch")]
+    fn test_deploy_staged_upgrade_with_wrong_hash() {
+        let mut contract = test_contract_instance();
+        contract.upgrade_delay_ms = 0;
+        set_context(b"code".to_vec(), 0);
+        contract.stage_upgrade();
+
+        contract.deploy_staged_upgrade(Some("00".to_string()));
     }
 }
@@ -1,4 +1,4 @@
-use bitcoin::{sighash::SighashCache, Amount, Psbt, TxOut};
+use bitcoin::{secp256k1::ecdsa::Signature, sighash::SighashCache, Amount, Psbt, TxOut};
 use near_sdk::{env, require};
 
 const ERR_EMBED_NOT_ZERO: &str = "Embed output should have 0 value";
@@ -12,8 +12,20 @@ const BITCOIN_SIGNED_MSG_PREFIX_UNISAT: &[u8] = b"Bitcoin Signed Message:\n";
 pub fn get_embed_message(output: &TxOut) -> String {
     require!(output.script_pubkey.is_op_return(), ERR_EMBED_NOT_OPRETURN);
     require!(output.value == Amount::ZERO, ERR_EMBED_NOT_ZERO);
-    // first 2 bytes are OP codes, msg starts from the 3rd byte (4th in hex)
-    output.script_pubkey.to_hex_string()[4..].to_string()
+    // msg is the data pushed right after OP_RETURN, which is prefixed by
+    // OP_PUSHDATA1 instead of OP_PUSHBYTES if it's longer than 75 bytes
+    let msg = output
+        .script_pubkey
+        .instructions()
+        .nth(1)
+        .and_then(|instruction| instruction.ok())
+        .and_then(|instruction| {
+            instruction
+                .push_bytes()
+                .map(|bytes| bytes.as_bytes().to_vec())
+        })
+        .unwrap_or_default();
+    hex::encode(msg)
 }
 
 pub fn get_hash_to_sign(psbt: &Psbt, vin: u64) -> [u8; 32] {
@@ -83,6 +95,23 @@ pub fn verify_secp256k1_signature(
     }
 }
 
+/// converts a signature returned by chain signatures into an ECDSA signature,
+/// S value is normalized to the lower half as required by bitcoin standardness rules
+pub fn chain_signature_to_ecdsa(big_r: &str, s: &str) -> Option<Signature> {
+    // big R is a compressed point, whose x coordinate is the r value
+    let big_r = hex::decode(big_r).ok()?;
+    if big_r.len() != 33 {
+        return None;
+    }
+    let s = hex::decode(format!("{:0>64}", s)).ok()?;
+
+    let mut compact = big_r[1..].to_vec();
+    compact.extend(s);
+    let mut sig = Signature::from_compact(&compact).ok()?;
+    sig.normalize_s();
+    Some(sig)
+}
+
 pub fn compress_pub_key(uncompressed_pub_key_bytes: &[u8; 64]) -> Vec<u8> {
     // Extract the x and y coordinates
     let x_coord = &uncompressed_pub_key_bytes[0..32]; // First 32 bytes after the prefix
@@ -119,6 +148,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_chain_signature_to_ecdsa() {
+        let sig = chain_signature_to_ecdsa(
+            "02E14D22E30DF1F02A3C46C52EB2B999AB009600FA945CACD3242AD66480E26EA7",
+            "7E7ADD7EF49E871C41EDF56BDF5C93B44E21A83CD55FA656318A1F0E6CD17CE9",
+        )
+        .unwrap();
+        assert_eq!(
+            sig.serialize_compact().to_hex_string(Case::Lower),
+            "e14d22e30df1f02a3c46c52eb2b999ab009600fa945cacd3242ad66480e26ea77e7add7ef49e871c41edf56bdf5c93b44e21a83cd55fa656318a1f0e6cd17ce9"
+        );
+
+        // high S value should be normalized
+        let sig = chain_signature_to_ecdsa(
+            "02E14D22E30DF1F02A3C46C52EB2B999AB009600FA945CACD3242AD66480E26EA7",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364140",
+        )
+        .unwrap();
+        assert_eq!(
+            sig.serialize_compact()[32..].to_hex_string(Case::Lower),
+            "0000000000000000000000000000000000000000000000000000000000000001"
+        );
+
+        assert!(chain_signature_to_ecdsa("r", "s").is_none());
+    }
+
     #[test]
     fn test_verify_signed_message_unisat() {
         let plain_msg = "hello:02405803ac0c989534cdd54d5e1215e4149dc11aee83c21097571150c633dbc1cc";
@@ -1,80 +1,70 @@
 use std::cmp::min;
 
 use crate::*;
-use account::{Deposit, DepositStatus};
-use bitcoin::{consensus::encode::deserialize_hex, Psbt, Transaction};
-use consts::CHAIN_SIGNATURES_PATH_V1;
-use near_sdk::{json_types::U128, Timestamp};
+use account::{Deposit, DepositStatus, WithdrawalPolicy};
+use bitcoin::{
+    consensus::encode::{deserialize_hex, serialize_hex},
+    Psbt, Transaction,
+};
+use consts::{CHAIN_SIGNATURES_PATH_V1, CHAIN_SIGNATURES_PATH_V2, CHAIN_SIGNATURES_PATH_V3};
+use migration::read_state_version;
+use recovery::{cancel_recovery_message, request_recovery_message};
 use serde::{Deserialize, Serialize};
-use types::{output_id, DepositEmbedMsg, PendingSignPsbt};
-use withdraw::{verify_pending_sign_partial_sig, verify_sign_withdrawal_psbt, withdrawal_message};
+use types::{output_id, DepositEmbedMsg, PendingSignPsbt, SUPPORTED_REDEEM_VERSIONS};
+use utils::current_timestamp_ms;
+use withdraw::{
+    cancel_pending_sign_psbt_message, normalize_withdrawal_policy, set_withdrawal_policy_message,
+    verify_pending_sign_partial_sig, verify_sign_withdrawal_psbt, withdrawal_message,
+    WithdrawalOutput, ERR_WITHDRAWAL_RATE_LIMIT_EXCEEDED,
+};
+
+pub use bithive_types::view::{
+    AccountView, BuildInfo, ContractSourceMetadata, ContractSummary, DepositConstantsV1,
+    DepositConstantsV2, DepositConstantsV3, Standard, WithdrawalConstantsV1, WithdrawalTicketView,
+};
 
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
-pub struct ContractSummary {
-    owner_id: AccountId,
-    btc_light_client_id: AccountId,
-    bip322_verifier_id: Option<AccountId>,
-    chain_signatures_id: AccountId,
-    chain_signatures_root_pubkey: Option<near_sdk::PublicKey>,
-    n_confirmation: u64,
-    withdrawal_waiting_time_ms: u64,
-    min_deposit_satoshi: u64,
-    earliest_deposit_block_height: u32,
-    solo_withdrawal_sequence_heights: Vec<u16>,
-    paused: bool,
+pub struct GetV1DepositConstantsArgs {
+    deposit_vout: u64,
+    user_pubkey: String,
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
-pub struct GetV1DepositConstantsArgs {
-    deposit_vout: u64,
+pub struct GetV2DepositConstantsArgs {
+    deposit_vout: u16,
     user_pubkey: String,
+    guardian_pubkey: String,
 }
 
-/// Constants for version 1 of the deposit script
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
-pub struct DepositConstantsV1 {
-    /// bithive pubkey used in the deposit script
-    bithive_pubkey: String,
-    /// message that needs to be embedded in the deposit transaction via OP_RETURN
-    deposit_embed_msg: Option<String>,
-    /// minimum deposit amount in satoshi
-    min_deposit_satoshi: u64,
-    /// earliest deposit block height
-    earliest_deposit_block_height: u32,
-    /// the current active value of sequence height for solo withdrawal
-    solo_withdrawal_sequence_height: u16,
+pub struct GetV3DepositConstantsArgs {
+    deposit_vout: u64,
+    user_pubkey: String,
+    maturity_height: u32,
 }
 
+/// Pending sign PSBT with its txn ID
 #[derive(Serialize)]
 #[serde(crate = "near_sdk::serde")]
-pub struct AccountView {
-    pub pubkey: PubKey,
-    /// total deposit amount in full BTC decimals
-    pub total_deposit: u64,
-    /// amount of deposits queued for withdrawal in full BTC decimals
-    pub queue_withdrawal_amount: u64,
-    /// timestamp when the queue withdrawal started in ms
-    pub queue_withdrawal_start_ts: Timestamp,
-    /// timestamp when the queue withdrawal ends in ms
-    pub queue_withdrawal_end_ts: Timestamp,
-    /// nonce is used in signing messages to prevent replay attacks
-    pub nonce: u64,
-    /// PSBT of the withdrawal txn that needs to be signed via chain signatures
-    pub pending_sign_psbt: Option<PendingSignPsbt>,
-    /// deposit user paid to cover the storage of pending sign PSBT
-    /// this should only be increased when needed
-    pub pending_sign_deposit: U128,
+pub struct PendingSignPsbtView {
+    tx_id: String,
+    #[serde(flatten)]
+    pending_sign_psbt: PendingSignPsbt,
 }
 
-/// Constants for withdrawing v1 deposits
-#[derive(Serialize, Deserialize)]
+/// Unsigned PSBT for multisig withdrawal
+#[derive(Serialize)]
 #[serde(crate = "near_sdk::serde")]
-pub struct WithdrawalConstantsV1 {
-    /// raw message that needs to be signed by the user for queueing withdrawal
-    queue_withdrawal_msg: String,
+pub struct WithdrawalPsbt {
+    /// hex encoded unsigned PSBT
+    psbt_hex: String,
+    /// vout of the reinvest deposit embed output, should be passed to `sign_withdrawal`
+    reinvest_embed_vout: Option<u64>,
+    /// txn fee in satoshi
+    fee: u64,
 }
 
 /// Deposit info
@@ -89,17 +79,45 @@ pub struct DepositInfo {
 impl Contract {
     pub fn get_summary(&self) -> ContractSummary {
         ContractSummary {
-            owner_id: self.owner_id.clone(),
-            btc_light_client_id: self.btc_light_client_id.clone(),
-            bip322_verifier_id: self.bip322_verifier_id.clone(),
-            chain_signatures_id: self.chain_signatures_id.clone(),
-            chain_signatures_root_pubkey: self.chain_signatures_root_pubkey.clone(),
+            owner_id: self.owner_id.to_string(),
+            btc_light_client_id: self.btc_light_client_id.to_string(),
+            bip322_verifier_id: self.bip322_verifier_id.as_ref().map(|id| id.to_string()),
+            chain_signatures_id: self.chain_signatures_id.to_string(),
+            chain_signatures_root_pubkey: self
+                .chain_signatures_root_pubkey
+                .as_ref()
+                .map(String::from),
             n_confirmation: self.n_confirmation,
             withdrawal_waiting_time_ms: self.withdrawal_waiting_time_ms,
+            withdrawal_waiting_tiers: into_vec(&self.withdrawal_waiting_tiers),
+            withdrawal_waiting_btc_blocks: self.withdrawal_waiting_btc_blocks,
+            btc_tip_height: self.btc_tip_height,
+            pending_sign_psbt_expiry_ms: self.pending_sign_psbt_expiry_ms,
+            withdrawal_rate_limit: self
+                .withdrawal_rate_limit
+                .as_ref()
+                .map(|limit| limit.view(current_timestamp_ms())),
             min_deposit_satoshi: self.min_deposit_satoshi,
+            max_deposit_satoshi: self.max_deposit_satoshi,
+            max_user_deposit_satoshi: self.max_user_deposit_satoshi,
+            sequence_height_deposit_limits: into_vec(&self.seq_height_deposit_limits),
+            max_total_deposit_satoshi: self.max_total_deposit_satoshi,
+            total_deposit: self.total_deposit,
             earliest_deposit_block_height: self.earliest_deposit_block_height,
             solo_withdrawal_sequence_heights: self.solo_withdrawal_seq_heights.clone(),
+            guardian_recovery_sequence_heights: self.guardian_recovery_seq_heights.clone(),
+            guardian_recovery_waiting_time_ms: self.guardian_recovery_waiting_time_ms,
+            deposit_terms: into_vec(&self.deposit_terms),
+            deposit_mode: self.deposit_mode.into(),
+            config_timelock_ms: self.config_timelock_ms,
+            upgrade_delay_ms: self.upgrade_delay_ms,
+            emit_legacy_events: self.emit_legacy_events,
             paused: self.paused,
+            state_version: read_state_version(),
+            supported_redeem_versions: SUPPORTED_REDEEM_VERSIONS
+                .iter()
+                .map(|v| v.to_string())
+                .collect(),
         }
     }
 
@@ -112,8 +130,7 @@ impl Contract {
         &self,
         args: Option<GetV1DepositConstantsArgs>,
     ) -> DepositConstantsV1 {
-        // the first item is the current active one
-        let sequence_height = self.solo_withdrawal_seq_heights[0];
+        let sequence_height = self.active_solo_withdrawal_seq_height();
 
         let embed_msg = args.map(|args| DepositEmbedMsg::V1 {
             deposit_vout: args.deposit_vout,
@@ -126,9 +143,77 @@ impl Contract {
                 .generate_btc_pubkey(CHAIN_SIGNATURES_PATH_V1)
                 .to_string(),
             deposit_embed_msg: embed_msg.map(|embed_msg| hex::encode(embed_msg.encode())),
-            min_deposit_satoshi: self.min_deposit_satoshi,
+            min_deposit_satoshi: self.deposit_limits(sequence_height).min_deposit_satoshi,
+            earliest_deposit_block_height: self.earliest_deposit_block_height,
+            solo_withdrawal_sequence_height: sequence_height,
+        }
+    }
+
+    /// Return constants that will be used for making v2 deposits with guardian recovery
+    /// ### Arguments
+    /// * `args.deposit_vout` - vout of the deposit output
+    /// * `args.user_pubkey` - user pubkey
+    /// * `args.guardian_pubkey` - pubkey of the guardian who could recover the deposit
+    pub fn get_v2_deposit_constants(
+        &self,
+        args: Option<GetV2DepositConstantsArgs>,
+    ) -> DepositConstantsV2 {
+        let sequence_height = self.active_solo_withdrawal_seq_height();
+        let recovery_sequence_height = *self
+            .guardian_recovery_seq_heights
+            .first()
+            .expect("Guardian recovery is not enabled");
+
+        let embed_msg = args.map(|args| DepositEmbedMsg::V2 {
+            deposit_vout: args.deposit_vout,
+            user_pubkey: hex::decode(args.user_pubkey).unwrap().try_into().unwrap(),
+            sequence_height,
+            guardian_pubkey: hex::decode(args.guardian_pubkey)
+                .unwrap()
+                .try_into()
+                .unwrap(),
+            recovery_sequence_height,
+        });
+
+        DepositConstantsV2 {
+            bithive_pubkey: self
+                .generate_btc_pubkey(CHAIN_SIGNATURES_PATH_V2)
+                .to_string(),
+            deposit_embed_msg: embed_msg.map(|embed_msg| hex::encode(embed_msg.encode())),
+            min_deposit_satoshi: self.deposit_limits(sequence_height).min_deposit_satoshi,
+            earliest_deposit_block_height: self.earliest_deposit_block_height,
+            solo_withdrawal_sequence_height: sequence_height,
+            guardian_recovery_sequence_height: recovery_sequence_height,
+        }
+    }
+
+    /// Return constants that will be used for making v3 fixed-term deposits
+    /// ### Arguments
+    /// * `args.deposit_vout` - vout of the deposit output
+    /// * `args.user_pubkey` - user pubkey
+    /// * `args.maturity_height` - maturity height of one of the available deposit terms
+    pub fn get_v3_deposit_constants(
+        &self,
+        args: Option<GetV3DepositConstantsArgs>,
+    ) -> DepositConstantsV3 {
+        let sequence_height = self.active_solo_withdrawal_seq_height();
+
+        let embed_msg = args.map(|args| DepositEmbedMsg::V3 {
+            deposit_vout: args.deposit_vout,
+            user_pubkey: hex::decode(args.user_pubkey).unwrap().try_into().unwrap(),
+            sequence_height,
+            maturity_height: args.maturity_height,
+        });
+
+        DepositConstantsV3 {
+            bithive_pubkey: self
+                .generate_btc_pubkey(CHAIN_SIGNATURES_PATH_V3)
+                .to_string(),
+            deposit_embed_msg: embed_msg.map(|embed_msg| hex::encode(embed_msg.encode())),
+            min_deposit_satoshi: self.deposit_limits(sequence_height).min_deposit_satoshi,
             earliest_deposit_block_height: self.earliest_deposit_block_height,
             solo_withdrawal_sequence_height: sequence_height,
+            deposit_terms: into_vec(&self.deposit_terms),
         }
     }
 
@@ -148,6 +233,101 @@ impl Contract {
         }
     }
 
+    /// Build an unsigned PSBT for multisig withdrawal, which has all the fields
+    /// required by `sign_withdrawal` set for each deposit input
+    /// ### Arguments
+    /// * `user_pubkey` - user pubkey
+    /// * `deposit_ids` - IDs (`tx_id:vout`) of active deposits to withdraw
+    /// * `outputs` - withdrawal outputs, one of which could leave `value` empty to receive the change
+    /// * `fee_rate` - fee rate in sat/vB
+    /// * `reinvest` - amount in satoshi to be deposited again, if any
+    pub fn build_withdrawal_psbt(
+        &self,
+        user_pubkey: String,
+        deposit_ids: Vec<String>,
+        outputs: Vec<WithdrawalOutput>,
+        fee_rate: u64,
+        reinvest: Option<u64>,
+    ) -> WithdrawalPsbt {
+        let account = self.get_account(&user_pubkey.into());
+        let (psbt, reinvest_embed_vout) =
+            self.new_withdrawal_psbt(&account, &deposit_ids, &outputs, fee_rate, reinvest);
+
+        let input_sum: u64 = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.as_ref().unwrap().value.to_sat())
+            .sum();
+        let output_sum: u64 = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .map(|output| output.value.to_sat())
+            .sum();
+
+        WithdrawalPsbt {
+            psbt_hex: hex::encode(psbt.serialize()),
+            reinvest_embed_vout,
+            fee: input_sum - output_sum,
+        }
+    }
+
+    /// Return the raw message that needs to be signed by the user for cancelling a pending sign PSBT
+    /// ### Arguments
+    /// * `user_pubkey` - user pubkey
+    /// * `tx_id` - txn ID of the pending sign PSBT
+    pub fn get_cancel_pending_sign_psbt_msg(&self, user_pubkey: String, tx_id: String) -> String {
+        let account = self.get_account(&user_pubkey.into());
+        cancel_pending_sign_psbt_message(account.nonce, &tx_id.into())
+    }
+
+    /// Return the message that needs to be signed by the user to set the withdrawal policy
+    /// ### Arguments
+    /// * `user_pubkey` - user pubkey
+    /// * `policy` - new withdrawal policy
+    pub fn get_set_withdrawal_policy_msg(
+        &self,
+        user_pubkey: String,
+        policy: WithdrawalPolicy,
+    ) -> String {
+        let account = self.get_account(&user_pubkey.into());
+        set_withdrawal_policy_message(account.nonce, &normalize_withdrawal_policy(policy))
+    }
+
+    /// Return the message that needs to be signed by the guardian to request recovery of a deposit
+    /// ### Arguments
+    /// * `user_pubkey` - user pubkey
+    /// * `deposit_id` - ID of the deposit to recover
+    pub fn get_request_recovery_msg(&self, user_pubkey: String, deposit_id: String) -> String {
+        let account = self.get_account(&user_pubkey.into());
+        request_recovery_message(account.guardian_nonce, &deposit_id.into())
+    }
+
+    /// Return the message that needs to be signed by the user to cancel a recovery request
+    /// ### Arguments
+    /// * `user_pubkey` - user pubkey
+    /// * `deposit_id` - ID of the deposit whose recovery request should be cancelled
+    pub fn get_cancel_recovery_msg(&self, user_pubkey: String, deposit_id: String) -> String {
+        let account = self.get_account(&user_pubkey.into());
+        cancel_recovery_message(account.nonce, &deposit_id.into())
+    }
+
+    /// Return hex encoded withdrawal txn of the pending sign PSBT that is ready to be broadcasted,
+    /// or None if not all of its inputs are signed yet
+    /// ### Arguments
+    /// * `user_pubkey` - user pubkey
+    /// * `tx_id` - txn ID of the pending sign PSBT
+    pub fn get_finalized_withdrawal_tx(
+        &self,
+        user_pubkey: String,
+        tx_id: String,
+    ) -> Option<String> {
+        let account = self.get_account(&user_pubkey.into());
+        let pending_sign_psbt = account.get_pending_sign_psbt(&tx_id.into())?;
+        self.finalize_withdrawal_psbt(&account, pending_sign_psbt.psbt.as_ref())
+            .map(|tx| serialize_hex(&tx))
+    }
+
     pub fn accounts_len(&self) -> u64 {
         self.accounts.len()
     }
@@ -161,6 +341,22 @@ impl Contract {
             .collect()
     }
 
+    pub fn deposit_allowlist_len(&self) -> u64 {
+        self.deposit_allowlist.len()
+    }
+
+    pub fn list_deposit_allowlist(&self, offset: u64, limit: u64) -> Vec<PubKey> {
+        self.deposit_allowlist
+            .iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    pub fn is_in_deposit_allowlist(&self, user_pubkey: String) -> bool {
+        self.deposit_allowlist.contains(&user_pubkey.into())
+    }
+
     pub fn view_account(&self, user_pubkey: String) -> AccountView {
         let account = self.get_account(&user_pubkey.into());
         self.get_account_view(&account)
@@ -200,6 +396,29 @@ impl Contract {
             .collect()
     }
 
+    pub fn user_pending_sign_psbts_len(&self, user_pubkey: String) -> u64 {
+        let account = self.get_account(&user_pubkey.into());
+        account.pending_sign_psbts_len()
+    }
+
+    pub fn list_user_pending_sign_psbts(
+        &self,
+        user_pubkey: String,
+        offset: u64,
+        limit: u64,
+    ) -> Vec<PendingSignPsbtView> {
+        let account = self.get_account(&user_pubkey.into());
+        (offset..min(account.pending_sign_psbts_len(), offset + limit))
+            .map(|idx| {
+                let pending_sign_psbt = account.get_pending_sign_psbt_by_index(idx).unwrap();
+                PendingSignPsbtView {
+                    tx_id: pending_sign_psbt.tx_id().into(),
+                    pending_sign_psbt,
+                }
+            })
+            .collect()
+    }
+
     pub fn get_deposit(&self, user_pubkey: String, tx_id: String, vout: u64) -> Option<Deposit> {
         let account = self.get_account(&user_pubkey.into());
         account
@@ -220,7 +439,16 @@ impl Contract {
             !self.confirmed_deposit_txns.contains(&output_id),
             "deposit txn already confirmed"
         );
-        self.verify_deposit_txn(&tx, embed_vout);
+        let deposit = self.verify_deposit_txn(&tx, embed_vout);
+        if let Err(err) = self.verify_deposit_allowed(&deposit.user_pubkey) {
+            env::panic_str(err);
+        }
+        if let Err(err) = self.verify_deposit_not_denied(&tx, &deposit) {
+            env::panic_str(err);
+        }
+        if let Err(err) = self.verify_deposit_limits(&deposit) {
+            env::panic_str(err);
+        }
     }
 
     /// Dry run sign withdrawal txn to verify if it can be accepted or not
@@ -241,37 +469,86 @@ impl Contract {
         let psbt = Psbt::deserialize(&psbt_bytes).unwrap();
 
         let account = self.get_account(&user_pubkey.clone().into());
+        account.assert_not_frozen();
 
         let input_to_sign = psbt.unsigned_tx.input.get(vin_to_sign as usize).unwrap();
         account.get_active_deposit(
             &input_to_sign.previous_output.txid.to_string().into(),
             input_to_sign.previous_output.vout.into(),
         );
+        self.verify_psbt_deposit_inputs(&account, &psbt);
+        verify_pending_sign_partial_sig(&psbt, vin_to_sign, &user_pubkey);
 
-        if account.pending_sign_psbt.is_some() {
-            verify_sign_withdrawal_psbt(account.pending_sign_psbt.as_ref().unwrap(), &psbt);
+        // apply the same restrictions as `sign_withdrawal`
+        let within_rate_limit = if let Some(pending_sign_psbt) =
+            account.find_pending_sign_psbt(&psbt.unsigned_tx.input)
+        {
+            verify_sign_withdrawal_psbt(&pending_sign_psbt, &psbt);
+            self.verify_withdrawal_allowed(
+                &account,
+                &psbt,
+                &[
+                    pending_sign_psbt.reinvest_deposit_vout,
+                    pending_sign_psbt.reinvest_embed_vout,
+                ],
+                (!pending_sign_psbt.bithive_signed).then_some(pending_sign_psbt.withdraw_amount),
+            )
         } else {
-            verify_pending_sign_partial_sig(&psbt, vin_to_sign, &user_pubkey);
-            self.verify_pending_sign_request_amount(&account, &psbt, reinvest_embed_vout);
-        }
+            let (reinvest_deposit_vout, withdraw_amount) =
+                self.verify_pending_sign_request_amount(&account, &psbt, reinvest_embed_vout);
+            self.verify_withdrawal_allowed(
+                &account,
+                &psbt,
+                &[reinvest_deposit_vout, reinvest_embed_vout],
+                Some(withdraw_amount),
+            )
+        };
+        require!(within_rate_limit, ERR_WITHDRAWAL_RATE_LIMIT_EXCEEDED);
     }
 }
 
 impl Contract {
     fn get_account_view(&self, account: &Account) -> AccountView {
+        let waiting_time = self.withdrawal_waiting_time();
+        let tickets = account.withdrawal_tickets();
         AccountView {
-            pubkey: account.pubkey.clone(),
+            pubkey: account.pubkey.to_string(),
             total_deposit: account.total_deposit,
-            queue_withdrawal_amount: account.queue_withdrawal_amount,
-            queue_withdrawal_start_ts: account.queue_withdrawal_start_ts,
-            queue_withdrawal_end_ts: if account.queue_withdrawal_start_ts == 0 {
-                0
-            } else {
-                account.queue_withdrawal_start_ts + self.withdrawal_waiting_time_ms
-            },
+            total_weighted_deposit: account.total_weighted_deposit,
+            queue_withdrawal_amount: account.queue_withdrawal_amount(),
+            queue_withdrawal_start_ts: tickets
+                .iter()
+                .map(|ticket| ticket.start_ts)
+                .min()
+                .unwrap_or(0),
+            queue_withdrawal_end_ts: tickets
+                .iter()
+                .filter_map(|ticket| ticket.unlock_ts(&waiting_time))
+                .max()
+                .unwrap_or(0),
+            withdrawal_tickets: tickets
+                .iter()
+                .map(|ticket| WithdrawalTicketView {
+                    ticket: ticket.clone().into(),
+                    unlock_ts: ticket.unlock_ts(&waiting_time),
+                    unlock_btc_height: ticket.unlock_btc_height(&waiting_time),
+                })
+                .collect(),
             nonce: account.nonce,
-            pending_sign_psbt: account.pending_sign_psbt.clone(),
+            guardian_nonce: account.guardian_nonce,
+            pending_sign_psbts_len: account.pending_sign_psbts_len(),
             pending_sign_deposit: account.pending_sign_deposit.into(),
+            withdrawal_policy: account.withdrawal_policy().clone().into(),
+            pending_withdrawal_policy: account.pending_withdrawal_policy().cloned().map(Into::into),
+            daily_withdrawal_amount: account.daily_withdrawal_amount(),
+            recovery_requests: into_vec(account.recovery_requests()),
+            freeze: account.freeze().cloned().map(Into::into),
+            unmigrated_deposits: account.unmigrated_deposits(),
         }
     }
 }
+
+/// Convert stored items to their JSON types
+fn into_vec<T: Clone + Into<U>, U>(items: &[T]) -> Vec<U> {
+    items.iter().cloned().map(Into::into).collect()
+}
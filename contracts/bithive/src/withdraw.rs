@@ -1,39 +1,66 @@
 use std::str::FromStr;
 
 use crate::*;
-use account::Deposit;
-use bitcoin::{consensus::encode::deserialize_hex, Psbt, PublicKey, Transaction, TxIn};
-use consts::{CHAIN_SIGNATURES_KEY_VERSION_V1, CHAIN_SIGNATURES_PATH_V1};
-use events::Event;
+use account::{
+    Deposit, WithdrawalPolicy, WithdrawalWaitingTime, ERR_PENDING_SIGN_PSBT_NOT_FOUND,
+    ERR_PENDING_SIGN_PSBT_SIGNED,
+};
+use bitcoin::{
+    absolute::LockTime, consensus::encode::deserialize_hex, script::PushBytesBuf,
+    sighash::EcdsaSighashType, transaction::Version, Amount, OutPoint, Psbt, PublicKey, ScriptBuf,
+    Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+use consts::{
+    CHAIN_SIGNATURES_KEY_VERSION_V1, CHAIN_SIGNATURES_KEY_VERSION_V2,
+    CHAIN_SIGNATURES_KEY_VERSION_V3, CHAIN_SIGNATURES_PATH_V1, CHAIN_SIGNATURES_PATH_V2,
+    CHAIN_SIGNATURES_PATH_V3,
+};
+use deposit::ERR_BAD_PUBKEY_HEX;
+use events::{Event, EventV2};
 use ext::{
-    ext_bip322_verifier, ext_btc_light_client, ext_chain_signatures, ProofArgs, SignRequest,
-    SignatureResponse, GAS_LIGHT_CLIENT_VERIFY,
+    ext_bip322_verifier, ext_btc_light_client, ext_chain_signatures, BtcBlockHeader, ProofArgs,
+    SignRequest, SignatureResponse, GAS_LIGHT_CLIENT_GET_TIP, GAS_LIGHT_CLIENT_VERIFY,
 };
 use near_sdk::{
     env::{self},
     json_types::U128,
-    near_bindgen, require, Balance, Gas, Promise, PromiseError, PromiseOrValue, ONE_NEAR,
+    log, near_bindgen, require, Balance, Gas, Promise, PromiseError, PromiseOrValue, Timestamp,
+    ONE_NEAR,
 };
+use recovery::{cancel_recovery_message, deposit_guardian_pubkey, request_recovery_message};
 use serde::{Deserialize, Serialize};
-use types::{DepositEmbedMsg, PendingSignPsbt, RedeemVersion, SubmitWithdrawTxArgs, TxId};
+use types::{
+    parse_output_id, DepositEmbedMsg, PendingSignPsbt, RedeemVersion, SubmitWithdrawTxArgs, TxId,
+};
 use utils::{
-    assert_gas, current_timestamp_ms, get_hash_to_sign, verify_secp256k1_signature,
-    verify_signed_message_ecdsa,
+    assert_gas, chain_signature_to_ecdsa, current_timestamp_ms, get_hash_to_sign,
+    verify_secp256k1_signature, verify_signed_message_ecdsa,
 };
 
-const GAS_CHAIN_SIG_SIGN: Gas = Gas(250 * Gas::ONE_TERA.0);
-const GAS_CHAIN_SIG_SIGN_CB: Gas = Gas(10 * Gas::ONE_TERA.0);
+pub(crate) const GAS_CHAIN_SIG_SIGN: Gas = Gas(250 * Gas::ONE_TERA.0);
+pub(crate) const GAS_CHAIN_SIG_SIGN_CB: Gas = Gas(10 * Gas::ONE_TERA.0);
 const GAS_WITHDRAW_VERIFY_CB: Gas = Gas(80 * Gas::ONE_TERA.0);
 const GAS_BIP322_VERIFY: Gas = Gas(20 * Gas::ONE_TERA.0);
 const GAS_BIP322_VERIFY_CB: Gas = Gas(20 * Gas::ONE_TERA.0);
+const GAS_GET_BTC_TIP_CB: Gas = Gas(10 * Gas::ONE_TERA.0);
+/// gas needed to verify and sign a withdrawal PSBT, including the chain signatures call
+const GAS_SIGN_WITHDRAWAL: Gas =
+    Gas(40 * Gas::ONE_TERA.0 + GAS_CHAIN_SIG_SIGN.0 + GAS_CHAIN_SIG_SIGN_CB.0);
 
 // queue withdrawal errors
 const ERR_BIP322_NOT_ENABLED: &str = "BIP322 is not enabled";
 const ERR_INVALID_WITHDRAWAL_AMOUNT: &str = "Withdrawal amount must be greater than 0";
+const ERR_FAILED_TO_GET_BTC_TIP: &str = "Failed to get BTC tip height from light client";
+const ERR_NONCE_CHANGED: &str = "Nonce changed before the action is executed";
+// cancel pending sign PSBT errors
+const ERR_PENDING_SIGN_PSBT_NOT_EXPIRED: &str = "Pending sign PSBT not expired yet";
+// set withdrawal policy errors
+const ERR_BAD_DESTINATION_SCRIPT: &str = "Invalid destination script pubkey hex";
+const ERR_LOOSEN_DELAY_TOO_LONG: &str = "Withdrawal policy loosen delay is too long";
 // sign withdrawal errors
 const ERR_INVALID_STORAGE_DEPOSIT: &str = "Invalid storage deposit amount";
 const ERR_INSUFFICIENT_STORAGE_DEPOSIT: &str = "Insufficient storage deposit";
-const ERR_INVALID_PSBT_HEX: &str = "Invalid PSBT hex";
+pub(crate) const ERR_INVALID_PSBT_HEX: &str = "Invalid PSBT hex";
 const ERR_NO_WITHDRAW_REQUESTED: &str = "No withdrawal request made";
 const ERR_WITHDRAW_NOT_READY: &str = "Not ready to withdraw now";
 const ERR_MISSING_PARTIAL_SIG: &str = "Missing partial sig for given input";
@@ -41,13 +68,100 @@ const ERR_INVALID_PARTIAL_SIG: &str = "Invalid partial signature for withdrawal
 const ERR_BAD_WITHDRAWAL_AMOUNT: &str = "Withdrawal amount is larger than queued amount";
 const ERR_PSBT_INPUT_LEN_MISMATCH: &str = "PSBT input length mismatch";
 const ERR_PSBT_INPUT_MISMATCH: &str = "PSBT input mismatch";
+const ERR_PSBT_MISSING_WITNESS_UTXO: &str = "PSBT input missing witness UTXO";
+const ERR_PSBT_WITNESS_UTXO_VALUE_MISMATCH: &str = "PSBT input witness UTXO value mismatch";
+const ERR_PSBT_WITNESS_UTXO_SCRIPT_MISMATCH: &str = "PSBT input witness UTXO script mismatch";
+const ERR_PSBT_WITNESS_SCRIPT_MISMATCH: &str = "PSBT input witness script mismatch";
+const ERR_PSBT_BAD_SIGHASH_TYPE: &str = "PSBT input sighash type must be SIGHASH_ALL";
 const ERR_PSBT_REINVEST_PUBKEY_MISMATCH: &str = "PSBT reinvest pubkey mismatch";
 const ERR_PSBT_REINVEST_OUTPUT_MISMATCH: &str = "PSBT reinvest output mismatch";
+const ERR_DESTINATION_NOT_ALLOWED: &str = "Withdrawal destination not allowed";
+pub(crate) const ERR_WITHDRAWAL_RATE_LIMIT_EXCEEDED: &str = "Withdrawal rate limit exceeded";
+const ERR_DEPOSIT_NOT_MATURED: &str = "Deposit not matured yet";
+const ERR_INVALID_CHAIN_SIG: &str = "Invalid signature from chain signatures";
+const ERR_CHAIN_SIG_FAILED: &str = "Failed to sign with chain signatures";
+const ERR_TX_NOT_CONFIRMED: &str = "Transaction not confirmed";
+// build withdrawal PSBT errors
+const ERR_NO_DEPOSIT_TO_WITHDRAW: &str = "No deposit to withdraw";
+pub(crate) const ERR_BAD_DEPOSIT_ID: &str = "Invalid deposit ID";
+const ERR_DUPLICATE_DEPOSIT_ID: &str = "Duplicate deposit ID";
+const ERR_BAD_OUTPUT_SCRIPT: &str = "Invalid output script pubkey hex";
+const ERR_BAD_OUTPUT_VALUE: &str = "Output value must be greater than 0";
+const ERR_OUTPUT_BELOW_DUST: &str = "Output value is below dust limit";
+const ERR_MULTIPLE_CHANGE_OUTPUTS: &str = "At most one output can receive the change";
+const ERR_INSUFFICIENT_INPUT_AMOUNT: &str = "Deposit amount is not enough to cover outputs and fee";
+const ERR_OUTPUT_VALUE_OVERFLOW: &str = "Total output value overflows";
+const ERR_FEE_OVERFLOW: &str = "Fee overflows, fee rate is too high";
 // submit withdrawal errors
 const ERR_INVALID_TX_HEX: &str = "Invalid txn hex";
 const ERR_NOT_WITHDRAW_TXN: &str = "Not a withdrawal transaction";
+const ERR_TOTAL_DEPOSIT_UNDERFLOW: &str = "Total deposit is less than the withdrawn deposit";
+
+pub(crate) const REFUND_THRESHOLD: Balance = ONE_NEAR / 100; // 0.01 NEAR
+
+const MAX_LOOSEN_DELAY_MS: u64 = 365 * 24 * 3600 * 1000; // 1 year
+
+/// Output of the withdrawal txn to build
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawalOutput {
+    /// hex encoded script pubkey
+    pub script_pubkey: String,
+    /// output amount in satoshi, leave it empty to receive the change
+    pub value: Option<u64>,
+}
+
+/// Action that has to be authorized by the user with a signed message
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum UserAction {
+    QueueWithdrawal {
+        amount: u64,
+    },
+    CancelPendingSignPsbt {
+        tx_id: String,
+    },
+    SetWithdrawalPolicy {
+        policy: WithdrawalPolicy,
+    },
+    /// signed by the guardian of the deposit instead of the user
+    RequestRecovery {
+        deposit_id: String,
+    },
+    CancelRecovery {
+        deposit_id: String,
+    },
+}
+
+impl UserAction {
+    /// nonce the message of this action is signed with, guardians have their own nonce
+    /// so that they cannot invalidate messages signed by the user
+    pub fn nonce(&self, account: &Account) -> u64 {
+        match self {
+            UserAction::RequestRecovery { .. } => account.guardian_nonce,
+            _ => account.nonce,
+        }
+    }
 
-const REFUND_THRESHOLD: Balance = ONE_NEAR / 100; // 0.01 NEAR
+    /// raw message that needs to be signed by the user for this action
+    pub fn message(&self, nonce: u64) -> String {
+        match self {
+            UserAction::QueueWithdrawal { amount } => withdrawal_message(nonce, *amount),
+            UserAction::CancelPendingSignPsbt { tx_id } => {
+                cancel_pending_sign_psbt_message(nonce, &tx_id.clone().into())
+            }
+            UserAction::SetWithdrawalPolicy { policy } => {
+                set_withdrawal_policy_message(nonce, policy)
+            }
+            UserAction::RequestRecovery { deposit_id } => {
+                request_recovery_message(nonce, &deposit_id.clone().into())
+            }
+            UserAction::CancelRecovery { deposit_id } => {
+                cancel_recovery_message(nonce, &deposit_id.clone().into())
+            }
+        }
+    }
+}
 
 /// in case different wallet signs message in different form,
 /// the signer needs to explicitly specify the type
@@ -63,8 +177,8 @@ pub enum SigType {
 
 #[near_bindgen]
 impl Contract {
-    /// Submit a queue withdrawal request for a user
-    /// NOTE: this will reset the queue withdrawal count down
+    /// Submit a queue withdrawal request for a user, which creates a new withdrawal ticket
+    /// that has its own waiting time
     /// ### Arguments
     /// * `user_pubkey` - hex encoded user pub key
     /// * `withdraw_amount` - amount to withdraw
@@ -78,54 +192,124 @@ impl Contract {
         sig_type: SigType,
     ) -> PromiseOrValue<bool> {
         self.assert_running();
-        assert_gas(Gas(40 * Gas::ONE_TERA.0) + GAS_BIP322_VERIFY + GAS_BIP322_VERIFY_CB); // 80 Tgas
         require!(withdraw_amount > 0, ERR_INVALID_WITHDRAWAL_AMOUNT);
+        self.get_account(&user_pubkey.clone().into())
+            .assert_not_frozen();
 
-        let mut account = self.get_account(&user_pubkey.clone().into());
+        let action = UserAction::QueueWithdrawal {
+            amount: withdraw_amount,
+        };
+        assert_gas(Gas(40 * Gas::ONE_TERA.0) + self.user_action_gas(&action)); // 80 or 100 Tgas
+        self.verify_user_action(user_pubkey, action, msg_sig, sig_type)
+    }
 
-        // verify msg signature
-        let expected_withdraw_msg = withdrawal_message(account.nonce, withdraw_amount);
-        match sig_type {
-            SigType::ECDSA => {
-                let msg = verify_signed_message_ecdsa(
-                    &expected_withdraw_msg.into_bytes(),
-                    &hex::decode(&msg_sig).unwrap(),
-                    &hex::decode(&user_pubkey).unwrap(),
-                );
-                account.queue_withdrawal(withdraw_amount, msg, &msg_sig);
-                self.set_account(account);
-                PromiseOrValue::Value(true)
-            }
-            SigType::Bip322Full { address } => {
-                require!(self.bip322_verifier_id.is_some(), ERR_BIP322_NOT_ENABLED);
-                ext_bip322_verifier::ext(self.bip322_verifier_id.clone().unwrap())
-                    .with_static_gas(GAS_BIP322_VERIFY)
-                    .verify_bip322_full(
-                        user_pubkey.clone(),
-                        address,
-                        expected_withdraw_msg.clone(),
-                        msg_sig.clone(),
-                    )
-                    .then(
-                        Self::ext(env::current_account_id())
-                            .with_static_gas(GAS_BIP322_VERIFY_CB)
-                            .on_bip322_verify(
-                                user_pubkey,
-                                withdraw_amount,
-                                expected_withdraw_msg,
-                                msg_sig,
-                            ),
-                    )
-                    .into()
-            }
+    /// Cancel a pending sign PSBT of a user, the withdrawal amount it took is put back to the queue.
+    /// This is needed if the user decides not to proceed before bithive signs the withdrawal txn,
+    /// afterwards the PSBT could only be replaced by fee or cleared once its inputs are spent
+    /// ### Arguments
+    /// * `user_pubkey` - hex encoded user pub key
+    /// * `tx_id` - txn ID of the pending sign PSBT to cancel
+    /// * `msg_sig` - hex encoded signature of cancel message that should match `user_pubkey`
+    /// * `sig_type` - signature type
+    pub fn cancel_pending_sign_psbt(
+        &mut self,
+        user_pubkey: String,
+        tx_id: String,
+        msg_sig: String,
+        sig_type: SigType,
+    ) -> PromiseOrValue<bool> {
+        self.assert_running();
+
+        let account = self.get_account(&user_pubkey.clone().into());
+        let pending_sign_psbt = account
+            .get_pending_sign_psbt(&tx_id.clone().into())
+            .expect(ERR_PENDING_SIGN_PSBT_NOT_FOUND);
+        require!(
+            !pending_sign_psbt.bithive_signed,
+            ERR_PENDING_SIGN_PSBT_SIGNED
+        );
+
+        let action = UserAction::CancelPendingSignPsbt { tx_id };
+        assert_gas(Gas(40 * Gas::ONE_TERA.0) + self.user_action_gas(&action)); // 80 or 100 Tgas
+        self.verify_user_action(user_pubkey, action, msg_sig, sig_type)
+    }
+
+    /// Set restrictions on multisig withdrawals of a user.
+    /// A stricter policy takes effect immediately, while a looser one only takes effect
+    /// after the delay chosen in the current policy
+    /// ### Arguments
+    /// * `user_pubkey` - hex encoded user pub key
+    /// * `policy` - new withdrawal policy
+    /// * `msg_sig` - hex encoded signature of set withdrawal policy message that should match `user_pubkey`
+    /// * `sig_type` - signature type
+    pub fn set_withdrawal_policy(
+        &mut self,
+        user_pubkey: String,
+        policy: WithdrawalPolicy,
+        msg_sig: String,
+        sig_type: SigType,
+    ) -> PromiseOrValue<bool> {
+        self.assert_running();
+
+        let action = UserAction::SetWithdrawalPolicy {
+            policy: normalize_withdrawal_policy(policy),
+        };
+        assert_gas(Gas(40 * Gas::ONE_TERA.0) + self.user_action_gas(&action)); // 80 Tgas
+        self.verify_user_action(user_pubkey, action, msg_sig, sig_type)
+    }
+
+    /// Discard an expired pending sign PSBT of a user, this could be called by anyone.
+    /// The withdrawal amount it took is put back to the queue and its storage deposit is refunded.
+    /// PSBTs signed by bithive could not be discarded, since the signed txn could still be broadcasted.
+    /// If waiting time is measured in BTC blocks, the restored ticket waits from the current BTC tip height
+    /// ### Arguments
+    /// * `user_pubkey` - hex encoded user pub key
+    /// * `tx_id` - txn ID of the pending sign PSBT to discard
+    pub fn discard_expired_pending_sign_psbt(
+        &mut self,
+        user_pubkey: String,
+        tx_id: String,
+    ) -> PromiseOrValue<()> {
+        self.assert_running();
+
+        let account = self.get_account(&user_pubkey.clone().into());
+        self.assert_pending_sign_psbt_expired(&account, &tx_id.clone().into());
+
+        // the restored withdrawal ticket needs the current BTC tip height to wait again
+        if self.withdrawal_waiting_btc_blocks.is_some() {
+            assert_gas(Gas(10 * Gas::ONE_TERA.0) + GAS_LIGHT_CLIENT_GET_TIP + GAS_GET_BTC_TIP_CB); // 30 Tgas
+            return ext_btc_light_client::ext(self.btc_light_client_id.clone())
+                .with_static_gas(GAS_LIGHT_CLIENT_GET_TIP)
+                .get_last_block_header()
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_GET_BTC_TIP_CB)
+                        .on_get_btc_tip_to_discard(user_pubkey, tx_id),
+                )
+                .into();
         }
+
+        self.expire_pending_sign_psbt(user_pubkey, tx_id.into(), None);
+        PromiseOrValue::Value(())
+    }
+
+    #[private]
+    pub fn on_get_btc_tip_to_discard(
+        &mut self,
+        user_pubkey: String,
+        tx_id: String,
+        #[callback_result] result: Result<BtcBlockHeader, PromiseError>,
+    ) {
+        let header = result.expect(ERR_FAILED_TO_GET_BTC_TIP);
+        self.update_btc_tip_height(header.block_height);
+        self.expire_pending_sign_psbt(user_pubkey, tx_id.into(), Some(header.block_height));
     }
 
     #[private]
     pub fn on_bip322_verify(
         &mut self,
         user_pubkey: String,
-        withdraw_amount: u64,
+        action: UserAction,
         msg: String,
         msg_sig: String,
         #[callback_result] result: Result<bool, PromiseError>,
@@ -135,13 +319,48 @@ impl Contract {
             return PromiseOrValue::Value(false);
         }
 
-        let mut account = self.get_account(&user_pubkey.clone().into());
-        account.queue_withdrawal(withdraw_amount, msg.into_bytes(), &msg_sig);
+        self.execute_verified_user_action(user_pubkey, action, msg.into_bytes(), msg_sig)
+    }
+
+    #[private]
+    pub fn on_get_btc_tip_height(
+        &mut self,
+        user_pubkey: String,
+        action: UserAction,
+        nonce: u64,
+        msg_hex: String,
+        msg_sig: String,
+        #[callback_result] result: Result<BtcBlockHeader, PromiseError>,
+    ) -> bool {
+        let header = match result {
+            Ok(header) => header,
+            Err(_) => {
+                log!(ERR_FAILED_TO_GET_BTC_TIP);
+                return false;
+            }
+        };
+        self.update_btc_tip_height(header.block_height);
+
+        let mut account = self.get_account(&user_pubkey.into());
+        // the signed message must not be used by another action in the meantime
+        require!(account.nonce == nonce, ERR_NONCE_CHANGED);
+        execute_user_action(
+            &mut account,
+            &action,
+            &self.withdrawal_waiting_time(),
+            Some(header.block_height),
+            hex::decode(msg_hex).unwrap(),
+            &msg_sig,
+        );
         self.set_account(account);
-        PromiseOrValue::Value(true)
+        true
     }
 
-    /// Sign a BTC withdrawal PSBT via chain signatures for multisig withdrawal
+    /// Sign a BTC withdrawal PSBT via chain signatures for multisig withdrawal.
+    /// If the withdrawal is not ready with the synced BTC tip height, the current one is fetched
+    /// from light client first, which needs 320 Tgas.
+    /// If the global withdrawal rate limit is exceeded, it returns None without panicking
+    /// so that the `withdrawal_rate_limit_exceeded` event is kept, and the attached NEAR is refunded
     /// ### Arguments
     /// * `psbt_hex` - hex encoded PSBT to sign, must be partially signed by the user first
     /// * `user_pubkey` - user public key
@@ -156,103 +375,138 @@ impl Contract {
         vin_to_sign: u64,
         reinvest_embed_vout: Option<u64>,
         storage_deposit: Option<U128>,
-    ) -> Promise {
+    ) -> PromiseOrValue<Option<SignatureResponse>> {
         self.assert_running();
 
-        assert_gas(Gas(40 * Gas::ONE_TERA.0) + GAS_CHAIN_SIG_SIGN + GAS_CHAIN_SIG_SIGN_CB); // 300 Tgas
-
-        let mut attached_near_for_storage = 0u128;
+        assert_gas(GAS_SIGN_WITHDRAWAL); // 300 Tgas
 
-        let psbt_bytes = hex::decode(psbt_hex).unwrap();
+        let psbt_bytes = hex::decode(&psbt_hex).unwrap();
         let psbt = Psbt::deserialize(&psbt_bytes).expect(ERR_INVALID_PSBT_HEX);
 
-        let mut account = self.get_account(&user_pubkey.clone().into());
-
-        let input_to_sign = psbt.unsigned_tx.input.get(vin_to_sign as usize).unwrap();
-        let deposit = account.get_active_deposit(
-            &input_to_sign.previous_output.txid.to_string().into(),
-            input_to_sign.previous_output.vout.into(),
-        );
+        // the withdrawal is checked against the current BTC tip height from light client,
+        // if it is not ready with the synced one
+        let account = self.get_account(&user_pubkey.clone().into());
+        if self.is_btc_tip_behind(&account, &psbt, reinvest_embed_vout) {
+            assert_gas(Gas(10 * Gas::ONE_TERA.0) + GAS_LIGHT_CLIENT_GET_TIP + GAS_SIGN_WITHDRAWAL); // 320 Tgas
 
-        if account.pending_sign_psbt.is_some() {
-            // if the user has previously requested to sign a withdrawal tx, he cannot request to
-            // sign another one until the previous one is completed or replaced by fee
-            verify_sign_withdrawal_psbt(account.pending_sign_psbt.as_ref().unwrap(), &psbt);
-        } else {
-            // if not, verify the withdrawal PSBT and save it for signing
-            verify_pending_sign_partial_sig(&psbt, vin_to_sign, &user_pubkey);
-            let reinvest_deposit_vout =
-                self.verify_pending_sign_request_amount(&account, &psbt, reinvest_embed_vout);
+            // the attached deposit could not be refunded if the request panics in the callback,
+            // so everything that does not depend on the tip is checked before fetching it
+            self.verify_sign_withdrawal_without_btc_tip(
+                &account,
+                &psbt,
+                psbt_bytes.len(),
+                &user_pubkey,
+                vin_to_sign,
+                reinvest_embed_vout,
+                storage_deposit,
+                env::attached_deposit(),
+            );
+            return ext_btc_light_client::ext(self.btc_light_client_id.clone())
+                .with_static_gas(GAS_LIGHT_CLIENT_GET_TIP)
+                .get_last_block_header()
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_SIGN_WITHDRAWAL)
+                        .on_get_btc_tip_to_sign(
+                            psbt_hex,
+                            user_pubkey,
+                            vin_to_sign,
+                            reinvest_embed_vout,
+                            storage_deposit,
+                            env::predecessor_account_id(),
+                            env::attached_deposit().into(),
+                        ),
+                )
+                .into();
+        }
 
-            // if there is more than one input in PSBT, we charge the user for PSBT storage deposit
-            if psbt.unsigned_tx.input.len() > 1 {
-                attached_near_for_storage = storage_deposit.unwrap_or(U128::from(0)).into();
-                require!(
-                    env::attached_deposit() >= attached_near_for_storage,
-                    ERR_INVALID_STORAGE_DEPOSIT
-                );
-                let storage_needed = psbt_bytes.len() as u128 * env::storage_byte_cost();
-                require!(
-                    account.pending_sign_deposit + attached_near_for_storage >= storage_needed,
-                    ERR_INSUFFICIENT_STORAGE_DEPOSIT
-                );
-            }
+        self.request_withdrawal_signature(
+            &psbt_bytes,
+            psbt,
+            user_pubkey,
+            vin_to_sign,
+            reinvest_embed_vout,
+            storage_deposit,
+            env::predecessor_account_id(),
+            env::attached_deposit(),
+        )
+    }
 
-            // update account state
-            account.pending_sign_psbt = Some(PendingSignPsbt {
-                psbt: psbt.clone().into(),
-                reinvest_deposit_vout,
-                reinvest_embed_vout,
-            });
-            account.pending_sign_deposit += attached_near_for_storage;
-            // reset queue withdrawal amount
-            account.queue_withdrawal_amount = 0;
-            account.queue_withdrawal_start_ts = 0;
+    #[allow(clippy::too_many_arguments)]
+    #[private]
+    pub fn on_get_btc_tip_to_sign(
+        &mut self,
+        psbt_hex: String,
+        user_pubkey: String,
+        vin_to_sign: u64,
+        reinvest_embed_vout: Option<u64>,
+        storage_deposit: Option<U128>,
+        caller_id: AccountId,
+        attached_deposit: U128,
+        #[callback_result] result: Result<BtcBlockHeader, PromiseError>,
+    ) -> PromiseOrValue<Option<SignatureResponse>> {
+        match result {
+            Ok(header) => self.update_btc_tip_height(header.block_height),
+            Err(_) => log!(ERR_FAILED_TO_GET_BTC_TIP),
+        }
 
-            self.set_account(account);
+        let psbt_bytes = hex::decode(&psbt_hex).unwrap();
+        let psbt = Psbt::deserialize(&psbt_bytes).expect(ERR_INVALID_PSBT_HEX);
+        let account = self.get_account(&user_pubkey.clone().into());
+        // refund instead of panicking, otherwise the attached deposit would be kept by the contract
+        if self.is_btc_tip_behind(&account, &psbt, reinvest_embed_vout) {
+            log!(ERR_WITHDRAW_NOT_READY);
+            if attached_deposit.0 > 0 {
+                Promise::new(caller_id).transfer(attached_deposit.0);
+            }
+            return PromiseOrValue::Value(None);
         }
 
-        // request signature from chain signatures
-        let payload = get_hash_to_sign(&psbt, vin_to_sign);
-        let (path, key_version) = match deposit.redeem_version {
-            RedeemVersion::V1 => (
-                CHAIN_SIGNATURES_PATH_V1.to_string(),
-                CHAIN_SIGNATURES_KEY_VERSION_V1,
-            ),
-        };
-        let req = SignRequest {
-            payload,
-            path,
-            key_version,
-        };
-        // the rest of the attached NEAR will be used for chain signatures
-        let chain_signatures_deposit = env::attached_deposit() - attached_near_for_storage;
-        ext_chain_signatures::ext(self.chain_signatures_id.clone())
-            .with_static_gas(GAS_CHAIN_SIG_SIGN)
-            .with_attached_deposit(chain_signatures_deposit)
-            .sign(req)
-            .then(
-                Self::ext(env::current_account_id())
-                    .with_static_gas(GAS_CHAIN_SIG_SIGN_CB)
-                    .on_sign_withdrawal(
-                        user_pubkey,
-                        env::predecessor_account_id(),
-                        chain_signatures_deposit.into(),
-                    ),
-            )
+        self.request_withdrawal_signature(
+            &psbt_bytes,
+            psbt,
+            user_pubkey,
+            vin_to_sign,
+            reinvest_embed_vout,
+            storage_deposit,
+            caller_id,
+            attached_deposit.0,
+        )
     }
 
     #[private]
     pub fn on_sign_withdrawal(
         &mut self,
         user_pubkey: String,
+        tx_id: String,
+        vin_to_sign: u64,
         caller_id: AccountId,
         attached_deposit: U128,
         #[callback_result] result: Result<SignatureResponse, PromiseError>,
     ) -> Option<SignatureResponse> {
         if let Ok(sig) = result {
+            let mut account = self.get_account(&user_pubkey.clone().into());
+            if !self.save_withdrawal_signature(
+                &mut account,
+                &tx_id.clone().into(),
+                vin_to_sign,
+                &sig,
+            ) {
+                // nothing is cosigned, so the amount is not counted against the limits
+                self.release_withdrawal_limits(&user_pubkey, &tx_id.clone().into());
+                Event::SignWithdrawalFailed {
+                    user_pubkey: user_pubkey.clone(),
+                    tx_id: tx_id.clone(),
+                    vin_to_sign: vin_to_sign.into(),
+                    reason: ERR_INVALID_CHAIN_SIG.to_string(),
+                }
+                .emit();
+                return None;
+            }
+            self.set_account(account);
+
             Event::SignWithdrawal {
-                user_pubkey: &user_pubkey,
+                user_pubkey: user_pubkey.clone(),
             }
             .emit();
 
@@ -262,6 +516,16 @@ impl Contract {
             if attached_deposit.0 >= REFUND_THRESHOLD {
                 Promise::new(caller_id).transfer(attached_deposit.into());
             }
+            // nothing is cosigned, so the amount is not counted against the limits
+            self.release_withdrawal_limits(&user_pubkey, &tx_id.clone().into());
+
+            Event::SignWithdrawalFailed {
+                user_pubkey: user_pubkey.clone(),
+                tx_id: tx_id.clone(),
+                vin_to_sign: vin_to_sign.into(),
+                reason: ERR_CHAIN_SIG_FAILED.to_string(),
+            }
+            .emit();
             None
         }
     }
@@ -305,14 +569,25 @@ impl Contract {
         tx_hex: String,
         #[callback_result] result: Result<bool, PromiseError>,
     ) -> bool {
-        let valid = result.unwrap_or(false);
-        if !valid {
-            return false;
-        }
-
         let tx = deserialize_hex::<Transaction>(&tx_hex).expect(ERR_INVALID_TX_HEX);
         let tx_id: TxId = tx.compute_txid().to_string().into();
 
+        // the withdrawal could be submitted again once the state migration is finished
+        let verified = if result.unwrap_or(false) {
+            self.verify_not_migrating()
+        } else {
+            Err(ERR_TX_NOT_CONFIRMED)
+        };
+        if let Err(err) = verified {
+            Event::WithdrawalRejected {
+                user_pubkey: user_pubkey.clone(),
+                tx_id: tx_id.to_string(),
+                reason: err.to_string(),
+            }
+            .emit();
+            return false;
+        }
+
         let mut account = self.get_account(&user_pubkey.clone().into());
         let deposit_inputs = filter_deposit_inputs(&account, &tx.input);
         require!(!deposit_inputs.is_empty(), ERR_NOT_WITHDRAW_TXN);
@@ -323,8 +598,39 @@ impl Contract {
                 deposit_input.previous_output.vout.into(),
             );
             let is_multisig = is_multisig_withdrawal(&deposit, deposit_input);
+            self.total_deposit = self
+                .total_deposit
+                .checked_sub(deposit.value)
+                .expect(ERR_TOTAL_DEPOSIT_UNDERFLOW);
+            let deposit_tx_id = deposit.deposit_tx_id.to_string();
+            let (deposit_vout, value) = (deposit.deposit_vout, deposit.value);
+            let redeem_version = deposit.redeem_version.clone();
             account.complete_withdrawal(deposit, &tx_id, is_multisig);
+
+            if self.emit_legacy_events {
+                Event::Withdrawn {
+                    user_pubkey: account.pubkey.to_string(),
+                    withdrawal_tx_id: tx_id.to_string(),
+                    deposit_tx_id: deposit_tx_id.clone(),
+                    deposit_vout: deposit_vout.into(),
+                    is_multisig,
+                }
+                .emit();
+            }
+            EventV2::Withdrawn {
+                user_pubkey: account.pubkey.to_string(),
+                withdrawal_tx_id: tx_id.to_string(),
+                deposit_tx_id: deposit_tx_id.clone(),
+                deposit_vout: deposit_vout.into(),
+                value: value.into(),
+                redeem_version: redeem_version.clone().into(),
+                is_multisig,
+                total_deposit: account.total_deposit.into(),
+            }
+            .emit();
         }
+        // pending sign PSBTs spending the same deposits can never be broadcasted now
+        account.clear_pending_sign_psbts(&tx.input);
         self.set_account(account);
 
         true
@@ -332,71 +638,1045 @@ impl Contract {
 }
 
 impl Contract {
-    /// Verify if the withdrawal amount in the PSBT is valid
-    /// Returns the reinvest deposit vout if any
-    pub(crate) fn verify_pending_sign_request_amount(
-        &self,
-        account: &Account,
-        psbt: &Psbt,
-        reinvest_embed_vout: Option<u64>,
-    ) -> Option<u64> {
-        require!(
-            account.queue_withdrawal_amount > 0 && account.queue_withdrawal_start_ts > 0,
-            ERR_NO_WITHDRAW_REQUESTED
-        );
-
-        // make sure queue waiting time has passed
-        require!(
-            current_timestamp_ms()
-                >= account.queue_withdrawal_start_ts + self.withdrawal_waiting_time_ms,
-            ERR_WITHDRAW_NOT_READY
-        );
-
-        // sum all known deposit inputs
-        let deposit_input_sum = filter_deposit_inputs(account, &psbt.unsigned_tx.input)
-            .iter()
-            .map(|input| {
-                let deposit = account.get_active_deposit(
-                    &input.previous_output.txid.to_string().into(),
-                    input.previous_output.vout.into(),
-                );
-                deposit.value
-            })
-            .sum::<u64>();
-
-        // subtract reinvest amount if provided
-        let reinvest_amount = reinvest_embed_vout
-            .map(|embed_vout| {
-                let deposit = self.verify_deposit_txn(&psbt.unsigned_tx, embed_vout);
-                require!(
-                    deposit.user_pubkey == account.pubkey,
-                    ERR_PSBT_REINVEST_PUBKEY_MISMATCH
+    /// Verify the message signed by the user for the given action, and execute it if valid
+    pub(crate) fn verify_user_action(
+        &mut self,
+        user_pubkey: String,
+        action: UserAction,
+        msg_sig: String,
+        sig_type: SigType,
+    ) -> PromiseOrValue<bool> {
+        let account = self.get_account(&user_pubkey.clone().into());
+        let expected_msg = action.message(action.nonce(&account));
+        let signer_pubkey = match &action {
+            UserAction::RequestRecovery { deposit_id } => {
+                deposit_guardian_pubkey(&account, deposit_id)
+            }
+            _ => user_pubkey.clone(),
+        };
+        match sig_type {
+            SigType::ECDSA => {
+                let msg = verify_signed_message_ecdsa(
+                    &expected_msg.into_bytes(),
+                    &hex::decode(&msg_sig).unwrap(),
+                    &hex::decode(&signer_pubkey).unwrap(),
                 );
-                deposit.value
-            })
-            .unwrap_or(0);
-        let actual_withdraw_amount = deposit_input_sum - reinvest_amount;
-
-        // make sure the actual amount is less than or equal to the requested withdrawal amount
-        require!(
-            actual_withdraw_amount <= account.queue_withdrawal_amount,
-            ERR_BAD_WITHDRAWAL_AMOUNT
-        );
-
-        // return the reinvest deposit vout if any
-        reinvest_embed_vout.map(|embed_vout| {
-            let embed_msg = self.verify_embed_output(&psbt.unsigned_tx, embed_vout);
-            match embed_msg {
-                DepositEmbedMsg::V1 { deposit_vout, .. } => deposit_vout,
+                self.execute_verified_user_action(user_pubkey, action, msg, msg_sig)
             }
-        })
+            SigType::Bip322Full { address } => {
+                require!(self.bip322_verifier_id.is_some(), ERR_BIP322_NOT_ENABLED);
+                ext_bip322_verifier::ext(self.bip322_verifier_id.clone().unwrap())
+                    .with_static_gas(GAS_BIP322_VERIFY)
+                    .verify_bip322_full(
+                        signer_pubkey,
+                        address,
+                        expected_msg.clone(),
+                        msg_sig.clone(),
+                    )
+                    .then(
+                        Self::ext(env::current_account_id())
+                            .with_static_gas(self.user_action_gas(&action) - GAS_BIP322_VERIFY)
+                            .on_bip322_verify(user_pubkey, action, expected_msg, msg_sig),
+                    )
+                    .into()
+            }
+        }
     }
-}
 
-pub(crate) fn withdrawal_message(nonce: u64, amount: u64) -> String {
+    /// Execute the action whose message signature has been verified.
+    /// If waiting time is measured in BTC blocks, a new or restored withdrawal ticket needs
+    /// the current BTC tip height from light client first
+    fn execute_verified_user_action(
+        &mut self,
+        user_pubkey: String,
+        action: UserAction,
+        msg: Vec<u8>,
+        msg_sig: String,
+    ) -> PromiseOrValue<bool> {
+        let mut account = self.get_account(&user_pubkey.clone().into());
+        if self.needs_btc_tip_height(&action) {
+            return ext_btc_light_client::ext(self.btc_light_client_id.clone())
+                .with_static_gas(GAS_LIGHT_CLIENT_GET_TIP)
+                .get_last_block_header()
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_GET_BTC_TIP_CB)
+                        .on_get_btc_tip_height(
+                            user_pubkey,
+                            action,
+                            account.nonce,
+                            hex::encode(msg),
+                            msg_sig,
+                        ),
+                )
+                .into();
+        }
+
+        // no withdrawal ticket waiting for BTC blocks is created without fetching the tip height
+        execute_user_action(
+            &mut account,
+            &action,
+            &self.withdrawal_waiting_time(),
+            None,
+            msg,
+            &msg_sig,
+        );
+        self.set_account(account);
+        PromiseOrValue::Value(true)
+    }
+
+    /// Verify the withdrawal PSBT, save it as pending sign and request the signature from
+    /// chain signatures. `caller_id` and `attached_deposit` are of the original `sign_withdrawal` call
+    #[allow(clippy::too_many_arguments)]
+    fn request_withdrawal_signature(
+        &mut self,
+        psbt_bytes: &[u8],
+        psbt: Psbt,
+        user_pubkey: String,
+        vin_to_sign: u64,
+        reinvest_embed_vout: Option<u64>,
+        storage_deposit: Option<U128>,
+        caller_id: AccountId,
+        attached_deposit: Balance,
+    ) -> PromiseOrValue<Option<SignatureResponse>> {
+        let mut attached_near_for_storage = 0u128;
+
+        let mut account = self.get_account(&user_pubkey.clone().into());
+        account.assert_not_frozen();
+
+        let input_to_sign = psbt.unsigned_tx.input.get(vin_to_sign as usize).unwrap();
+        let deposit = account.get_active_deposit(
+            &input_to_sign.previous_output.txid.to_string().into(),
+            input_to_sign.previous_output.vout.into(),
+        );
+        // make sure the hash to sign is computed from the saved deposit data
+        self.verify_psbt_deposit_inputs(&account, &psbt);
+
+        // the PSBT must be submitted by the user, including a replacement of a pending one
+        verify_pending_sign_partial_sig(&psbt, vin_to_sign, &user_pubkey);
+
+        let user_pk = PublicKey::from_str(&user_pubkey).expect(ERR_BAD_PUBKEY_HEX);
+        let tx_id: TxId = psbt.unsigned_tx.compute_txid().to_string().into();
+        if let Some(mut pending_sign_psbt) = account.find_pending_sign_psbt(&psbt.unsigned_tx.input)
+        {
+            // if the user has previously requested to sign a withdrawal tx that spends any of
+            // the same deposits, the request must be the same tx or a replacement by fee of it
+            verify_sign_withdrawal_psbt(&pending_sign_psbt, &psbt);
+            // the amount is counted once bithive signs any version of the txn,
+            // and counted again if the previous request failed before that
+            let withdraw_amount =
+                (!pending_sign_psbt.bithive_signed).then_some(pending_sign_psbt.withdraw_amount);
+            // the replacement must follow the withdrawal policy set by the user as well
+            let within_rate_limit = self.verify_withdrawal_allowed(
+                &account,
+                &psbt,
+                &[
+                    pending_sign_psbt.reinvest_deposit_vout,
+                    pending_sign_psbt.reinvest_embed_vout,
+                ],
+                withdraw_amount,
+            );
+            if let Some(withdraw_amount) = withdraw_amount {
+                // stop cosigning if too much has been withdrawn recently
+                if !within_rate_limit {
+                    self.reject_withdrawal_over_rate_limit(
+                        &user_pubkey,
+                        withdraw_amount,
+                        caller_id,
+                        attached_deposit,
+                    );
+                    return PromiseOrValue::Value(None);
+                }
+                pending_sign_psbt.rate_limit_ts =
+                    self.record_withdrawal_rate_limit(withdraw_amount);
+                pending_sign_psbt.daily_withdrawal_ts =
+                    account.record_daily_withdrawal(withdraw_amount);
+            }
+            // keep the saved PSBT in sync with the one being signed
+            account.remove_pending_sign_psbt(&pending_sign_psbt.tx_id());
+            pending_sign_psbt.update_psbt(&psbt, &user_pk);
+            account.insert_pending_sign_psbt(pending_sign_psbt);
+
+            self.set_account(account);
+        } else {
+            // if not, verify the withdrawal PSBT and save it for signing
+            let (reinvest_deposit_vout, withdraw_amount) =
+                self.verify_pending_sign_request_amount(&account, &psbt, reinvest_embed_vout);
+            // enforce the withdrawal policy set by the user
+            let within_rate_limit = self.verify_withdrawal_allowed(
+                &account,
+                &psbt,
+                &[reinvest_deposit_vout, reinvest_embed_vout],
+                Some(withdraw_amount),
+            );
+            // stop cosigning if too much has been withdrawn recently,
+            // which is checked before the account is changed
+            if !within_rate_limit {
+                self.reject_withdrawal_over_rate_limit(
+                    &user_pubkey,
+                    withdraw_amount,
+                    caller_id,
+                    attached_deposit,
+                );
+                return PromiseOrValue::Value(None);
+            }
+            let rate_limit_ts = self.record_withdrawal_rate_limit(withdraw_amount);
+            let daily_withdrawal_ts = account.record_daily_withdrawal(withdraw_amount);
+
+            attached_near_for_storage = pending_sign_storage_deposit(
+                &account,
+                &psbt,
+                psbt_bytes.len(),
+                storage_deposit,
+                attached_deposit,
+            );
+
+            // update account state
+            let mut pending_sign_psbt = PendingSignPsbt::new(
+                psbt.clone(),
+                &user_pk,
+                withdraw_amount,
+                reinvest_deposit_vout,
+                reinvest_embed_vout,
+            );
+            pending_sign_psbt.storage_deposit = attached_near_for_storage.into();
+            pending_sign_psbt.storage_payer = caller_id.clone();
+            pending_sign_psbt.daily_withdrawal_ts = daily_withdrawal_ts;
+            pending_sign_psbt.rate_limit_ts = rate_limit_ts;
+            account.insert_pending_sign_psbt(pending_sign_psbt);
+            account.pending_sign_deposit += attached_near_for_storage;
+            // take the withdrawal amount from matured tickets
+            account
+                .take_matured_withdrawal_amount(withdraw_amount, &self.withdrawal_waiting_time());
+
+            self.set_account(account);
+        }
+
+        // request signature from chain signatures
+        let payload = get_hash_to_sign(&psbt, vin_to_sign);
+        let (path, key_version) = chain_signatures_path(&deposit.redeem_version);
+        let req = SignRequest {
+            payload,
+            path: path.to_string(),
+            key_version,
+        };
+        // the rest of the attached NEAR will be used for chain signatures
+        let chain_signatures_deposit = attached_deposit - attached_near_for_storage;
+        ext_chain_signatures::ext(self.chain_signatures_id.clone())
+            .with_static_gas(GAS_CHAIN_SIG_SIGN)
+            .with_attached_deposit(chain_signatures_deposit)
+            .sign(req)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_CHAIN_SIG_SIGN_CB)
+                    .on_sign_withdrawal(
+                        user_pubkey,
+                        tx_id.to_string(),
+                        vin_to_sign,
+                        caller_id,
+                        chain_signatures_deposit.into(),
+                    ),
+            )
+            .into()
+    }
+
+    /// Actions that create withdrawal tickets, including the one restored from a cancelled PSBT
+    fn needs_btc_tip_height(&self, action: &UserAction) -> bool {
+        self.withdrawal_waiting_btc_blocks.is_some()
+            && matches!(
+                action,
+                UserAction::QueueWithdrawal { .. } | UserAction::CancelPendingSignPsbt { .. }
+            )
+    }
+
+    /// Gas needed after the action request is received
+    pub(crate) fn user_action_gas(&self, action: &UserAction) -> Gas {
+        let gas = GAS_BIP322_VERIFY + GAS_BIP322_VERIFY_CB;
+        if self.needs_btc_tip_height(action) {
+            gas + GAS_LIGHT_CLIENT_GET_TIP + GAS_GET_BTC_TIP_CB
+        } else {
+            gas
+        }
+    }
+
+    /// Whether the withdrawal is only not ready because of the synced BTC tip height,
+    /// i.e. fixed-term deposits spent are not matured or withdrawal tickets have not waited
+    /// for enough BTC blocks. The synced height could only be behind the current one
+    fn is_btc_tip_behind(
+        &self,
+        account: &Account,
+        psbt: &Psbt,
+        reinvest_embed_vout: Option<u64>,
+    ) -> bool {
+        let deposits_matured = filter_deposit_inputs(account, &psbt.unsigned_tx.input)
+            .iter()
+            .all(|input| {
+                account
+                    .get_active_deposit(
+                        &input.previous_output.txid.to_string().into(),
+                        input.previous_output.vout.into(),
+                    )
+                    .maturity_height()
+                    .map_or(true, |height| self.btc_tip_height >= height as u64)
+            });
+        if !deposits_matured {
+            return true;
+        }
+
+        // replacements of pending sign PSBTs do not take any amount from withdrawal tickets
+        if self.withdrawal_waiting_btc_blocks.is_none()
+            || account
+                .find_pending_sign_psbt(&psbt.unsigned_tx.input)
+                .is_some()
+        {
+            return false;
+        }
+        let withdraw_amount = self.pending_sign_request_amount(account, psbt, reinvest_embed_vout);
+        let is_ready = |btc_tip_height: u64| {
+            let waiting_time = WithdrawalWaitingTime {
+                btc_tip_height,
+                ..self.withdrawal_waiting_time()
+            };
+            let matured_amount = account.matured_withdrawal_amount(&waiting_time);
+            matured_amount > 0 && withdraw_amount <= matured_amount
+        };
+        // no need to fetch the current tip if tickets waiting in ms are not ready either
+        !is_ready(self.btc_tip_height) && is_ready(u64::MAX)
+    }
+
+    /// Run the checks of `request_withdrawal_signature` that do not depend on the BTC tip height,
+    /// before the current tip is fetched. The maturity of deposits and withdrawal tickets is
+    /// checked again in the callback, which refunds the attached deposit if they are not ready
+    #[allow(clippy::too_many_arguments)]
+    fn verify_sign_withdrawal_without_btc_tip(
+        &self,
+        account: &Account,
+        psbt: &Psbt,
+        psbt_len: usize,
+        user_pubkey: &str,
+        vin_to_sign: u64,
+        reinvest_embed_vout: Option<u64>,
+        storage_deposit: Option<U128>,
+        attached_deposit: Balance,
+    ) {
+        account.assert_not_frozen();
+
+        let input_to_sign = psbt.unsigned_tx.input.get(vin_to_sign as usize).unwrap();
+        account.get_active_deposit(
+            &input_to_sign.previous_output.txid.to_string().into(),
+            input_to_sign.previous_output.vout.into(),
+        );
+        self.verify_psbt_deposit_inputs(account, psbt);
+        verify_pending_sign_partial_sig(psbt, vin_to_sign, user_pubkey);
+        PublicKey::from_str(user_pubkey).expect(ERR_BAD_PUBKEY_HEX);
+
+        if let Some(pending_sign_psbt) = account.find_pending_sign_psbt(&psbt.unsigned_tx.input) {
+            verify_sign_withdrawal_psbt(&pending_sign_psbt, psbt);
+            verify_withdrawal_destinations(
+                account.withdrawal_policy(),
+                psbt,
+                &[
+                    pending_sign_psbt.reinvest_deposit_vout,
+                    pending_sign_psbt.reinvest_embed_vout,
+                ],
+            );
+            if !pending_sign_psbt.bithive_signed {
+                account.verify_daily_withdrawal(pending_sign_psbt.withdraw_amount);
+            }
+        } else {
+            let withdraw_amount =
+                self.pending_sign_request_amount(account, psbt, reinvest_embed_vout);
+            let reinvest_deposit_vout =
+                reinvest_embed_vout.map(|embed_vout| self.reinvest_deposit_vout(psbt, embed_vout));
+            verify_withdrawal_destinations(
+                account.withdrawal_policy(),
+                psbt,
+                &[reinvest_deposit_vout, reinvest_embed_vout],
+            );
+            account.verify_daily_withdrawal(withdraw_amount);
+            pending_sign_storage_deposit(
+                account,
+                psbt,
+                psbt_len,
+                storage_deposit,
+                attached_deposit,
+            );
+        }
+    }
+
+    /// Panics if the pending sign PSBT could not be discarded as expired
+    fn assert_pending_sign_psbt_expired(&self, account: &Account, tx_id: &TxId) {
+        let pending_sign_psbt = account
+            .get_pending_sign_psbt(tx_id)
+            .expect(ERR_PENDING_SIGN_PSBT_NOT_FOUND);
+        require!(
+            current_timestamp_ms()
+                >= pending_sign_psbt.sign_start_ts + self.pending_sign_psbt_expiry_ms,
+            ERR_PENDING_SIGN_PSBT_NOT_EXPIRED
+        );
+        require!(
+            !pending_sign_psbt.bithive_signed,
+            ERR_PENDING_SIGN_PSBT_SIGNED
+        );
+    }
+
+    /// Discard an expired pending sign PSBT, `btc_tip_height` is recorded by the restored
+    /// withdrawal ticket if waiting time is measured in BTC blocks
+    fn expire_pending_sign_psbt(
+        &mut self,
+        user_pubkey: String,
+        tx_id: TxId,
+        btc_tip_height: Option<u64>,
+    ) {
+        let mut account = self.get_account(&user_pubkey.into());
+        // the PSBT could have been signed or replaced while fetching the BTC tip height
+        self.assert_pending_sign_psbt_expired(&account, &tx_id);
+        let pending_sign_psbt = account.expire_pending_sign_psbt(
+            &tx_id,
+            &self.withdrawal_waiting_time(),
+            btc_tip_height,
+        );
+        refund_pending_sign_storage(&pending_sign_psbt);
+        self.set_account(account);
+    }
+
+    /// Waiting time of withdrawal tickets, the tier of each ticket is fixed when it is queued
+    pub(crate) fn withdrawal_waiting_time(&self) -> WithdrawalWaitingTime {
+        WithdrawalWaitingTime {
+            ms: self.withdrawal_waiting_time_ms,
+            tiers: self.withdrawal_waiting_tiers.clone(),
+            btc_blocks: self.withdrawal_waiting_btc_blocks,
+            btc_tip_height: self.btc_tip_height,
+        }
+    }
+
+    /// Whether the amount could be cosigned without exceeding the withdrawal rate limit
+    pub(crate) fn is_within_withdrawal_rate_limit(&self, amount: u64) -> bool {
+        self.withdrawal_rate_limit.as_ref().map_or(true, |limit| {
+            limit.can_consume(amount, current_timestamp_ms())
+        })
+    }
+
+    /// Record the amount to be cosigned in the withdrawal rate limit, which has been checked.
+    /// Returns the timestamp of the record if there is a limit, which is needed to release it
+    pub(crate) fn record_withdrawal_rate_limit(&mut self, amount: u64) -> Option<Timestamp> {
+        self.withdrawal_rate_limit.as_mut().map(|limit| {
+            limit
+                .try_consume(amount, current_timestamp_ms())
+                .expect(ERR_WITHDRAWAL_RATE_LIMIT_EXCEEDED)
+        })
+    }
+
+    fn emit_withdrawal_rate_limit_exceeded(&self, user_pubkey: &str, amount: u64) {
+        if let Some(limit) = self.withdrawal_rate_limit.as_ref() {
+            Event::WithdrawalRateLimitExceeded {
+                user_pubkey: user_pubkey.to_string(),
+                amount: amount.into(),
+                used_amount: limit.used_amount(current_timestamp_ms()).into(),
+                max_amount: limit.max_amount.into(),
+            }
+            .emit();
+        }
+    }
+
+    /// Reject a withdrawal without panicking so that the event is kept, and refund the caller
+    pub(crate) fn reject_withdrawal_over_rate_limit(
+        &self,
+        user_pubkey: &str,
+        amount: u64,
+        caller_id: AccountId,
+        attached_deposit: Balance,
+    ) {
+        self.emit_withdrawal_rate_limit_exceeded(user_pubkey, amount);
+        if attached_deposit > 0 {
+            Promise::new(caller_id).transfer(attached_deposit);
+        }
+    }
+
+    /// Verify the restrictions on a withdrawal PSBT that are enforced before cosigning:
+    /// destinations allowed by the user and maturity of fixed-term deposits, and if
+    /// `withdraw_amount` is to be counted, the daily limit of the user.
+    /// Returns false instead of panicking if the global withdrawal rate limit would be exceeded
+    pub(crate) fn verify_withdrawal_allowed(
+        &self,
+        account: &Account,
+        psbt: &Psbt,
+        reinvest_vouts: &[Option<u64>],
+        withdraw_amount: Option<u64>,
+    ) -> bool {
+        verify_withdrawal_destinations(account.withdrawal_policy(), psbt, reinvest_vouts);
+        // fixed-term deposits cannot be withdrawn before maturity
+        self.verify_deposits_matured(account, &psbt.unsigned_tx.input);
+
+        match withdraw_amount {
+            Some(amount) => {
+                account.verify_daily_withdrawal(amount);
+                self.is_within_withdrawal_rate_limit(amount)
+            }
+            None => true,
+        }
+    }
+
+    /// Forget the records of a pending sign PSBT against the daily limit of the user
+    /// and the global withdrawal rate limit, unless bithive has signed any version of it
+    fn release_withdrawal_limits(&mut self, user_pubkey: &str, tx_id: &TxId) {
+        let mut account = self.get_account(&user_pubkey.to_string().into());
+        let mut pending_sign_psbt = match account.get_pending_sign_psbt(tx_id) {
+            Some(pending_sign_psbt) if !pending_sign_psbt.bithive_signed => pending_sign_psbt,
+            _ => return,
+        };
+        let amount = pending_sign_psbt.withdraw_amount;
+        if let Some(ts) = pending_sign_psbt.daily_withdrawal_ts.take() {
+            account.release_daily_withdrawal(ts, amount);
+        }
+        if let (Some(ts), Some(limit)) = (
+            pending_sign_psbt.rate_limit_ts.take(),
+            self.withdrawal_rate_limit.as_mut(),
+        ) {
+            limit.release(ts, amount);
+        }
+        // records are released only once
+        account.remove_pending_sign_psbt(tx_id);
+        account.insert_pending_sign_psbt(pending_sign_psbt);
+        self.set_account(account);
+    }
+
+    /// Verify if every deposit input in the PSBT carries the witness UTXO, witness script
+    /// and sighash type that match the saved deposit
+    pub(crate) fn verify_psbt_deposit_inputs(&self, account: &Account, psbt: &Psbt) {
+        for (vin, (txin, input)) in psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .zip(psbt.inputs.iter())
+            .enumerate()
+        {
+            let deposit = match account.try_get_active_deposit(
+                &txin.previous_output.txid.to_string().into(),
+                txin.previous_output.vout.into(),
+            ) {
+                Some(deposit) => deposit,
+                None => continue,
+            };
+            let expected_script = self.deposit_redeem_script(&deposit);
+
+            let witness_utxo = input
+                .witness_utxo
+                .as_ref()
+                .unwrap_or_else(|| panic!("{}: vin {}", ERR_PSBT_MISSING_WITNESS_UTXO, vin));
+            require!(
+                witness_utxo.value.to_sat() == deposit.value,
+                format!("{}: vin {}", ERR_PSBT_WITNESS_UTXO_VALUE_MISMATCH, vin)
+            );
+            require!(
+                witness_utxo.script_pubkey == ScriptBuf::new_p2wsh(&expected_script.wscript_hash()),
+                format!("{}: vin {}", ERR_PSBT_WITNESS_UTXO_SCRIPT_MISMATCH, vin)
+            );
+            require!(
+                input.witness_script.as_ref() == Some(&expected_script),
+                format!("{}: vin {}", ERR_PSBT_WITNESS_SCRIPT_MISMATCH, vin)
+            );
+            // sighash type defaults to SIGHASH_ALL if not specified
+            require!(
+                matches!(input.ecdsa_hash_ty(), Ok(EcdsaSighashType::All)),
+                format!("{}: vin {}", ERR_PSBT_BAD_SIGHASH_TYPE, vin)
+            );
+        }
+    }
+
+    /// Verify that all fixed-term deposits spent by the inputs have reached their maturity height.
+    /// The synced BTC tip height is used, which `sign_withdrawal` refreshes from light client
+    /// if the deposits are not matured with it
+    pub(crate) fn verify_deposits_matured(&self, account: &Account, inputs: &[TxIn]) {
+        for input in filter_deposit_inputs(account, inputs) {
+            let deposit = account.get_active_deposit(
+                &input.previous_output.txid.to_string().into(),
+                input.previous_output.vout.into(),
+            );
+            if let Some(maturity_height) = deposit.maturity_height() {
+                require!(
+                    self.btc_tip_height >= maturity_height as u64,
+                    format!(
+                        "{}: {} matures at height {}",
+                        ERR_DEPOSIT_NOT_MATURED,
+                        deposit.id(),
+                        maturity_height
+                    )
+                );
+            }
+        }
+    }
+
+    /// Verify the signature returned by chain signatures and save it into the pending sign PSBT.
+    /// Returns false if the signature is not valid for the given input
+    pub(crate) fn save_withdrawal_signature(
+        &self,
+        account: &mut Account,
+        tx_id: &TxId,
+        vin: u64,
+        sig: &SignatureResponse,
+    ) -> bool {
+        // the PSBT might have been replaced or cancelled in the meantime
+        let mut pending_sign_psbt = match account.get_pending_sign_psbt(tx_id) {
+            Some(pending_sign_psbt) => pending_sign_psbt,
+            None => return false,
+        };
+        let psbt = pending_sign_psbt.psbt.as_mut();
+        let deposit = match psbt.unsigned_tx.input.get(vin as usize).and_then(|input| {
+            account.try_get_active_deposit(
+                &input.previous_output.txid.to_string().into(),
+                input.previous_output.vout.into(),
+            )
+        }) {
+            Some(deposit) => deposit,
+            None => return false,
+        };
+        let signature = match chain_signature_to_ecdsa(&sig.big_r.affine_point, &sig.s.scalar) {
+            Some(signature) => signature,
+            None => return false,
+        };
+
+        let (path, _) = chain_signatures_path(&deposit.redeem_version);
+        let bithive_pubkey = self.generate_btc_pubkey(path);
+        let hash_to_sign = get_hash_to_sign(psbt, vin);
+        let compact_sig = signature.serialize_compact();
+        // S value might have been normalized, so try with v = 0 and v = 1
+        let valid = [0u8, 1u8].iter().any(|v| {
+            verify_secp256k1_signature(
+                &bithive_pubkey.inner.serialize(),
+                &hash_to_sign,
+                &compact_sig,
+                *v,
+            )
+            .is_ok()
+        });
+        if !valid {
+            return false;
+        }
+
+        psbt.inputs[vin as usize].partial_sigs.insert(
+            bithive_pubkey,
+            bitcoin::ecdsa::Signature::sighash_all(signature),
+        );
+        pending_sign_psbt.bithive_signed = true;
+        account.insert_pending_sign_psbt(pending_sign_psbt);
+        true
+    }
+
+    /// Build the withdrawal txn from a PSBT whose deposit inputs are signed by both
+    /// the user and bithive, and other inputs are finalized.
+    /// Returns None if any of the inputs is not ready
+    pub(crate) fn finalize_withdrawal_psbt(
+        &self,
+        account: &Account,
+        psbt: &Psbt,
+    ) -> Option<Transaction> {
+        let user_pubkey = PublicKey::from_str(&account.pubkey.to_string()).ok()?;
+        let mut tx = psbt.unsigned_tx.clone();
+        for (vin, (txin, input)) in tx.input.iter_mut().zip(psbt.inputs.iter()).enumerate() {
+            let deposit = account.try_get_active_deposit(
+                &txin.previous_output.txid.to_string().into(),
+                txin.previous_output.vout.into(),
+            );
+            match deposit {
+                Some(deposit) => {
+                    let (path, _) = chain_signatures_path(&deposit.redeem_version);
+                    let bithive_pubkey = self.generate_btc_pubkey(path);
+                    if !has_valid_partial_sig(psbt, vin as u64, &user_pubkey)
+                        || !has_valid_partial_sig(psbt, vin as u64, &bithive_pubkey)
+                    {
+                        return None;
+                    }
+                    let user_sig = input.partial_sigs.get(&user_pubkey)?;
+                    let bithive_sig = input.partial_sigs.get(&bithive_pubkey)?;
+                    txin.witness = multisig_witness(
+                        &deposit.redeem_version,
+                        &user_sig.to_vec(),
+                        &bithive_sig.to_vec(),
+                        &self.deposit_redeem_script(&deposit),
+                    );
+                }
+                None => {
+                    if input.final_script_sig.is_none() && input.final_script_witness.is_none() {
+                        return None;
+                    }
+                    txin.script_sig = input.final_script_sig.clone().unwrap_or_default();
+                    txin.witness = input.final_script_witness.clone().unwrap_or_default();
+                }
+            }
+        }
+        Some(tx)
+    }
+
+    /// Verify if the withdrawal amount in the PSBT is valid
+    /// Returns the reinvest deposit vout if any, and the actual withdrawal amount
+    pub(crate) fn verify_pending_sign_request_amount(
+        &self,
+        account: &Account,
+        psbt: &Psbt,
+        reinvest_embed_vout: Option<u64>,
+    ) -> (Option<u64>, u64) {
+        let actual_withdraw_amount =
+            self.pending_sign_request_amount(account, psbt, reinvest_embed_vout);
+
+        // make sure waiting time of at least one ticket has passed
+        let matured_amount = account.matured_withdrawal_amount(&self.withdrawal_waiting_time());
+        require!(matured_amount > 0, ERR_WITHDRAW_NOT_READY);
+
+        // make sure the actual amount is less than or equal to the matured withdrawal amount
+        require!(
+            actual_withdraw_amount <= matured_amount,
+            ERR_BAD_WITHDRAWAL_AMOUNT
+        );
+
+        // return the reinvest deposit vout if any
+        let reinvest_deposit_vout =
+            reinvest_embed_vout.map(|embed_vout| self.reinvest_deposit_vout(psbt, embed_vout));
+        (reinvest_deposit_vout, actual_withdraw_amount)
+    }
+
+    /// Deposit vout of the reinvest embed output
+    fn reinvest_deposit_vout(&self, psbt: &Psbt, embed_vout: u64) -> u64 {
+        match self.verify_embed_output(&psbt.unsigned_tx, embed_vout) {
+            DepositEmbedMsg::V1 { deposit_vout, .. } => deposit_vout,
+            DepositEmbedMsg::V2 { deposit_vout, .. } => deposit_vout.into(),
+            DepositEmbedMsg::V3 { deposit_vout, .. } => deposit_vout,
+        }
+    }
+
+    /// Amount withdrawn by the PSBT, which is the deposit inputs minus the reinvestment
+    fn pending_sign_request_amount(
+        &self,
+        account: &Account,
+        psbt: &Psbt,
+        reinvest_embed_vout: Option<u64>,
+    ) -> u64 {
+        require!(
+            account.queue_withdrawal_amount() > 0,
+            ERR_NO_WITHDRAW_REQUESTED
+        );
+
+        // sum all known deposit inputs
+        let deposit_input_sum = filter_deposit_inputs(account, &psbt.unsigned_tx.input)
+            .iter()
+            .map(|input| {
+                let deposit = account.get_active_deposit(
+                    &input.previous_output.txid.to_string().into(),
+                    input.previous_output.vout.into(),
+                );
+                deposit.value
+            })
+            .sum::<u64>();
+
+        // subtract reinvest amount if provided
+        let reinvest_amount = reinvest_embed_vout
+            .map(|embed_vout| {
+                self.verify_reinvest_deposit(account, &psbt.unsigned_tx, embed_vout)
+                    .value
+            })
+            .unwrap_or(0);
+        deposit_input_sum - reinvest_amount
+    }
+
+    /// The reinvest output must be accepted by `submit_deposit_tx` later,
+    /// so it is checked against the deposit limits as well
+    fn verify_reinvest_deposit(
+        &self,
+        account: &Account,
+        tx: &Transaction,
+        embed_vout: u64,
+    ) -> Deposit {
+        let deposit = self.verify_deposit_txn(tx, embed_vout);
+        require!(
+            deposit.user_pubkey == account.pubkey,
+            ERR_PSBT_REINVEST_PUBKEY_MISMATCH
+        );
+        if let Err(err) = self.verify_deposit_limits(&deposit) {
+            env::panic_str(err);
+        }
+        deposit
+    }
+}
+
+impl Contract {
+    /// Build an unsigned multisig withdrawal PSBT that spends the given active deposits.
+    /// Returns the PSBT and vout of the reinvest deposit embed output if any
+    pub(crate) fn new_withdrawal_psbt(
+        &self,
+        account: &Account,
+        deposit_ids: &[String],
+        outputs: &[WithdrawalOutput],
+        fee_rate: u64,
+        reinvest_amount: Option<u64>,
+    ) -> (Psbt, Option<u64>) {
+        require!(!deposit_ids.is_empty(), ERR_NO_DEPOSIT_TO_WITHDRAW);
+        require!(
+            outputs.iter().filter(|o| o.value.is_none()).count() <= 1,
+            ERR_MULTIPLE_CHANGE_OUTPUTS
+        );
+
+        let mut deposits: Vec<Deposit> = vec![];
+        for id in deposit_ids {
+            let (tx_id, vout) = parse_output_id(id).expect(ERR_BAD_DEPOSIT_ID);
+            let deposit = account.get_active_deposit(&tx_id, vout);
+            require!(
+                deposits.iter().all(|d| d.id() != deposit.id()),
+                ERR_DUPLICATE_DEPOSIT_ID
+            );
+            deposits.push(deposit);
+        }
+        let witness_scripts: Vec<(RedeemVersion, ScriptBuf)> = deposits
+            .iter()
+            .map(|d| (d.redeem_version.clone(), self.deposit_redeem_script(d)))
+            .collect();
+
+        let mut tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: deposits
+                .iter()
+                .map(|d| TxIn {
+                    previous_output: OutPoint::new(
+                        Txid::from_str(&d.deposit_tx_id.to_string()).unwrap(),
+                        d.deposit_vout as u32,
+                    ),
+                    script_sig: ScriptBuf::new(),
+                    // multisig path has no relative timelock, keep it replaceable by fee
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![],
+        };
+
+        let mut change_vout = None;
+        for output in outputs {
+            require!(output.value != Some(0), ERR_BAD_OUTPUT_VALUE);
+            let script_pubkey =
+                ScriptBuf::from_hex(&output.script_pubkey).expect(ERR_BAD_OUTPUT_SCRIPT);
+            match output.value {
+                Some(value) => require!(
+                    Amount::from_sat(value) >= script_pubkey.minimal_non_dust(),
+                    ERR_OUTPUT_BELOW_DUST
+                ),
+                None => change_vout = Some(tx.output.len()),
+            }
+            tx.output.push(TxOut {
+                value: Amount::from_sat(output.value.unwrap_or(0)),
+                script_pubkey,
+            });
+        }
+
+        // append reinvest deposit output and its embed output
+        let reinvest_embed_vout = reinvest_amount.map(|amount| {
+            let user_pubkey =
+                PublicKey::from_str(&account.pubkey.to_string()).expect(ERR_BAD_PUBKEY_HEX);
+            let sequence_height = self.active_solo_withdrawal_seq_height();
+            let deposit_script = Self::deposit_script_v1(
+                &user_pubkey,
+                &self.generate_btc_pubkey(CHAIN_SIGNATURES_PATH_V1),
+                Sequence::from_height(sequence_height),
+            );
+            let deposit_vout = tx.output.len() as u64;
+            tx.output.push(TxOut {
+                value: Amount::from_sat(amount),
+                script_pubkey: ScriptBuf::new_p2wsh(&deposit_script.wscript_hash()),
+            });
+
+            let embed_msg = DepositEmbedMsg::V1 {
+                deposit_vout,
+                user_pubkey: user_pubkey.inner.serialize(),
+                sequence_height,
+            };
+            tx.output.push(TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptBuf::new_op_return(
+                    PushBytesBuf::try_from(embed_msg.encode()).unwrap(),
+                ),
+            });
+
+            if self.earliest_deposit_block_height > 0 {
+                tx.lock_time = LockTime::from_height(self.earliest_deposit_block_height).unwrap();
+            }
+            deposit_vout + 1
+        });
+
+        // deduct fee from the change output
+        let input_sum: u64 = deposits.iter().map(|d| d.value).sum();
+        let output_sum = tx
+            .output
+            .iter()
+            .try_fold(0u64, |sum, o| sum.checked_add(o.value.to_sat()))
+            .expect(ERR_OUTPUT_VALUE_OVERFLOW);
+        let fee = fee_rate
+            .checked_mul(estimate_multisig_withdrawal_vsize(&tx, &witness_scripts))
+            .expect(ERR_FEE_OVERFLOW);
+        require!(
+            output_sum
+                .checked_add(fee)
+                .is_some_and(|total| input_sum >= total),
+            ERR_INSUFFICIENT_INPUT_AMOUNT
+        );
+        if let Some(vout) = change_vout {
+            let change = Amount::from_sat(input_sum - output_sum - fee);
+            require!(change > Amount::ZERO, ERR_INSUFFICIENT_INPUT_AMOUNT);
+            require!(
+                change >= tx.output[vout].script_pubkey.minimal_non_dust(),
+                ERR_OUTPUT_BELOW_DUST
+            );
+            tx.output[vout].value = change;
+        }
+
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        for ((input, deposit), (_, script)) in psbt
+            .inputs
+            .iter_mut()
+            .zip(deposits.iter())
+            .zip(witness_scripts)
+        {
+            input.witness_utxo = Some(TxOut {
+                value: Amount::from_sat(deposit.value),
+                script_pubkey: ScriptBuf::new_p2wsh(&script.wscript_hash()),
+            });
+            input.witness_script = Some(script);
+            input.sighash_type = Some(EcdsaSighashType::All.into());
+        }
+
+        if let Some(embed_vout) = reinvest_embed_vout {
+            self.verify_reinvest_deposit(account, &psbt.unsigned_tx, embed_vout);
+        }
+
+        (psbt, reinvest_embed_vout)
+    }
+}
+
+/// If there is more than one input in PSBT, we charge the user for PSBT storage deposit
+/// which should cover all of the pending sign PSBTs. Returns the NEAR amount kept for storage
+fn pending_sign_storage_deposit(
+    account: &Account,
+    psbt: &Psbt,
+    psbt_len: usize,
+    storage_deposit: Option<U128>,
+    attached_deposit: Balance,
+) -> Balance {
+    if psbt.unsigned_tx.input.len() <= 1 {
+        return 0;
+    }
+    let attached_near_for_storage: Balance = storage_deposit.unwrap_or(U128::from(0)).into();
+    require!(
+        attached_deposit >= attached_near_for_storage,
+        ERR_INVALID_STORAGE_DEPOSIT
+    );
+    let storage_needed =
+        (account.pending_sign_psbts_size() + psbt_len as u64) as u128 * env::storage_byte_cost();
+    require!(
+        account.pending_sign_deposit + attached_near_for_storage >= storage_needed,
+        ERR_INSUFFICIENT_STORAGE_DEPOSIT
+    );
+    attached_near_for_storage
+}
+
+pub(crate) fn withdrawal_message(nonce: u64, amount: u64) -> String {
     format!("bithive.withdraw:{}:{}sats", nonce, amount)
 }
 
+pub(crate) fn cancel_pending_sign_psbt_message(nonce: u64, tx_id: &TxId) -> String {
+    format!("bithive.cancel_withdrawal:{}:{}", nonce, tx_id)
+}
+
+/// Make sure destination scripts are valid hex and in lowercase
+pub(crate) fn normalize_withdrawal_policy(mut policy: WithdrawalPolicy) -> WithdrawalPolicy {
+    require!(
+        policy.loosen_delay_ms <= MAX_LOOSEN_DELAY_MS,
+        ERR_LOOSEN_DELAY_TOO_LONG
+    );
+    if let Some(destinations) = policy.allowed_destinations.as_mut() {
+        for destination in destinations.iter_mut() {
+            require!(
+                hex::decode(&destination).is_ok(),
+                ERR_BAD_DESTINATION_SCRIPT
+            );
+            *destination = destination.to_lowercase();
+        }
+    }
+    policy
+}
+
+pub(crate) fn set_withdrawal_policy_message(nonce: u64, policy: &WithdrawalPolicy) -> String {
+    let daily_limit = policy
+        .daily_limit
+        .map_or("unlimited".to_string(), |limit| format!("{}sats", limit));
+    let destinations = policy
+        .allowed_destinations
+        .as_ref()
+        .map_or("any".to_string(), |destinations| destinations.join(","));
+    format!(
+        "bithive.set_withdrawal_policy:{}:{}:{}:{}ms",
+        nonce, daily_limit, destinations, policy.loosen_delay_ms
+    )
+}
+
+/// Execute the verified action, `waiting_time` and `btc_tip_height` are recorded by new withdrawal tickets
+fn execute_user_action(
+    account: &mut Account,
+    action: &UserAction,
+    waiting_time: &WithdrawalWaitingTime,
+    btc_tip_height: Option<u64>,
+    msg: Vec<u8>,
+    msg_sig: &str,
+) {
+    match action {
+        UserAction::QueueWithdrawal { amount } => {
+            account.queue_withdrawal(*amount, waiting_time, btc_tip_height, msg, msg_sig)
+        }
+        UserAction::CancelPendingSignPsbt { tx_id } => {
+            let pending_sign_psbt = account.cancel_pending_sign_psbt(
+                &tx_id.clone().into(),
+                waiting_time,
+                btc_tip_height,
+                msg,
+                msg_sig,
+            );
+            refund_pending_sign_storage(&pending_sign_psbt);
+        }
+        UserAction::SetWithdrawalPolicy { policy } => {
+            account.set_withdrawal_policy(policy, msg, msg_sig)
+        }
+        UserAction::RequestRecovery { deposit_id } => {
+            account.request_recovery(&deposit_id.clone().into(), msg, msg_sig)
+        }
+        UserAction::CancelRecovery { deposit_id } => {
+            account.cancel_recovery(&deposit_id.clone().into(), msg, msg_sig)
+        }
+    }
+}
+
+/// Verify if every output of the withdrawal txn, except the reinvestment ones,
+/// is sent to a destination allowed by the user
+fn verify_withdrawal_destinations(
+    policy: &WithdrawalPolicy,
+    psbt: &Psbt,
+    reinvest_vouts: &[Option<u64>],
+) {
+    for (vout, output) in psbt.unsigned_tx.output.iter().enumerate() {
+        if reinvest_vouts.contains(&Some(vout as u64)) {
+            continue;
+        }
+        require!(
+            policy.is_destination_allowed(&hex::encode(output.script_pubkey.as_bytes())),
+            ERR_DESTINATION_NOT_ALLOWED
+        );
+    }
+}
+
+/// Refund the storage deposit attached for a discarded pending sign PSBT
+fn refund_pending_sign_storage(pending_sign_psbt: &PendingSignPsbt) {
+    if pending_sign_psbt.storage_deposit.0 > 0 {
+        Promise::new(pending_sign_psbt.storage_payer.clone())
+            .transfer(pending_sign_psbt.storage_deposit.0);
+    }
+}
+
 /// Verify if the PSBT has a valid partial signature for the given input
 /// This is to make sure the PSBT is submitted by the user himself
 pub(crate) fn verify_pending_sign_partial_sig(psbt: &Psbt, vin_to_sign: u64, user_pubkey: &str) {
@@ -419,6 +1699,23 @@ pub(crate) fn verify_pending_sign_partial_sig(psbt: &Psbt, vin_to_sign: u64, use
         .expect(ERR_INVALID_PARTIAL_SIG);
 }
 
+/// Whether the PSBT has a valid partial signature of the given pubkey for the given input
+fn has_valid_partial_sig(psbt: &Psbt, vin: u64, pubkey: &PublicKey) -> bool {
+    let sig = match psbt
+        .inputs
+        .get(vin as usize)
+        .and_then(|input| input.partial_sigs.get(pubkey))
+    {
+        Some(sig) => sig.signature.serialize_compact(),
+        None => return false,
+    };
+    let hash_to_sign = get_hash_to_sign(psbt, vin);
+    // try with v = 0 and v = 1
+    (0..2u8).any(|v| {
+        verify_secp256k1_signature(&pubkey.inner.serialize(), &hash_to_sign, &sig, v).is_ok()
+    })
+}
+
 /// The PSBT provided must be the same or RBF of the saved withdrawal PSBT
 pub(crate) fn verify_sign_withdrawal_psbt(
     pending_sign_psbt: &PendingSignPsbt,
@@ -455,6 +1752,57 @@ pub(crate) fn verify_sign_withdrawal_psbt(
     }
 }
 
+/// Chain signatures path and key version used by each redeem version
+pub(crate) fn chain_signatures_path(redeem_version: &RedeemVersion) -> (&'static str, u32) {
+    match redeem_version {
+        RedeemVersion::V1 => (CHAIN_SIGNATURES_PATH_V1, CHAIN_SIGNATURES_KEY_VERSION_V1),
+        RedeemVersion::V2 { .. } => (CHAIN_SIGNATURES_PATH_V2, CHAIN_SIGNATURES_KEY_VERSION_V2),
+        RedeemVersion::V3 { .. } => (CHAIN_SIGNATURES_PATH_V3, CHAIN_SIGNATURES_KEY_VERSION_V3),
+    }
+}
+
+/// Witness of a deposit input spent via multisig path:
+/// `OP_0 <sig_user> <sig_bithive> <empty> <script>` for v1 and v3, and
+/// `OP_0 <sig_user> <sig_bithive> <1> <empty> <script>` for v2 which has a nested branch
+fn multisig_witness(
+    redeem_version: &RedeemVersion,
+    sig_user: &[u8],
+    sig_bithive: &[u8],
+    witness_script: &ScriptBuf,
+) -> Witness {
+    match redeem_version {
+        RedeemVersion::V1 | RedeemVersion::V3 { .. } => Witness::from_slice(&[
+            &[][..],
+            sig_user,
+            sig_bithive,
+            &[][..],
+            witness_script.as_bytes(),
+        ]),
+        RedeemVersion::V2 { .. } => Witness::from_slice(&[
+            &[][..],
+            sig_user,
+            sig_bithive,
+            &[1u8][..],
+            &[][..],
+            witness_script.as_bytes(),
+        ]),
+    }
+}
+
+/// Estimate vsize of the withdrawal txn once all deposit inputs are signed
+fn estimate_multisig_withdrawal_vsize(
+    tx: &Transaction,
+    witness_scripts: &[(RedeemVersion, ScriptBuf)],
+) -> u64 {
+    // DER encoded signature with sighash flag takes at most 73 bytes
+    let dummy_sig = [0u8; 73];
+    let mut tx = tx.clone();
+    for (input, (redeem_version, script)) in tx.input.iter_mut().zip(witness_scripts) {
+        input.witness = multisig_witness(redeem_version, &dummy_sig, &dummy_sig, script);
+    }
+    tx.vsize() as u64
+}
+
 fn filter_deposit_inputs<'a>(account: &Account, inputs: &'a [TxIn]) -> Vec<&'a TxIn> {
     inputs
         .iter()
@@ -469,19 +1817,54 @@ fn filter_deposit_inputs<'a>(account: &Account, inputs: &'a [TxIn]) -> Vec<&'a T
 
 fn is_multisig_withdrawal(deposit: &Deposit, tx_in: &TxIn) -> bool {
     match deposit.redeem_version {
-        RedeemVersion::V1 => {
+        RedeemVersion::V1 | RedeemVersion::V3 { .. } => {
             let witness = tx_in.witness.to_vec();
             // witness script should have 5 elements, and the second last one should be empty
             witness.len() == 5 && witness[witness.len() - 2].is_empty()
         }
+        RedeemVersion::V2 { .. } => {
+            let witness = tx_in.witness.to_vec();
+            // witness script should have 6 elements, and the nested branch should be selected,
+            // otherwise it's spent via guardian recovery path
+            witness.len() == 6 && witness[3] == [1u8] && witness[4].is_empty()
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use bitcoin::{hashes::Hash, Amount, OutPoint, ScriptBuf, Sequence, TxOut, Txid, Witness};
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+    use crate::tests::test_contract_instance;
+    use timelock::ConfigChange;
+
+    const USER_PUBKEY: &str = "02f6b15f899fac9c7dc60dcac795291c70e50c3a2ee1d5070dee0d8020781584e5";
+    const DEPOSIT_TX_ID: &str = "253b73f1450d6be67a16e46d05f62235f1728d737d9540f12b69f84f4cc5b595";
+
+    fn contract_with_deposits(values: &[u64]) -> Contract {
+        let mut contract = test_contract_instance();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        for (vout, value) in values.iter().enumerate() {
+            account.create_deposit(Deposit::new(
+                USER_PUBKEY.to_string().into(),
+                RedeemVersion::V1,
+                DEPOSIT_TX_ID.to_string().into(),
+                vout as u64,
+                *value,
+                5,
+            ));
+        }
+        contract.set_account(account);
+        contract
+    }
 
-    use super::*;
+    fn p2wpkh_output(value: Option<u64>) -> WithdrawalOutput {
+        WithdrawalOutput {
+            script_pubkey: "0014f6064f024b21637d7fc244081d7839dbc452d2fe".to_string(),
+            value,
+        }
+    }
 
     fn test_psbt(inputs: Vec<TxIn>, outputs: Vec<TxOut>) -> Psbt {
         let tx = Transaction {
@@ -530,8 +1913,15 @@ mod tests {
     fn test_verify_sign_withdrawal_psbt_wrong_input_len() {
         let pending_sign_psbt = PendingSignPsbt {
             psbt: test_psbt(vec![test_input1(), test_input2()], vec![]).into(),
+            withdraw_amount: 0,
             reinvest_deposit_vout: None,
             reinvest_embed_vout: None,
+            sign_start_ts: 0,
+            storage_deposit: 0.into(),
+            storage_payer: AccountId::new_unchecked("alice".to_string()),
+            bithive_signed: false,
+            daily_withdrawal_ts: None,
+            rate_limit_ts: None,
         };
         let request_psbt = test_psbt(vec![test_input1()], vec![]);
         verify_sign_withdrawal_psbt(&pending_sign_psbt, &request_psbt);
@@ -542,8 +1932,15 @@ mod tests {
     fn test_verify_sign_withdrawal_psbt_wrong_input() {
         let pending_sign_psbt = PendingSignPsbt {
             psbt: test_psbt(vec![test_input1()], vec![]).into(),
+            withdraw_amount: 0,
             reinvest_deposit_vout: None,
             reinvest_embed_vout: None,
+            sign_start_ts: 0,
+            storage_deposit: 0.into(),
+            storage_payer: AccountId::new_unchecked("alice".to_string()),
+            bithive_signed: false,
+            daily_withdrawal_ts: None,
+            rate_limit_ts: None,
         };
         let request_psbt = test_psbt(vec![test_input2()], vec![]);
         verify_sign_withdrawal_psbt(&pending_sign_psbt, &request_psbt);
@@ -554,10 +1951,1109 @@ mod tests {
     fn test_verify_sign_withdrawal_psbt_wrong_reinvest_output() {
         let pending_sign_psbt = PendingSignPsbt {
             psbt: test_psbt(vec![test_input1(), test_input2()], vec![test_output1()]).into(),
+            withdraw_amount: 0,
             reinvest_deposit_vout: Some(0),
             reinvest_embed_vout: Some(1),
+            sign_start_ts: 0,
+            storage_deposit: 0.into(),
+            storage_payer: AccountId::new_unchecked("alice".to_string()),
+            bithive_signed: false,
+            daily_withdrawal_ts: None,
+            rate_limit_ts: None,
         };
         let request_psbt = test_psbt(vec![test_input1(), test_input2()], vec![test_output2()]);
         verify_sign_withdrawal_psbt(&pending_sign_psbt, &request_psbt);
     }
+
+    #[test]
+    fn test_build_withdrawal_psbt() {
+        let contract = contract_with_deposits(&[10000, 20000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let deposit_ids = vec![
+            format!("{}:0", DEPOSIT_TX_ID),
+            format!("{}:1", DEPOSIT_TX_ID),
+        ];
+        let (psbt, reinvest_embed_vout) = contract.new_withdrawal_psbt(
+            &account,
+            &deposit_ids,
+            &[p2wpkh_output(Some(5000)), p2wpkh_output(None)],
+            2,
+            None,
+        );
+
+        assert_eq!(reinvest_embed_vout, None);
+        assert_eq!(psbt.unsigned_tx.input.len(), 2);
+        for (vin, input) in psbt.inputs.iter().enumerate() {
+            let deposit = account.get_active_deposit(&DEPOSIT_TX_ID.to_string().into(), vin as u64);
+            let script = contract.deposit_redeem_script(&deposit);
+            let witness_utxo = input.witness_utxo.as_ref().unwrap();
+            assert_eq!(witness_utxo.value.to_sat(), deposit.value);
+            assert_eq!(
+                witness_utxo.script_pubkey,
+                ScriptBuf::new_p2wsh(&script.wscript_hash())
+            );
+            assert_eq!(input.witness_script, Some(script));
+            assert_eq!(input.sighash_type, Some(EcdsaSighashType::All.into()));
+            assert_eq!(
+                psbt.unsigned_tx.input[vin].sequence,
+                Sequence::ENABLE_RBF_NO_LOCKTIME
+            );
+            // hash to sign can be computed for every input
+            get_hash_to_sign(&psbt, vin as u64);
+        }
+
+        // change output takes the rest minus fee
+        let fee = 2 * estimate_multisig_withdrawal_vsize(
+            &psbt.unsigned_tx,
+            &psbt
+                .inputs
+                .iter()
+                .map(|i| (RedeemVersion::V1, i.witness_script.clone().unwrap()))
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            psbt.unsigned_tx.output[1].value.to_sat(),
+            30000 - 5000 - fee
+        );
+    }
+
+    #[test]
+    fn test_build_withdrawal_psbt_with_reinvest() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let (psbt, reinvest_embed_vout) = contract.new_withdrawal_psbt(
+            &account,
+            &[format!("{}:0", DEPOSIT_TX_ID)],
+            &[p2wpkh_output(None)],
+            1,
+            Some(4000),
+        );
+
+        assert_eq!(reinvest_embed_vout, Some(2));
+        let deposit = contract.verify_deposit_txn(&psbt.unsigned_tx, 2);
+        assert_eq!(deposit.user_pubkey, account.pubkey);
+        assert_eq!(deposit.deposit_vout, 1);
+        assert_eq!(deposit.value, 4000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Deposit amount is less than minimum deposit amount")]
+    fn test_build_withdrawal_psbt_reinvest_below_min_deposit() {
+        let mut contract = contract_with_deposits(&[10000]);
+        contract.min_deposit_satoshi = 5000;
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        contract.new_withdrawal_psbt(
+            &account,
+            &[format!("{}:0", DEPOSIT_TX_ID)],
+            &[p2wpkh_output(None)],
+            1,
+            Some(4000),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Deposit amount is less than minimum deposit amount")]
+    fn test_verify_reinvest_deposit_below_min_deposit() {
+        let mut contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let (psbt, reinvest_embed_vout) = contract.new_withdrawal_psbt(
+            &account,
+            &[format!("{}:0", DEPOSIT_TX_ID)],
+            &[p2wpkh_output(None)],
+            1,
+            Some(4000),
+        );
+
+        // the minimum is raised after the PSBT is built
+        contract.min_deposit_satoshi = 5000;
+        contract.verify_reinvest_deposit(&account, &psbt.unsigned_tx, reinvest_embed_vout.unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "Deposit is not active")]
+    fn test_build_withdrawal_psbt_inactive_deposit() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        contract.new_withdrawal_psbt(
+            &account,
+            &[format!("{}:1", DEPOSIT_TX_ID)],
+            &[p2wpkh_output(None)],
+            1,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Deposit amount is not enough to cover outputs and fee")]
+    fn test_build_withdrawal_psbt_insufficient_amount() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        contract.new_withdrawal_psbt(
+            &account,
+            &[format!("{}:0", DEPOSIT_TX_ID)],
+            &[p2wpkh_output(Some(10000))],
+            1,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Output value is below dust limit")]
+    fn test_build_withdrawal_psbt_dust_output() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        contract.new_withdrawal_psbt(
+            &account,
+            &[format!("{}:0", DEPOSIT_TX_ID)],
+            &[p2wpkh_output(Some(100)), p2wpkh_output(None)],
+            1,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Output value is below dust limit")]
+    fn test_build_withdrawal_psbt_dust_change() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        // leave only a few sats for the change output after paying the fee
+        contract.new_withdrawal_psbt(
+            &account,
+            &[format!("{}:0", DEPOSIT_TX_ID)],
+            &[p2wpkh_output(Some(9700)), p2wpkh_output(None)],
+            1,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Fee overflows, fee rate is too high")]
+    fn test_build_withdrawal_psbt_fee_overflow() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        contract.new_withdrawal_psbt(
+            &account,
+            &[format!("{}:0", DEPOSIT_TX_ID)],
+            &[p2wpkh_output(None)],
+            u64::MAX,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Total output value overflows")]
+    fn test_build_withdrawal_psbt_output_value_overflow() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        contract.new_withdrawal_psbt(
+            &account,
+            &[format!("{}:0", DEPOSIT_TX_ID)],
+            &[p2wpkh_output(Some(u64::MAX)), p2wpkh_output(Some(1000))],
+            1,
+            None,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "No solo withdrawal seq height configured")]
+    fn test_build_withdrawal_psbt_reinvest_without_seq_height() {
+        let mut contract = contract_with_deposits(&[10000]);
+        contract.solo_withdrawal_seq_heights = vec![];
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        contract.new_withdrawal_psbt(
+            &account,
+            &[format!("{}:0", DEPOSIT_TX_ID)],
+            &[p2wpkh_output(None)],
+            1,
+            Some(4000),
+        );
+    }
+
+    fn test_withdrawal_psbt(contract: &Contract) -> Psbt {
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        contract
+            .new_withdrawal_psbt(
+                &account,
+                &[format!("{}:0", DEPOSIT_TX_ID)],
+                &[p2wpkh_output(None)],
+                1,
+                None,
+            )
+            .0
+    }
+
+    #[test]
+    fn test_verify_psbt_deposit_inputs() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let mut psbt = test_withdrawal_psbt(&contract);
+        contract.verify_psbt_deposit_inputs(&account, &psbt);
+
+        // sighash type could be omitted
+        psbt.inputs[0].sighash_type = None;
+        contract.verify_psbt_deposit_inputs(&account, &psbt);
+    }
+
+    #[test]
+    #[should_panic(expected = "PSBT input missing witness UTXO: vin 0")]
+    fn test_verify_psbt_deposit_inputs_missing_witness_utxo() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let mut psbt = test_withdrawal_psbt(&contract);
+        psbt.inputs[0].witness_utxo = None;
+        contract.verify_psbt_deposit_inputs(&account, &psbt);
+    }
+
+    #[test]
+    #[should_panic(expected = "PSBT input witness UTXO value mismatch: vin 0")]
+    fn test_verify_psbt_deposit_inputs_wrong_value() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let mut psbt = test_withdrawal_psbt(&contract);
+        psbt.inputs[0].witness_utxo.as_mut().unwrap().value = Amount::from_sat(20000);
+        contract.verify_psbt_deposit_inputs(&account, &psbt);
+    }
+
+    #[test]
+    #[should_panic(expected = "PSBT input witness UTXO script mismatch: vin 0")]
+    fn test_verify_psbt_deposit_inputs_wrong_utxo_script() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let mut psbt = test_withdrawal_psbt(&contract);
+        psbt.inputs[0].witness_utxo.as_mut().unwrap().script_pubkey = ScriptBuf::new();
+        contract.verify_psbt_deposit_inputs(&account, &psbt);
+    }
+
+    #[test]
+    #[should_panic(expected = "PSBT input witness script mismatch: vin 0")]
+    fn test_verify_psbt_deposit_inputs_wrong_witness_script() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let mut psbt = test_withdrawal_psbt(&contract);
+        psbt.inputs[0].witness_script = None;
+        contract.verify_psbt_deposit_inputs(&account, &psbt);
+    }
+
+    #[test]
+    #[should_panic(expected = "PSBT input sighash type must be SIGHASH_ALL: vin 0")]
+    fn test_verify_psbt_deposit_inputs_wrong_sighash_type() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let mut psbt = test_withdrawal_psbt(&contract);
+        psbt.inputs[0].sighash_type = Some(EcdsaSighashType::SinglePlusAnyoneCanPay.into());
+        contract.verify_psbt_deposit_inputs(&account, &psbt);
+    }
+
+    fn test_ecdsa_sig() -> bitcoin::ecdsa::Signature {
+        bitcoin::ecdsa::Signature::sighash_all(
+            chain_signature_to_ecdsa(
+                "02E14D22E30DF1F02A3C46C52EB2B999AB009600FA945CACD3242AD66480E26EA7",
+                "7E7ADD7EF49E871C41EDF56BDF5C93B44E21A83CD55FA656318A1F0E6CD17CE9",
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_pending_sign_psbt_update() {
+        let contract = contract_with_deposits(&[10000]);
+        let user_pubkey = PublicKey::from_str(USER_PUBKEY).unwrap();
+        let bithive_pubkey = contract.generate_btc_pubkey(CHAIN_SIGNATURES_PATH_V1);
+
+        // only user partial sigs are taken from the request
+        let mut psbt = test_withdrawal_psbt(&contract);
+        psbt.inputs[0]
+            .partial_sigs
+            .insert(user_pubkey, test_ecdsa_sig());
+        psbt.inputs[0]
+            .partial_sigs
+            .insert(bithive_pubkey, test_ecdsa_sig());
+        let mut pending_sign_psbt = PendingSignPsbt::new(psbt.clone(), &user_pubkey, 0, None, None);
+        let partial_sigs = &pending_sign_psbt.psbt.as_ref().inputs[0].partial_sigs;
+        assert_eq!(partial_sigs.len(), 1);
+        assert!(partial_sigs.contains_key(&user_pubkey));
+
+        // signatures are kept for the same txn
+        pending_sign_psbt.psbt.as_mut().inputs[0]
+            .partial_sigs
+            .insert(bithive_pubkey, test_ecdsa_sig());
+        pending_sign_psbt.update_psbt(&psbt, &user_pubkey);
+        assert_eq!(
+            pending_sign_psbt.psbt.as_ref().inputs[0].partial_sigs.len(),
+            2
+        );
+
+        // signatures are dropped once the txn is replaced
+        let mut rbf_psbt = psbt.clone();
+        rbf_psbt.unsigned_tx.output[0].value -= Amount::from_sat(100);
+        rbf_psbt.inputs[0].partial_sigs.clear();
+        pending_sign_psbt.update_psbt(&rbf_psbt, &user_pubkey);
+        assert_eq!(
+            pending_sign_psbt.psbt.as_ref().unsigned_tx,
+            rbf_psbt.unsigned_tx
+        );
+        assert!(pending_sign_psbt.psbt.as_ref().inputs[0]
+            .partial_sigs
+            .is_empty());
+    }
+
+    #[test]
+    fn test_save_invalid_withdrawal_signature() {
+        let contract = contract_with_deposits(&[10000]);
+        let user_pubkey = PublicKey::from_str(USER_PUBKEY).unwrap();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let psbt = test_withdrawal_psbt(&contract);
+        let pending_sign_psbt = PendingSignPsbt::new(psbt, &user_pubkey, 0, None, None);
+        let tx_id = pending_sign_psbt.tx_id();
+        account.insert_pending_sign_psbt(pending_sign_psbt);
+
+        let sig = SignatureResponse {
+            big_r: ext::BigR {
+                affine_point: "02E14D22E30DF1F02A3C46C52EB2B999AB009600FA945CACD3242AD66480E26EA7"
+                    .to_string(),
+            },
+            s: ext::S {
+                scalar: "7E7ADD7EF49E871C41EDF56BDF5C93B44E21A83CD55FA656318A1F0E6CD17CE9"
+                    .to_string(),
+            },
+            recovery_id: 0,
+        };
+        assert!(!contract.save_withdrawal_signature(&mut account, &tx_id, 0, &sig));
+        assert!(account
+            .get_pending_sign_psbt(&tx_id)
+            .unwrap()
+            .psbt
+            .as_ref()
+            .inputs[0]
+            .partial_sigs
+            .is_empty());
+    }
+
+    #[test]
+    fn test_finalize_unsigned_withdrawal_psbt() {
+        let contract = contract_with_deposits(&[10000]);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let psbt = test_withdrawal_psbt(&contract);
+        assert!(contract.finalize_withdrawal_psbt(&account, &psbt).is_none());
+    }
+
+    #[test]
+    fn test_multiple_pending_sign_psbts() {
+        let contract = contract_with_deposits(&[10000, 20000]);
+        let user_pubkey = PublicKey::from_str(USER_PUBKEY).unwrap();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let (psbt1, _) = contract.new_withdrawal_psbt(
+            &account,
+            &[format!("{}:0", DEPOSIT_TX_ID)],
+            &[p2wpkh_output(None)],
+            1,
+            None,
+        );
+        let (psbt2, _) = contract.new_withdrawal_psbt(
+            &account,
+            &[format!("{}:1", DEPOSIT_TX_ID)],
+            &[p2wpkh_output(None)],
+            1,
+            None,
+        );
+        account.insert_pending_sign_psbt(PendingSignPsbt::new(
+            psbt1.clone(),
+            &user_pubkey,
+            10000,
+            None,
+            None,
+        ));
+        account.insert_pending_sign_psbt(PendingSignPsbt::new(
+            psbt2.clone(),
+            &user_pubkey,
+            20000,
+            None,
+            None,
+        ));
+        assert_eq!(account.pending_sign_psbts_len(), 2);
+        assert_eq!(account.pending_sign_withdraw_amount(), 30000);
+
+        // each PSBT is found by the deposits it spends
+        let found = account
+            .find_pending_sign_psbt(&psbt2.unsigned_tx.input)
+            .unwrap();
+        assert_eq!(
+            found.tx_id(),
+            psbt2.unsigned_tx.compute_txid().to_string().into()
+        );
+
+        // cancelling puts the amount back to the queue
+        account.cancel_pending_sign_psbt(
+            &found.tx_id(),
+            &contract.withdrawal_waiting_time(),
+            None,
+            vec![],
+            "",
+        );
+        assert_eq!(account.pending_sign_psbts_len(), 1);
+        assert_eq!(account.queue_withdrawal_amount(), 20000);
+        assert_eq!(account.nonce, 1);
+
+        // spending the deposits clears the PSBT
+        account.clear_pending_sign_psbts(&psbt1.unsigned_tx.input);
+        assert_eq!(account.pending_sign_psbts_len(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Pending sign PSBT already signed by bithive")]
+    fn test_cancel_signed_pending_sign_psbt() {
+        let contract = contract_with_deposits(&[10000]);
+        let user_pubkey = PublicKey::from_str(USER_PUBKEY).unwrap();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let mut pending_sign_psbt = PendingSignPsbt::new(
+            test_withdrawal_psbt(&contract),
+            &user_pubkey,
+            100,
+            None,
+            None,
+        );
+        pending_sign_psbt.bithive_signed = true;
+        let tx_id = pending_sign_psbt.tx_id();
+        account.insert_pending_sign_psbt(pending_sign_psbt);
+
+        account.cancel_pending_sign_psbt(
+            &tx_id,
+            &contract.withdrawal_waiting_time(),
+            None,
+            vec![],
+            "",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Pending sign PSBT not expired yet")]
+    fn test_discard_pending_sign_psbt_not_expired() {
+        let mut contract = contract_with_deposits(&[10000]);
+        contract.pending_sign_psbt_expiry_ms = 1000;
+        let user_pubkey = PublicKey::from_str(USER_PUBKEY).unwrap();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let pending_sign_psbt = PendingSignPsbt::new(
+            test_withdrawal_psbt(&contract),
+            &user_pubkey,
+            100,
+            None,
+            None,
+        );
+        let tx_id = pending_sign_psbt.tx_id();
+        account.insert_pending_sign_psbt(pending_sign_psbt);
+        contract.set_account(account);
+
+        contract.discard_expired_pending_sign_psbt(USER_PUBKEY.to_string(), tx_id.into());
+    }
+
+    #[test]
+    fn test_discard_expired_pending_sign_psbt() {
+        let mut contract = contract_with_deposits(&[10000]);
+        let user_pubkey = PublicKey::from_str(USER_PUBKEY).unwrap();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let pending_sign_psbt = PendingSignPsbt::new(
+            test_withdrawal_psbt(&contract),
+            &user_pubkey,
+            100,
+            None,
+            None,
+        );
+        let tx_id = pending_sign_psbt.tx_id();
+        account.insert_pending_sign_psbt(pending_sign_psbt);
+        contract.set_account(account);
+
+        set_block_timestamp_ms(1000);
+        contract.discard_expired_pending_sign_psbt(USER_PUBKEY.to_string(), tx_id.into());
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        assert_eq!(account.pending_sign_psbts_len(), 0);
+        assert_eq!(account.queue_withdrawal_amount(), 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "Pending sign PSBT already signed by bithive")]
+    fn test_discard_expired_signed_pending_sign_psbt() {
+        let mut contract = contract_with_deposits(&[10000]);
+        let user_pubkey = PublicKey::from_str(USER_PUBKEY).unwrap();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let mut pending_sign_psbt = PendingSignPsbt::new(
+            test_withdrawal_psbt(&contract),
+            &user_pubkey,
+            100,
+            None,
+            None,
+        );
+        pending_sign_psbt.bithive_signed = true;
+        let tx_id = pending_sign_psbt.tx_id();
+        account.insert_pending_sign_psbt(pending_sign_psbt);
+        contract.set_account(account);
+
+        set_block_timestamp_ms(1000);
+        contract.discard_expired_pending_sign_psbt(USER_PUBKEY.to_string(), tx_id.into());
+    }
+
+    #[test]
+    fn test_discard_expired_pending_sign_psbt_in_btc_blocks() {
+        let mut contract = contract_with_deposits(&[10000]);
+        contract.withdrawal_waiting_btc_blocks = Some(6);
+        contract.update_btc_tip_height(100);
+        let user_pubkey = PublicKey::from_str(USER_PUBKEY).unwrap();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let pending_sign_psbt = PendingSignPsbt::new(
+            test_withdrawal_psbt(&contract),
+            &user_pubkey,
+            100,
+            None,
+            None,
+        );
+        let tx_id = pending_sign_psbt.tx_id();
+        account.insert_pending_sign_psbt(pending_sign_psbt);
+        contract.set_account(account);
+
+        set_block_timestamp_ms(1000);
+        // the restored ticket waits from the current tip instead of the synced one
+        contract.on_get_btc_tip_to_discard(
+            USER_PUBKEY.to_string(),
+            tx_id.into(),
+            Ok(BtcBlockHeader { block_height: 120 }),
+        );
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        assert_eq!(account.pending_sign_psbts_len(), 0);
+        assert_eq!(account.withdrawal_tickets()[0].start_btc_height, Some(120));
+        assert_eq!(contract.btc_tip_height, 120);
+    }
+
+    #[test]
+    fn test_btc_tip_behind() {
+        let mut contract = test_contract_instance();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        account.create_deposit(Deposit::new(
+            USER_PUBKEY.to_string().into(),
+            RedeemVersion::V3 {
+                maturity_height: 900_000,
+            },
+            DEPOSIT_TX_ID.to_string().into(),
+            0,
+            10000,
+            5,
+        ));
+        let mut input = test_input1();
+        input.previous_output = OutPoint::new(Txid::from_str(DEPOSIT_TX_ID).unwrap(), 0);
+        let psbt = test_psbt(vec![input], vec![]);
+
+        // the fixed-term deposit is not matured with the synced tip height
+        contract.update_btc_tip_height(899_999);
+        assert!(contract.is_btc_tip_behind(&account, &psbt, None));
+        contract.update_btc_tip_height(900_000);
+        assert!(!contract.is_btc_tip_behind(&account, &psbt, None));
+
+        // the withdrawal ticket has not waited for enough BTC blocks with the synced tip height
+        contract.withdrawal_waiting_btc_blocks = Some(6);
+        account.queue_withdrawal(
+            10000,
+            &contract.withdrawal_waiting_time(),
+            Some(900_000),
+            vec![],
+            "",
+        );
+        assert!(contract.is_btc_tip_behind(&account, &psbt, None));
+        contract.update_btc_tip_height(900_006);
+        assert!(!contract.is_btc_tip_behind(&account, &psbt, None));
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is frozen")]
+    fn test_verify_sign_withdrawal_without_btc_tip() {
+        let mut contract = test_contract_instance();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        account.create_deposit(Deposit::new(
+            USER_PUBKEY.to_string().into(),
+            RedeemVersion::V3 {
+                maturity_height: 900_000,
+            },
+            DEPOSIT_TX_ID.to_string().into(),
+            0,
+            10000,
+            5,
+        ));
+        account.set_freeze(Some(account::AccountFreeze {
+            reason_code: 1,
+            frozen_ts: 0,
+        }));
+        let mut input = test_input1();
+        input.previous_output = OutPoint::new(Txid::from_str(DEPOSIT_TX_ID).unwrap(), 0);
+        let psbt = test_psbt(vec![input], vec![]);
+
+        // a frozen account is rejected before the current tip is fetched
+        contract.update_btc_tip_height(899_999);
+        assert!(contract.is_btc_tip_behind(&account, &psbt, None));
+        contract.verify_sign_withdrawal_without_btc_tip(
+            &account,
+            &psbt,
+            0,
+            USER_PUBKEY,
+            0,
+            None,
+            None,
+            0,
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_tickets() {
+        let contract = contract_with_deposits(&[10000]);
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let set_timestamp_ms = |ms: u64| {
+            near_sdk::testing_env!(near_sdk::test_utils::VMContextBuilder::new()
+                .block_timestamp(ms * 1_000_000)
+                .build());
+        };
+
+        let waiting_time = WithdrawalWaitingTime {
+            ms: 1000,
+            tiers: vec![],
+            btc_blocks: None,
+            btc_tip_height: 0,
+        };
+
+        set_timestamp_ms(1000);
+        account.queue_withdrawal(3000, &waiting_time, None, vec![], "");
+        set_timestamp_ms(2000);
+        account.queue_withdrawal(2000, &waiting_time, None, vec![], "");
+        assert_eq!(account.queue_withdrawal_amount(), 5000);
+        assert_eq!(account.withdrawal_tickets()[1].nonce, 1);
+
+        // only the first ticket is matured
+        set_timestamp_ms(2500);
+        assert_eq!(account.matured_withdrawal_amount(&waiting_time), 3000);
+        account.take_matured_withdrawal_amount(1000, &waiting_time);
+        assert_eq!(account.withdrawal_tickets()[0].amount, 2000);
+        assert_eq!(account.withdrawal_tickets()[0].start_ts, 1000);
+
+        // the first ticket is used up before the second one
+        set_timestamp_ms(3000);
+        account.take_matured_withdrawal_amount(2500, &waiting_time);
+        assert_eq!(account.withdrawal_tickets().len(), 1);
+        assert_eq!(account.withdrawal_tickets()[0].amount, 1500);
+        assert_eq!(account.withdrawal_tickets()[0].nonce, 1);
+    }
+
+    #[test]
+    fn test_request_recovery_guardian_nonce() {
+        let contract = contract_with_deposits(&[10000]);
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let deposit_id = format!("{}:0", "a".repeat(64));
+        account.request_recovery(&deposit_id.clone().into(), vec![], "");
+
+        // the user nonce is left for messages signed by the user
+        assert_eq!(account.guardian_nonce, 1);
+        assert_eq!(account.nonce, 0);
+        assert_eq!(
+            UserAction::RequestRecovery { deposit_id }.nonce(&account),
+            1
+        );
+        assert_eq!(UserAction::QueueWithdrawal { amount: 1 }.nonce(&account), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is frozen")]
+    fn test_frozen_account_queue_withdrawal() {
+        let contract = contract_with_deposits(&[10000]);
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        account.queue_withdrawal(1000, &contract.withdrawal_waiting_time(), None, vec![], "");
+
+        account.set_freeze(Some(account::AccountFreeze {
+            reason_code: 1,
+            frozen_ts: 0,
+        }));
+        account.queue_withdrawal(1000, &contract.withdrawal_waiting_time(), None, vec![], "");
+    }
+
+    #[test]
+    fn test_withdrawal_tickets_in_btc_blocks() {
+        let contract = contract_with_deposits(&[10000]);
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let waiting_time = |btc_tip_height: u64| WithdrawalWaitingTime {
+            ms: 0,
+            tiers: vec![],
+            btc_blocks: Some(6),
+            btc_tip_height,
+        };
+        // queued before switching to BTC blocks
+        account.queue_withdrawal(1000, &waiting_time(0), None, vec![], "");
+        account.queue_withdrawal(2000, &waiting_time(0), Some(100), vec![], "");
+        let tickets = account.withdrawal_tickets();
+        assert_eq!(tickets[0].unlock_btc_height(&waiting_time(0)), None);
+        assert_eq!(tickets[1].unlock_btc_height(&waiting_time(0)), Some(106));
+        assert_eq!(tickets[1].unlock_ts(&waiting_time(0)), None);
+
+        assert_eq!(account.matured_withdrawal_amount(&waiting_time(105)), 1000);
+        assert_eq!(account.matured_withdrawal_amount(&waiting_time(106)), 3000);
+    }
+
+    #[test]
+    fn test_withdrawal_waiting_tiers() {
+        let mut contract = contract_with_deposits(&[10000]);
+        contract.withdrawal_waiting_time_ms = 100;
+        contract.withdrawal_waiting_tiers = vec![
+            WithdrawalWaitingTier {
+                min_amount: 1000,
+                waiting_time_ms: 200,
+            },
+            WithdrawalWaitingTier {
+                min_amount: 5000,
+                waiting_time_ms: 300,
+            },
+        ];
+
+        let waiting_time = contract.withdrawal_waiting_time();
+        assert_eq!(waiting_time.tier_ms(999), 100);
+        assert_eq!(waiting_time.tier_ms(1000), 200);
+        assert_eq!(waiting_time.tier_ms(4999), 200);
+        assert_eq!(waiting_time.tier_ms(10000), 300);
+
+        // the tier is fixed by the total outstanding amount when each ticket is queued
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        account.queue_withdrawal(600, &waiting_time, None, vec![], "");
+        account.queue_withdrawal(600, &waiting_time, None, vec![], "");
+        let tickets = account.withdrawal_tickets();
+        assert_eq!(tickets[0].unlock_ts(&waiting_time), Some(100));
+        assert_eq!(tickets[1].unlock_ts(&waiting_time), Some(200));
+        // a smaller withdrawal does not shorten the waiting time of a queued ticket
+        assert_eq!(account.matured_withdrawal_amount(&waiting_time), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "tier waiting times must be non-decreasing")]
+    fn test_set_non_monotonic_waiting_tiers() {
+        let mut contract = contract_with_deposits(&[]);
+        near_sdk::testing_env!(near_sdk::test_utils::VMContextBuilder::new()
+            .predecessor_account_id(contract.owner_id.clone())
+            .attached_deposit(1)
+            .build());
+        contract.propose_config_change(ConfigChange::WithdrawalWaitingTiers(vec![
+            WithdrawalWaitingTier {
+                min_amount: 1000,
+                waiting_time_ms: 200,
+            },
+            WithdrawalWaitingTier {
+                min_amount: 5000,
+                waiting_time_ms: 100,
+            },
+        ]));
+    }
+
+    fn set_block_timestamp_ms(ms: u64) {
+        near_sdk::testing_env!(near_sdk::test_utils::VMContextBuilder::new()
+            .block_timestamp(ms * 1_000_000)
+            .build());
+    }
+
+    fn withdrawal_policy(daily_limit: Option<u64>, loosen_delay_ms: u64) -> WithdrawalPolicy {
+        WithdrawalPolicy {
+            daily_limit,
+            allowed_destinations: None,
+            loosen_delay_ms,
+        }
+    }
+
+    #[test]
+    fn test_set_withdrawal_policy_message() {
+        let policy = WithdrawalPolicy {
+            daily_limit: Some(1000),
+            allowed_destinations: Some(vec!["0014AB".to_string(), "0014cd".to_string()]),
+            loosen_delay_ms: 100,
+        };
+        assert_eq!(
+            set_withdrawal_policy_message(3, &normalize_withdrawal_policy(policy)),
+            "bithive.set_withdrawal_policy:3:1000sats:0014ab,0014cd:100ms"
+        );
+        assert_eq!(
+            set_withdrawal_policy_message(0, &WithdrawalPolicy::default()),
+            "bithive.set_withdrawal_policy:0:unlimited:any:0ms"
+        );
+    }
+
+    #[test]
+    fn test_loosen_withdrawal_policy_after_delay() {
+        set_block_timestamp_ms(1000);
+        let contract = contract_with_deposits(&[]);
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+
+        // tightening takes effect immediately
+        account.set_withdrawal_policy(&withdrawal_policy(Some(1000), 100), vec![], "");
+        assert_eq!(account.withdrawal_policy().daily_limit, Some(1000));
+        assert!(account.pending_withdrawal_policy().is_none());
+
+        // loosening waits for the delay of the current policy
+        account.set_withdrawal_policy(&withdrawal_policy(Some(2000), 0), vec![], "");
+        assert_eq!(account.withdrawal_policy().daily_limit, Some(1000));
+        assert_eq!(
+            account.pending_withdrawal_policy().unwrap().effective_ts,
+            1100
+        );
+
+        set_block_timestamp_ms(1100);
+        assert_eq!(account.withdrawal_policy().daily_limit, Some(2000));
+        assert!(account.pending_withdrawal_policy().is_none());
+        assert_eq!(account.nonce, 2);
+    }
+
+    #[test]
+    fn test_tighten_withdrawal_policy_drops_pending_one() {
+        set_block_timestamp_ms(1000);
+        let contract = contract_with_deposits(&[]);
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+
+        account.set_withdrawal_policy(&withdrawal_policy(Some(1000), 100), vec![], "");
+        account.set_withdrawal_policy(&withdrawal_policy(None, 100), vec![], "");
+        account.set_withdrawal_policy(&withdrawal_policy(Some(500), 100), vec![], "");
+
+        set_block_timestamp_ms(1100);
+        assert_eq!(account.withdrawal_policy().daily_limit, Some(500));
+    }
+
+    #[test]
+    #[should_panic(expected = "Daily withdrawal limit exceeded")]
+    fn test_daily_withdrawal_limit() {
+        set_block_timestamp_ms(1000);
+        let contract = contract_with_deposits(&[]);
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        account.set_withdrawal_policy(&withdrawal_policy(Some(1000), 0), vec![], "");
+
+        account.record_daily_withdrawal(600);
+        account.record_daily_withdrawal(400);
+        assert_eq!(account.daily_withdrawal_amount(), 1000);
+
+        account.record_daily_withdrawal(1);
+    }
+
+    #[test]
+    fn test_release_daily_withdrawal_on_sign_failure() {
+        set_block_timestamp_ms(1000);
+        let mut contract = contract_with_deposits(&[10000]);
+        let user_pubkey = PublicKey::from_str(USER_PUBKEY).unwrap();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        account.set_withdrawal_policy(&withdrawal_policy(Some(1000), 0), vec![], "");
+        // recorded by another withdrawal
+        account.record_daily_withdrawal(400);
+        let mut pending_sign_psbt = PendingSignPsbt::new(
+            test_withdrawal_psbt(&contract),
+            &user_pubkey,
+            400,
+            None,
+            None,
+        );
+        pending_sign_psbt.daily_withdrawal_ts = account.record_daily_withdrawal(400);
+        let tx_id = pending_sign_psbt.tx_id();
+        account.insert_pending_sign_psbt(pending_sign_psbt);
+        contract.set_account(account);
+
+        let sign_failed = |contract: &mut Contract| {
+            contract.on_sign_withdrawal(
+                USER_PUBKEY.to_string(),
+                tx_id.to_string(),
+                0,
+                AccountId::new_unchecked("alice".to_string()),
+                0.into(),
+                Err(PromiseError::Failed),
+            );
+        };
+        sign_failed(&mut contract);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        assert_eq!(account.daily_withdrawal_amount(), 400);
+
+        // the record of the other withdrawal is kept if the request fails again
+        sign_failed(&mut contract);
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        assert_eq!(account.daily_withdrawal_amount(), 400);
+    }
+
+    #[test]
+    fn test_release_nothing_recorded_on_sign_failure() {
+        set_block_timestamp_ms(1000);
+        let mut contract = contract_with_deposits(&[10000]);
+        contract.withdrawal_rate_limit = Some(WithdrawalRateLimit::new(1000, 3600 * 1000));
+        let user_pubkey = PublicKey::from_str(USER_PUBKEY).unwrap();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        // requested before the limits are set
+        let pending_sign_psbt = PendingSignPsbt::new(
+            test_withdrawal_psbt(&contract),
+            &user_pubkey,
+            1000,
+            None,
+            None,
+        );
+        let tx_id = pending_sign_psbt.tx_id();
+        account.insert_pending_sign_psbt(pending_sign_psbt);
+        account.set_withdrawal_policy(&withdrawal_policy(Some(1000), 0), vec![], "");
+        account.record_daily_withdrawal(1000);
+        contract.set_account(account);
+        contract.record_withdrawal_rate_limit(1000);
+
+        contract.on_sign_withdrawal(
+            USER_PUBKEY.to_string(),
+            tx_id.to_string(),
+            0,
+            AccountId::new_unchecked("alice".to_string()),
+            0.into(),
+            Err(PromiseError::Failed),
+        );
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        assert_eq!(account.daily_withdrawal_amount(), 1000);
+        let limit = contract.withdrawal_rate_limit.as_ref().unwrap();
+        assert_eq!(limit.used_amount(1000), 1000);
+    }
+
+    #[test]
+    fn test_release_withdrawal_rate_limit_on_sign_failure() {
+        set_block_timestamp_ms(1000);
+        let mut contract = contract_with_deposits(&[10000]);
+        contract.withdrawal_rate_limit = Some(WithdrawalRateLimit::new(1000, 3600 * 1000));
+        let user_pubkey = PublicKey::from_str(USER_PUBKEY).unwrap();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let mut pending_sign_psbt = PendingSignPsbt::new(
+            test_withdrawal_psbt(&contract),
+            &user_pubkey,
+            1000,
+            None,
+            None,
+        );
+        pending_sign_psbt.rate_limit_ts = contract.record_withdrawal_rate_limit(1000);
+        assert!(!contract.is_within_withdrawal_rate_limit(1));
+        let tx_id = pending_sign_psbt.tx_id();
+        account.insert_pending_sign_psbt(pending_sign_psbt);
+        contract.set_account(account);
+
+        contract.on_sign_withdrawal(
+            USER_PUBKEY.to_string(),
+            tx_id.to_string(),
+            0,
+            AccountId::new_unchecked("alice".to_string()),
+            0.into(),
+            Err(PromiseError::Failed),
+        );
+        let limit = contract.withdrawal_rate_limit.as_ref().unwrap();
+        assert_eq!(limit.used_amount(1000), 0);
+        assert!(contract.is_within_withdrawal_rate_limit(1000));
+    }
+
+    #[test]
+    fn test_verify_withdrawal_allowed() {
+        set_block_timestamp_ms(1000);
+        let mut contract = contract_with_deposits(&[10000]);
+        contract.withdrawal_rate_limit = Some(WithdrawalRateLimit::new(1000, 3600 * 1000));
+        let account = contract.get_account(&USER_PUBKEY.to_string().into());
+        let psbt = test_withdrawal_psbt(&contract);
+
+        assert!(contract.verify_withdrawal_allowed(&account, &psbt, &[], Some(1000)));
+        // checking does not consume the rate limit
+        assert!(contract.verify_withdrawal_allowed(&account, &psbt, &[], Some(1000)));
+        assert!(!contract.verify_withdrawal_allowed(&account, &psbt, &[], Some(1001)));
+        // amounts that are not counted again are not limited
+        assert!(contract.verify_withdrawal_allowed(&account, &psbt, &[], None));
+    }
+
+    #[test]
+    #[should_panic(expected = "Daily withdrawal limit exceeded")]
+    fn test_verify_withdrawal_allowed_daily_limit() {
+        set_block_timestamp_ms(1000);
+        let contract = contract_with_deposits(&[10000]);
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        account.set_withdrawal_policy(&withdrawal_policy(Some(1000), 0), vec![], "");
+        account.record_daily_withdrawal(600);
+        let psbt = test_withdrawal_psbt(&contract);
+
+        assert!(contract.verify_withdrawal_allowed(&account, &psbt, &[], Some(400)));
+        contract.verify_withdrawal_allowed(&account, &psbt, &[], Some(401));
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawal policy loosen delay is too long")]
+    fn test_withdrawal_policy_loosen_delay_too_long() {
+        normalize_withdrawal_policy(withdrawal_policy(None, u64::MAX));
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawal destination not allowed")]
+    fn test_withdrawal_destination_not_allowed() {
+        let policy = WithdrawalPolicy {
+            daily_limit: None,
+            allowed_destinations: Some(vec!["0014ab".to_string()]),
+            loosen_delay_ms: 0,
+        };
+        let psbt = test_psbt(
+            vec![],
+            vec![
+                TxOut {
+                    value: Amount::from_sat(1000),
+                    script_pubkey: ScriptBuf::from_hex("0014cd").unwrap(),
+                },
+                TxOut {
+                    value: Amount::from_sat(1000),
+                    script_pubkey: ScriptBuf::from_hex("0014ab").unwrap(),
+                },
+            ],
+        );
+
+        // reinvestment outputs are not checked
+        verify_withdrawal_destinations(&policy, &psbt, &[Some(0), None]);
+        verify_withdrawal_destinations(&policy, &psbt, &[None, None]);
+    }
+
+    #[test]
+    fn test_v2_multisig_withdrawal_witness() {
+        let redeem_version = RedeemVersion::V2 {
+            guardian_pubkey: USER_PUBKEY.to_string(),
+            recovery_sequence: 100,
+        };
+        let deposit = Deposit::new(
+            USER_PUBKEY.to_string().into(),
+            redeem_version.clone(),
+            DEPOSIT_TX_ID.to_string().into(),
+            0,
+            100,
+            5,
+        );
+        let script = ScriptBuf::from_bytes(vec![1, 2, 3]);
+        let sig = [0u8; 72];
+
+        // spent via user + bithive multisig branch
+        let mut input = test_input1();
+        input.witness = multisig_witness(&redeem_version, &sig, &sig, &script);
+        assert!(is_multisig_withdrawal(&deposit, &input));
+
+        // spent via guardian recovery branch
+        input.witness =
+            Witness::from_slice(&[&[][..], &sig, &sig, &[][..], &[][..], script.as_bytes()]);
+        assert!(!is_multisig_withdrawal(&deposit, &input));
+
+        // spent via solo withdrawal branch
+        input.witness = Witness::from_slice(&[&sig[..], &[1u8][..], script.as_bytes()]);
+        assert!(!is_multisig_withdrawal(&deposit, &input));
+    }
+
+    #[test]
+    #[should_panic(expected = "Deposit not matured yet")]
+    fn test_fixed_term_deposit_maturity() {
+        let mut contract = test_contract_instance();
+        let mut account = contract.get_account(&USER_PUBKEY.to_string().into());
+        account.create_deposit(Deposit::new(
+            USER_PUBKEY.to_string().into(),
+            RedeemVersion::V3 {
+                maturity_height: 900_000,
+            },
+            DEPOSIT_TX_ID.to_string().into(),
+            0,
+            100,
+            5,
+        ));
+        let mut input = test_input1();
+        input.previous_output = OutPoint::new(Txid::from_str(DEPOSIT_TX_ID).unwrap(), 0);
+
+        contract.update_btc_tip_height(900_000);
+        contract.verify_deposits_matured(&account, &[input.clone()]);
+
+        contract.btc_tip_height = 899_999;
+        contract.verify_deposits_matured(&account, &[input]);
+    }
 }
@@ -7,13 +7,16 @@ use std::str::FromStr;
 
 #[near_bindgen]
 #[derive(BorshSerialize, BorshDeserialize, Default)]
-pub struct Contract {}
+pub struct Contract {
+    /// height of the main chain tip served to tests
+    last_block_height: u64,
+}
 
 #[near_bindgen]
 impl Contract {
     #[init]
     pub fn init() -> Self {
-        Self {}
+        Self::default()
     }
 
     #[allow(unused_variables)]
@@ -21,6 +24,25 @@ impl Contract {
         // dummy return based on tx_index
         args.tx_index != 0
     }
+
+    pub fn set_last_block_height(&mut self, height: u64) {
+        self.last_block_height = height;
+    }
+
+    pub fn get_last_block_header(&self) -> ExtendedHeader {
+        ExtendedHeader {
+            block_hash: H256::default(),
+            block_height: self.last_block_height,
+        }
+    }
+}
+
+/// Only the fields used by bithive are served
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ExtendedHeader {
+    pub block_hash: H256,
+    pub block_height: u64,
 }
 
 #[derive(BorshDeserialize, BorshSerialize)]
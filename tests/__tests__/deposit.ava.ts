@@ -1,10 +1,23 @@
 import * as bitcoin from "bitcoinjs-lib";
 import {
   accountsLen,
+  addToDenylist,
+  addToDepositAllowlist,
+  depositAllowlistLen,
+  getSummary,
   getUserActiveDepositsLen,
+  grantRole,
+  isPubkeyDenied,
   listAccounts,
+  listDepositAllowlist,
   listUserActiveDeposits,
+  removeFromDenylist,
+  removeFromDepositAllowlist,
+  setDepositMode,
   setEarliestDepositBlockHeight,
+  setMaxDepositSatoshi,
+  setMaxTotalDepositSatoshi,
+  setSequenceHeightDepositLimits,
   submitDepositTx,
   viewAccount,
 } from "./helpers/bithive";
@@ -39,16 +52,18 @@ test("submit valid deposit txn", async (t) => {
   t.is(activeDeposits[0].deposit_vout, 0);
   t.is(activeDeposits[0].value, builder.depositAmount);
   t.is(activeDeposits[0].sequence, builder.sequence);
+  t.is(activeDeposits[0].stake_weight_bps, 10000);
   t.is(activeDeposits[0].complete_withdrawal_ts, 0);
   t.is(activeDeposits[0].withdrawal_tx_id, null);
 
   const account = await viewAccount(contract, builder.userPubkeyHex);
   t.is(account.pubkey, builder.userPubkeyHex);
   t.is(account.total_deposit, builder.depositAmount);
+  t.is(account.total_weighted_deposit, builder.depositAmount);
   t.is(account.queue_withdrawal_amount, 0);
-  t.is(account.queue_withdrawal_start_ts, 0);
+  t.deepEqual(account.withdrawal_tickets, []);
   t.is(account.nonce, 0);
-  t.is(account.pending_sign_psbt, null);
+  t.is(account.pending_sign_psbts_len, 0);
 });
 
 test("submit invalid embed msg", async (t) => {
@@ -171,6 +186,98 @@ test("submit deposit txn with too small deposit amount", async (t) => {
   t.is(await getUserActiveDepositsLen(contract, builder.userPubkeyHex), 0);
 });
 
+test("submit deposit txn exceeding deposit caps", async (t) => {
+  const { contract, owner, alice } = t.context.accounts;
+
+  const builder = new TestTransactionBuilder(contract, alice, {
+    userKeyPair: t.context.aliceKeyPair,
+    bithivePubkey: t.context.bithivePubkey,
+    depositAmount: 1e8,
+  });
+
+  await setMaxDepositSatoshi(contract, owner, 1e8 - 1);
+  await assertFailure(
+    t,
+    builder.submit(),
+    "Deposit amount is more than maximum deposit amount",
+  );
+
+  // limits of the sequence height override the default ones
+  await setSequenceHeightDepositLimits(contract, owner, 5, {
+    min_deposit_satoshi: 0,
+    max_deposit_satoshi: null,
+    max_user_deposit_satoshi: 1e8 - 1,
+  });
+  await assertFailure(t, builder.submit(), "User deposit cap exceeded");
+
+  await setSequenceHeightDepositLimits(contract, owner, 5, null);
+  await setMaxDepositSatoshi(contract, owner, null);
+  await setMaxTotalDepositSatoshi(contract, owner, 1e8 - 1);
+  await assertFailure(t, builder.submit(), "Total deposit cap exceeded");
+
+  await setMaxTotalDepositSatoshi(contract, owner, 1e8);
+  await builder.submit();
+  t.is(await getUserActiveDepositsLen(contract, builder.userPubkeyHex), 1);
+  t.is((await getSummary(contract)).total_deposit, 1e8);
+});
+
+test("submit deposit txn in allowlist mode", async (t) => {
+  const { contract, owner, alice } = t.context.accounts;
+
+  const builder = new TestTransactionBuilder(contract, alice, {
+    userKeyPair: t.context.aliceKeyPair,
+    bithivePubkey: t.context.bithivePubkey,
+  });
+
+  await setDepositMode(contract, owner, "Closed");
+  t.is((await getSummary(contract)).deposit_mode, "Closed");
+  await assertFailure(t, builder.submit(), "Deposit is closed");
+
+  await setDepositMode(contract, owner, "Allowlist");
+  await assertFailure(t, builder.submit(), "User is not allowed to deposit");
+
+  const bobPubkey = t.context.bobKeyPair.publicKey.toString("hex");
+  await addToDepositAllowlist(contract, owner, [
+    builder.userPubkeyHex,
+    bobPubkey,
+  ]);
+  t.is(await depositAllowlistLen(contract), 2);
+  t.deepEqual(await listDepositAllowlist(contract, 1, 1), [bobPubkey]);
+
+  await builder.submit();
+  t.is(await getUserActiveDepositsLen(contract, builder.userPubkeyHex), 1);
+
+  await removeFromDepositAllowlist(contract, owner, [bobPubkey]);
+  t.deepEqual(await listDepositAllowlist(contract, 0, 10), [
+    builder.userPubkeyHex,
+  ]);
+});
+
+test("submit deposit txn from denied pubkey", async (t) => {
+  const { contract, owner, alice, bob } = t.context.accounts;
+
+  const builder = new TestTransactionBuilder(contract, alice, {
+    userKeyPair: t.context.aliceKeyPair,
+    bithivePubkey: t.context.bithivePubkey,
+  });
+
+  // only the owner or compliance role could manage the denylist
+  await assertFailure(
+    t,
+    addToDenylist(contract, bob, [builder.userPubkeyHex], []),
+    "Missing role",
+  );
+  await grantRole(contract, owner, bob.accountId, "Compliance");
+  await addToDenylist(contract, bob, [builder.userPubkeyHex], []);
+  t.true(await isPubkeyDenied(contract, builder.userPubkeyHex));
+  await assertFailure(t, builder.submit(), "User pubkey is denied");
+
+  await removeFromDenylist(contract, bob, [builder.userPubkeyHex], []);
+  t.false(await isPubkeyDenied(contract, builder.userPubkeyHex));
+  await builder.submit();
+  t.is(await getUserActiveDepositsLen(contract, builder.userPubkeyHex), 1);
+});
+
 test("submit deposit txn with wrong timelock config", async (t) => {
   const { contract, alice, owner } = t.context.accounts;
   // enable timelock
@@ -2,6 +2,7 @@ import { Gas, NEAR, NearAccount } from "near-workspaces";
 import { ChainSignatureResponse } from "./utils";
 
 export const V1_PK_PATH = "/bithive/v1"; // this should be equal to the one defined in contract
+export const V2_PK_PATH = "/bithive/v2"; // this should be equal to the one defined in contract
 
 interface SubmitDepositArg {
   tx_hex: string;
@@ -52,12 +53,177 @@ export async function queueWithdrawal(
       msg_sig,
       sig_type,
     },
+    {
+      gas: Gas.parse("100 Tgas"),
+    },
+  );
+}
+
+export async function cancelPendingSignPsbt(
+  bithive: NearAccount,
+  caller: NearAccount,
+  user_pubkey: string,
+  tx_id: string,
+  msg_sig: string,
+  sig_type: SigType,
+) {
+  return caller.call(
+    bithive,
+    "cancel_pending_sign_psbt",
+    {
+      user_pubkey,
+      tx_id,
+      msg_sig,
+      sig_type,
+    },
+    {
+      gas: Gas.parse("80 Tgas"),
+    },
+  );
+}
+
+export interface WithdrawalPolicy {
+  daily_limit: number | null;
+  allowed_destinations: string[] | null;
+  loosen_delay_ms: number;
+}
+
+export async function setWithdrawalPolicy(
+  bithive: NearAccount,
+  caller: NearAccount,
+  user_pubkey: string,
+  policy: WithdrawalPolicy,
+  msg_sig: string,
+  sig_type: SigType,
+) {
+  return caller.call(
+    bithive,
+    "set_withdrawal_policy",
+    {
+      user_pubkey,
+      policy,
+      msg_sig,
+      sig_type,
+    },
+    {
+      gas: Gas.parse("80 Tgas"),
+    },
+  );
+}
+
+export async function getSetWithdrawalPolicyMsg(
+  bithive: NearAccount,
+  userPubkey: string,
+  policy: WithdrawalPolicy,
+): Promise<string> {
+  return bithive.view("get_set_withdrawal_policy_msg", {
+    user_pubkey: userPubkey,
+    policy,
+  });
+}
+
+export async function requestRecovery(
+  bithive: NearAccount,
+  caller: NearAccount,
+  user_pubkey: string,
+  deposit_id: string,
+  msg_sig: string,
+  sig_type: SigType,
+) {
+  return caller.call(
+    bithive,
+    "request_recovery",
+    {
+      user_pubkey,
+      deposit_id,
+      msg_sig,
+      sig_type,
+    },
+    {
+      gas: Gas.parse("80 Tgas"),
+    },
+  );
+}
+
+export async function getRequestRecoveryMsg(
+  bithive: NearAccount,
+  userPubkey: string,
+  depositId: string,
+): Promise<string> {
+  return bithive.view("get_request_recovery_msg", {
+    user_pubkey: userPubkey,
+    deposit_id: depositId,
+  });
+}
+
+export async function cancelRecovery(
+  bithive: NearAccount,
+  caller: NearAccount,
+  user_pubkey: string,
+  deposit_id: string,
+  msg_sig: string,
+  sig_type: SigType,
+) {
+  return caller.call(
+    bithive,
+    "cancel_recovery",
+    {
+      user_pubkey,
+      deposit_id,
+      msg_sig,
+      sig_type,
+    },
     {
       gas: Gas.parse("80 Tgas"),
     },
   );
 }
 
+export async function getCancelRecoveryMsg(
+  bithive: NearAccount,
+  userPubkey: string,
+  depositId: string,
+): Promise<string> {
+  return bithive.view("get_cancel_recovery_msg", {
+    user_pubkey: userPubkey,
+    deposit_id: depositId,
+  });
+}
+
+export async function signRecovery(
+  bithive: NearAccount,
+  caller: NearAccount,
+  psbtHex: string,
+  userPubkey: string,
+  vinToSign: number,
+): Promise<ChainSignatureResponse | null> {
+  return caller.call(
+    bithive.accountId,
+    "sign_recovery",
+    {
+      psbt_hex: psbtHex,
+      user_pubkey: userPubkey,
+      vin_to_sign: vinToSign,
+    },
+    {
+      attachedDeposit: NEAR.parse("0.5"),
+      gas: Gas.parse("300 Tgas"),
+    },
+  );
+}
+
+export async function discardExpiredPendingSignPsbt(
+  bithive: NearAccount,
+  caller: NearAccount,
+  user_pubkey: string,
+  tx_id: string,
+) {
+  return caller.call(bithive, "discard_expired_pending_sign_psbt", {
+    user_pubkey,
+    tx_id,
+  });
+}
+
 export async function signWithdrawal(
   bithive: NearAccount,
   caller: NearAccount,
@@ -153,16 +319,223 @@ export async function acceptChangeOwner(
   );
 }
 
-export async function setBtcLightClientId(
+export type ConfigChange =
+  | { BtcLightClientId: string }
+  | { Bip322VerifierId: string | null }
+  | { ChainSignaturesId: string }
+  | { NConfirmation: number }
+  | { WithdrawalWaitingTime: number }
+  | { WithdrawalWaitingTiers: WithdrawalWaitingTier[] }
+  | { WithdrawalWaitingBtcBlocks: number | null }
+  | { GuardianRecoveryWaitingTime: number }
+  | { ConfigTimelock: number }
+  | { UpgradeDelay: number };
+
+export async function proposeConfigChange(
+  bithive: NearAccount,
+  caller: NearAccount,
+  change: ConfigChange,
+): Promise<number> {
+  return caller.call(
+    bithive,
+    "propose_config_change",
+    { change },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function executeConfigChange(
+  bithive: NearAccount,
+  caller: NearAccount,
+  id: number,
+) {
+  return caller.call(
+    bithive,
+    "execute_config_change",
+    { id },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function cancelConfigChange(
+  bithive: NearAccount,
+  caller: NearAccount,
+  id: number,
+) {
+  return caller.call(
+    bithive,
+    "cancel_config_change",
+    { id },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function stageUpgrade(
+  bithive: NearAccount,
+  caller: NearAccount,
+  code: Buffer,
+) {
+  return caller.call(bithive, "stage_upgrade", code, {
+    attachedDeposit: "1",
+    gas: Gas.parse("300 Tgas"),
+  });
+}
+
+export async function deployStagedUpgrade(
+  bithive: NearAccount,
+  caller: NearAccount,
+  expectedCodeHash: string | null,
+) {
+  return caller.call(
+    bithive,
+    "deploy_staged_upgrade",
+    { expected_code_hash: expectedCodeHash },
+    {
+      attachedDeposit: "1",
+      gas: Gas.parse("300 Tgas"),
+    },
+  );
+}
+
+export async function getStagedUpgrade(
+  bithive: NearAccount,
+): Promise<{ code_hash: string; eta_ms: number } | null> {
+  return bithive.view("get_staged_upgrade", {});
+}
+
+export async function migrateAccounts(
+  bithive: NearAccount,
+  caller: NearAccount,
+  limit: number,
+): Promise<number> {
+  return caller.call(
+    bithive,
+    "migrate_accounts",
+    { limit },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function migrateAccountRecords(
+  bithive: NearAccount,
+  caller: NearAccount,
+  offset: number,
+  limit: number,
+): Promise<number> {
+  return caller.call(
+    bithive,
+    "migrate_account_records",
+    { offset, limit },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function getStateVersion(bithive: NearAccount): Promise<{
+  version: number;
+  migrating_from: number | null;
+  migrated_accounts: number;
+  unmigrated_accounts: number;
+}> {
+  return bithive.view("get_state_version", {});
+}
+
+export interface ContractSourceMetadata {
+  version: string | null;
+  link: string | null;
+  standards: { standard: string; version: string }[];
+  build_info: {
+    build_environment: string;
+    build_command: string[];
+    contract_path: string;
+    source_code_snapshot: string;
+  } | null;
+}
+
+export async function getContractSourceMetadata(
+  bithive: NearAccount,
+): Promise<ContractSourceMetadata> {
+  return bithive.view("contract_source_metadata", {});
+}
+
+export async function listPendingConfigChanges(bithive: NearAccount): Promise<
+  {
+    id: number;
+    change: ConfigChange;
+    eta_ms: number;
+  }[]
+> {
+  return bithive.view("list_pending_config_changes", {});
+}
+
+export async function applyConfigChange(
+  bithive: NearAccount,
+  caller: NearAccount,
+  change: ConfigChange,
+) {
+  const id = await proposeConfigChange(bithive, caller, change);
+  const { config_timelock_ms } = await getSummary(bithive);
+  await fastForward(bithive, config_timelock_ms);
+  return executeConfigChange(bithive, caller, id);
+}
+
+export interface WithdrawalWaitingTier {
+  min_amount: number;
+  waiting_time_ms: number;
+}
+
+export async function setWithdrawalRateLimit(
+  bithive: NearAccount,
+  caller: NearAccount,
+  maxAmount: number | null,
+  windowMs: number,
+) {
+  return caller.call(
+    bithive,
+    "set_withdrawal_rate_limit",
+    {
+      max_amount: maxAmount,
+      window_ms: windowMs,
+    },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function resetWithdrawalRateLimit(
+  bithive: NearAccount,
+  caller: NearAccount,
+) {
+  return caller.call(
+    bithive,
+    "reset_withdrawal_rate_limit",
+    {},
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function setGuardianRecoverySequenceHeights(
   bithive: NearAccount,
   caller: NearAccount,
-  contract: NearAccount,
+  values: number[],
 ) {
   return caller.call(
     bithive,
-    "set_btc_light_client_id",
+    "set_guardian_recovery_sequence_heights",
     {
-      new_contract_id: contract.accountId,
+      values,
     },
     {
       attachedDeposit: "1",
@@ -170,16 +543,21 @@ export async function setBtcLightClientId(
   );
 }
 
-export async function setNConfirmation(
+export interface DepositTerm {
+  maturity_height: number;
+  stake_weight_bps: number;
+}
+
+export async function setDepositTerms(
   bithive: NearAccount,
   caller: NearAccount,
-  n: number,
+  terms: DepositTerm[],
 ) {
   return caller.call(
     bithive,
-    "set_n_confirmation",
+    "set_deposit_terms",
     {
-      n,
+      terms,
     },
     {
       attachedDeposit: "1",
@@ -187,14 +565,28 @@ export async function setNConfirmation(
   );
 }
 
-export async function setWithdrawWaitingTime(
+export async function syncBtcTipHeight(
+  bithive: NearAccount,
+  caller: NearAccount,
+) {
+  return caller.call(
+    bithive,
+    "sync_btc_tip_height",
+    {},
+    {
+      gas: Gas.parse("50 Tgas"),
+    },
+  );
+}
+
+export async function setPendingSignPsbtExpiry(
   bithive: NearAccount,
   caller: NearAccount,
   ms: number,
 ) {
   return caller.call(
     bithive,
-    "set_withdrawal_waiting_time",
+    "set_pending_sign_psbt_expiry",
     {
       ms,
     },
@@ -204,6 +596,117 @@ export async function setWithdrawWaitingTime(
   );
 }
 
+export async function setMaxDepositSatoshi(
+  bithive: NearAccount,
+  caller: NearAccount,
+  maxDeposit: number | null,
+) {
+  return caller.call(
+    bithive,
+    "set_max_deposit_satoshi",
+    {
+      max_deposit_satoshi: maxDeposit,
+    },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function setMaxUserDepositSatoshi(
+  bithive: NearAccount,
+  caller: NearAccount,
+  maxUserDeposit: number | null,
+) {
+  return caller.call(
+    bithive,
+    "set_max_user_deposit_satoshi",
+    {
+      max_user_deposit_satoshi: maxUserDeposit,
+    },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function setMaxTotalDepositSatoshi(
+  bithive: NearAccount,
+  caller: NearAccount,
+  maxTotalDeposit: number | null,
+) {
+  return caller.call(
+    bithive,
+    "set_max_total_deposit_satoshi",
+    {
+      max_total_deposit_satoshi: maxTotalDeposit,
+    },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export interface DepositLimits {
+  min_deposit_satoshi: number;
+  max_deposit_satoshi: number | null;
+  max_user_deposit_satoshi: number | null;
+}
+
+export async function setSequenceHeightDepositLimits(
+  bithive: NearAccount,
+  caller: NearAccount,
+  sequenceHeight: number,
+  limits: DepositLimits | null,
+) {
+  return caller.call(
+    bithive,
+    "set_sequence_height_deposit_limits",
+    {
+      sequence_height: sequenceHeight,
+      limits,
+    },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export interface SequenceHeightDepositLimits {
+  sequence_height: number;
+  limits: DepositLimits;
+}
+
+export interface ConfigPatch {
+  pending_sign_psbt_expiry_ms?: number;
+  min_deposit_satoshi?: number;
+  max_deposit_satoshi?: number | null;
+  max_user_deposit_satoshi?: number | null;
+  max_total_deposit_satoshi?: number | null;
+  sequence_height_deposit_limits?: SequenceHeightDepositLimits[];
+  earliest_deposit_block_height?: number;
+  solo_withdrawal_sequence_heights?: number[];
+  guardian_recovery_sequence_heights?: number[];
+  deposit_terms?: DepositTerm[];
+}
+
+export async function updateConfig(
+  bithive: NearAccount,
+  caller: NearAccount,
+  patch: ConfigPatch,
+) {
+  return caller.call(
+    bithive,
+    "update_config",
+    {
+      patch,
+    },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
 export async function setEarliestDepositBlockHeight(
   bithive: NearAccount,
   caller: NearAccount,
@@ -234,6 +737,201 @@ export async function setPaused(
   );
 }
 
+export async function setEmitLegacyEvents(
+  bithive: NearAccount,
+  caller: NearAccount,
+  enabled: boolean,
+) {
+  return caller.call(
+    bithive,
+    "set_emit_legacy_events",
+    { enabled },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export type DepositMode = "Open" | "Allowlist" | "Closed";
+
+export async function setDepositMode(
+  bithive: NearAccount,
+  caller: NearAccount,
+  mode: DepositMode,
+) {
+  return caller.call(
+    bithive,
+    "set_deposit_mode",
+    { mode },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function addToDepositAllowlist(
+  bithive: NearAccount,
+  caller: NearAccount,
+  userPubkeys: string[],
+) {
+  return caller.call(
+    bithive,
+    "add_to_deposit_allowlist",
+    { user_pubkeys: userPubkeys },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function removeFromDepositAllowlist(
+  bithive: NearAccount,
+  caller: NearAccount,
+  userPubkeys: string[],
+) {
+  return caller.call(
+    bithive,
+    "remove_from_deposit_allowlist",
+    { user_pubkeys: userPubkeys },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function depositAllowlistLen(
+  bithive: NearAccount,
+): Promise<number> {
+  return bithive.view("deposit_allowlist_len");
+}
+
+export async function listDepositAllowlist(
+  bithive: NearAccount,
+  offset: number,
+  limit: number,
+): Promise<string[]> {
+  return bithive.view("list_deposit_allowlist", { offset, limit });
+}
+
+export type Role = "Pauser" | "ConfigManager" | "Compliance" | "Upgrader";
+
+export async function grantRole(
+  bithive: NearAccount,
+  caller: NearAccount,
+  accountId: string,
+  role: Role,
+) {
+  return caller.call(
+    bithive,
+    "grant_role",
+    { account_id: accountId, role },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function revokeRole(
+  bithive: NearAccount,
+  caller: NearAccount,
+  accountId: string,
+  role: Role,
+) {
+  return caller.call(
+    bithive,
+    "revoke_role",
+    { account_id: accountId, role },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function getRoles(
+  bithive: NearAccount,
+  offset: number,
+  limit: number,
+): Promise<{ account_id: string; roles: Role[] }[]> {
+  return bithive.view("get_roles", { offset, limit });
+}
+
+export async function addToDenylist(
+  bithive: NearAccount,
+  caller: NearAccount,
+  userPubkeys: string[],
+  outpoints: string[],
+) {
+  return caller.call(
+    bithive,
+    "add_to_denylist",
+    { user_pubkeys: userPubkeys, outpoints },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function removeFromDenylist(
+  bithive: NearAccount,
+  caller: NearAccount,
+  userPubkeys: string[],
+  outpoints: string[],
+) {
+  return caller.call(
+    bithive,
+    "remove_from_denylist",
+    { user_pubkeys: userPubkeys, outpoints },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function freezeAccount(
+  bithive: NearAccount,
+  caller: NearAccount,
+  userPubkey: string,
+  reasonCode: number,
+) {
+  return caller.call(
+    bithive,
+    "freeze_account",
+    { user_pubkey: userPubkey, reason_code: reasonCode },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function unfreezeAccount(
+  bithive: NearAccount,
+  caller: NearAccount,
+  userPubkey: string,
+) {
+  return caller.call(
+    bithive,
+    "unfreeze_account",
+    { user_pubkey: userPubkey },
+    {
+      attachedDeposit: "1",
+    },
+  );
+}
+
+export async function isPubkeyDenied(
+  bithive: NearAccount,
+  userPubkey: string,
+): Promise<boolean> {
+  return bithive.view("is_pubkey_denied", { user_pubkey: userPubkey });
+}
+
+export async function isOutpointDenied(
+  bithive: NearAccount,
+  outpoint: string,
+): Promise<boolean> {
+  return bithive.view("is_outpoint_denied", { outpoint });
+}
+
 interface ContractSummary {
   owner_id: string;
   btc_light_client_id: string;
@@ -241,7 +939,31 @@ interface ContractSummary {
   chain_signatures_root_pubkey: string;
   n_confirmation: number;
   withdrawal_waiting_time_ms: number;
+  withdrawal_waiting_btc_blocks: number | null;
+  withdrawal_waiting_tiers: WithdrawalWaitingTier[];
+  btc_tip_height: number;
+  pending_sign_psbt_expiry_ms: number;
+  withdrawal_rate_limit: {
+    max_amount: number;
+    window_ms: number;
+    used_amount: number;
+  } | null;
+  min_deposit_satoshi: number;
+  max_deposit_satoshi: number | null;
+  max_user_deposit_satoshi: number | null;
+  sequence_height_deposit_limits: SequenceHeightDepositLimits[];
+  max_total_deposit_satoshi: number | null;
+  total_deposit: number;
+  guardian_recovery_sequence_heights: number[];
+  guardian_recovery_waiting_time_ms: number;
+  deposit_terms: DepositTerm[];
+  deposit_mode: DepositMode;
+  config_timelock_ms: number;
+  upgrade_delay_ms: number;
+  emit_legacy_events: boolean;
   paused: boolean;
+  state_version: number;
+  supported_redeem_versions: string[];
 }
 
 export async function getSummary(
@@ -270,6 +992,8 @@ export const getUserActiveDepositsLen =
   buildGetUserLenFunction("active_deposits");
 export const getUserWithdrawnDepositsLen =
   buildGetUserLenFunction("withdrawn_deposits");
+export const getUserPendingSignPsbtsLen =
+  buildGetUserLenFunction("pending_sign_psbts");
 
 interface Deposit {
   user_pubkey: string;
@@ -279,6 +1003,7 @@ interface Deposit {
   deposit_vout: number;
   value: number;
   sequence: number;
+  stake_weight_bps: number;
   complete_withdrawal_ts: number;
   withdrawal_tx_id: string | null;
 }
@@ -303,16 +1028,64 @@ export const listUserActiveDeposits =
 export const listUserWithdrawnDeposits =
   buildListUserDepositFunction("withdrawn_deposits");
 
+interface WithdrawalTicket {
+  nonce: number;
+  amount: number;
+  start_ts: number;
+  waiting_time_ms: number | null;
+  start_btc_height: number | null;
+  unlock_ts: number | null;
+  unlock_btc_height: number | null;
+}
+
 interface Account {
   pubkey: string;
   total_deposit: number;
+  total_weighted_deposit: number;
   queue_withdrawal_amount: number;
   queue_withdrawal_start_ts: number;
+  queue_withdrawal_end_ts: number;
+  withdrawal_tickets: WithdrawalTicket[];
   nonce: number;
-  pending_sign_psbt: {
-    psbt: string;
-    reinvest_deposit_vout: number | null;
+  guardian_nonce: number;
+  pending_sign_psbts_len: number;
+  withdrawal_policy: WithdrawalPolicy;
+  pending_withdrawal_policy: {
+    policy: WithdrawalPolicy;
+    effective_ts: number;
   } | null;
+  daily_withdrawal_amount: number;
+  recovery_requests: {
+    deposit_id: string;
+    start_ts: number;
+  }[];
+  freeze: {
+    reason_code: number;
+    frozen_ts: number;
+  } | null;
+  unmigrated_deposits: number;
+}
+
+interface PendingSignPsbt {
+  tx_id: string;
+  psbt: string;
+  withdraw_amount: number;
+  reinvest_deposit_vout: number | null;
+  reinvest_embed_vout: number | null;
+  bithive_signed: boolean;
+}
+
+export async function listUserPendingSignPsbts(
+  bithive: NearAccount,
+  userPubkey: string,
+  offset: number,
+  limit: number,
+): Promise<PendingSignPsbt[]> {
+  return bithive.view("list_user_pending_sign_psbts", {
+    user_pubkey: userPubkey,
+    offset,
+    limit,
+  });
 }
 
 export async function viewAccount(
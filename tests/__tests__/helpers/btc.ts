@@ -37,6 +37,45 @@ export function depositScriptV1(
   );
 }
 
+export function depositScriptV2(
+  userPubkey: Buffer,
+  bithivePubkey: Buffer,
+  sequence: number,
+  guardianPubkey: Buffer,
+  recoverySequence: number,
+): Buffer {
+  return bitcoin.script.fromASM(
+    `
+    OP_IF
+        ${bitcoin.script.number.encode(sequence).toString("hex")}
+        OP_CHECKSEQUENCEVERIFY
+        OP_DROP
+        ${userPubkey.toString("hex")}
+        OP_CHECKSIG
+    OP_ELSE
+        OP_IF
+            OP_2
+            ${userPubkey.toString("hex")}
+            ${bithivePubkey.toString("hex")}
+            OP_2
+            OP_CHECKMULTISIG
+        OP_ELSE
+            ${bitcoin.script.number.encode(recoverySequence).toString("hex")}
+            OP_CHECKSEQUENCEVERIFY
+            OP_DROP
+            OP_2
+            ${guardianPubkey.toString("hex")}
+            ${bithivePubkey.toString("hex")}
+            OP_2
+            OP_CHECKMULTISIG
+        OP_ENDIF
+    OP_ENDIF
+    `
+      .trim()
+      .replace(/\s+/g, " "),
+  );
+}
+
 export function soloWithdrawScript(userSig: Buffer): Buffer {
   return bitcoin.script.compile([userSig, bitcoin.opcodes.OP_TRUE]);
 }
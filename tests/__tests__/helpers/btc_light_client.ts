@@ -0,0 +1,10 @@
+import { NearAccount } from "near-workspaces";
+
+export async function setLastBlockHeight(
+  lightClient: NearAccount,
+  height: number,
+) {
+  return lightClient.call(lightClient.accountId, "set_last_block_height", {
+    height,
+  });
+}
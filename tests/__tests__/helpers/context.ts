@@ -11,6 +11,7 @@ import {
   setCurrentAccountId,
   syncChainSignaturesRootPubkey,
   V1_PK_PATH,
+  V2_PK_PATH,
 } from "./bithive";
 
 const ECPair = ECPairFactory(ecc);
@@ -22,6 +23,7 @@ export function initUnit(sandbox = true) {
     aliceKeyPair: ECPairInterface;
     bobKeyPair: ECPairInterface;
     bithivePubkey: Buffer;
+    bithivePubkeyV2: Buffer;
   }>;
 
   test.beforeEach(async (t) => {
@@ -47,10 +49,14 @@ export function initUnit(sandbox = true) {
       await deriveAddress("bithive.test.near", V1_PK_PATH, "testnet")
     ).publicKey;
     const bithivePubkey = compressPubKey(bithivePkUncompressed);
+    const bithivePkV2Uncompressed = (
+      await deriveAddress("bithive.test.near", V2_PK_PATH, "testnet")
+    ).publicKey;
 
     t.context.aliceKeyPair = aliceKeyPair;
     t.context.bobKeyPair = bobKeyPair;
     t.context.bithivePubkey = bithivePubkey;
+    t.context.bithivePubkeyV2 = compressPubKey(bithivePkV2Uncompressed);
   });
 
   test.afterEach.always(async (t) => {
@@ -181,6 +187,7 @@ async function createFixtures(root: NearAccount) {
           chain_signatures_id: mockChainSignature.accountId,
           n_confirmation: 6,
           withdrawal_waiting_time_ms: daysToMs(2),
+          pending_sign_psbt_expiry_ms: daysToMs(7),
           min_deposit_satoshi: 100,
           earliest_deposit_block_height: 0,
           solo_withdrawal_seq_heights: [5],
@@ -3,17 +3,26 @@ import { message } from "@okxweb3/coin-bitcoin";
 import { NEAR, NearAccount } from "near-workspaces";
 import {
   depositScriptV1,
+  depositScriptV2,
   getWitnessUtxo,
   idToHash,
   toOutputScript,
 } from "./btc";
 import {
+  getSetWithdrawalPolicyMsg,
   queueWithdrawal,
+  setWithdrawalPolicy,
   signWithdrawal,
   submitDepositTx,
   submitWithdrawalTx,
+  WithdrawalPolicy,
 } from "./bithive";
-import { buildDepositEmbedMsg, getStorageDeposit, someH256 } from "./utils";
+import {
+  buildDepositEmbedMsg,
+  buildDepositEmbedMsgV2,
+  getStorageDeposit,
+  someH256,
+} from "./utils";
 import { ECPairInterface } from "ecpair";
 const bip68 = require("bip68"); // eslint-disable-line
 
@@ -44,6 +53,11 @@ export class TestTransactionBuilder {
       seq?: number;
       depositAmount?: number;
       enableTimelock?: boolean;
+      // builds a v2 deposit with guardian recovery path, `bithivePubkey` should be the v2 one
+      guardian?: {
+        pubkey: Buffer;
+        seq: number;
+      };
     },
   ) {
     this.bithive = bithive;
@@ -57,11 +71,15 @@ export class TestTransactionBuilder {
     this.tx = new bitcoin.Transaction();
     this.p2wsh = bitcoin.payments.p2wsh({
       redeem: {
-        output: depositScriptV1(
-          this.userPubkey,
-          args.bithivePubkey,
-          this.sequence,
-        ),
+        output: args.guardian
+          ? depositScriptV2(
+              this.userPubkey,
+              args.bithivePubkey,
+              this.sequence,
+              args.guardian.pubkey,
+              bip68.encode({ blocks: args.guardian.seq }),
+            )
+          : depositScriptV1(this.userPubkey, args.bithivePubkey, this.sequence),
       },
     });
 
@@ -82,7 +100,15 @@ export class TestTransactionBuilder {
     );
 
     // add embed output
-    const embedMsg = buildDepositEmbedMsg(0, this.userPubkeyHex, args.seq ?? 5);
+    const embedMsg = args.guardian
+      ? buildDepositEmbedMsgV2(
+          0,
+          this.userPubkeyHex,
+          args.seq ?? 5,
+          args.guardian.pubkey.toString("hex"),
+          args.guardian.seq,
+        )
+      : buildDepositEmbedMsg(0, this.userPubkeyHex, args.seq ?? 5);
     const embed = bitcoin.payments.embed({
       data: [embedMsg],
     });
@@ -121,6 +147,24 @@ export class TestTransactionBuilder {
     );
   }
 
+  async setWithdrawalPolicy(policy: WithdrawalPolicy) {
+    const msg = await getSetWithdrawalPolicyMsg(
+      this.bithive,
+      this.userPubkeyHex,
+      policy,
+    );
+    const sigBase64 = message.sign(this.userKeyPair.toWIF(), msg);
+    const sigHex = Buffer.from(sigBase64, "base64").toString("hex");
+    return setWithdrawalPolicy(
+      this.bithive,
+      this.caller,
+      this.userPubkeyHex,
+      policy,
+      sigHex,
+      "ECDSA",
+    );
+  }
+
   async queueWithdrawBip322(amount: number, sigHex: string, address: string) {
     return queueWithdrawal(
       this.bithive,
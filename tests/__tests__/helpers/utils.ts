@@ -106,3 +106,52 @@ export function buildDepositEmbedMsg(
   const magicHeader = "bithive";
   return Buffer.concat([Buffer.from(magicHeader), msg]);
 }
+
+export function buildDepositEmbedMsgV2(
+  depositVout: number,
+  pubkey: string,
+  sequence: number,
+  guardianPubkey: string,
+  recoverySequence: number,
+) {
+  const schema = {
+    enum: [
+      {
+        struct: {
+          V1: {
+            struct: {
+              deposit_vout: "u64",
+              user_pubkey: { array: { type: "u8", len: 33 } },
+              sequence_height: "u16",
+            },
+          },
+        },
+      },
+      {
+        struct: {
+          V2: {
+            struct: {
+              deposit_vout: "u16",
+              user_pubkey: { array: { type: "u8", len: 33 } },
+              sequence_height: "u16",
+              guardian_pubkey: { array: { type: "u8", len: 33 } },
+              recovery_sequence_height: "u16",
+            },
+          },
+        },
+      },
+    ],
+  };
+  const data = {
+    V2: {
+      deposit_vout: depositVout,
+      user_pubkey: Buffer.from(pubkey, "hex"),
+      sequence_height: sequence,
+      guardian_pubkey: Buffer.from(guardianPubkey, "hex"),
+      recovery_sequence_height: recoverySequence,
+    },
+  };
+  const msg = borsh.serialize(schema, data);
+  const magicHeader = "bithive";
+  return Buffer.concat([Buffer.from(magicHeader), msg]);
+}
@@ -1,15 +1,35 @@
 import {
+  applyConfigChange,
+  cancelConfigChange,
+  deployStagedUpgrade,
+  executeConfigChange,
+  fastForward,
+  getContractSourceMetadata,
+  listPendingConfigChanges,
+  migrateAccountRecords,
+  migrateAccounts,
   proposeChangeOwner,
+  proposeConfigChange,
   acceptChangeOwner,
+  getRoles,
+  getStagedUpgrade,
+  getStateVersion,
   getSummary,
-  setBtcLightClientId,
-  setNConfirmation,
+  grantRole,
+  revokeRole,
+  setDepositTerms,
+  setEmitLegacyEvents,
+  setGuardianRecoverySequenceHeights,
   setPaused,
-  setWithdrawWaitingTime,
+  setPendingSignPsbtExpiry,
+  stageUpgrade,
   submitDepositTx,
+  updateConfig,
 } from "./helpers/bithive";
 import { initUnit } from "./helpers/context";
-import { assertFailure } from "./helpers/utils";
+import { assertFailure, daysToMs } from "./helpers/utils";
+import { createHash } from "crypto";
+import fs from "fs";
 
 const test = initUnit();
 
@@ -23,19 +43,25 @@ test("non-privileged account cannot call owner methods", async (t) => {
 
   await assertFailure(
     t,
-    setBtcLightClientId(contract, alice, alice),
+    proposeConfigChange(contract, alice, {
+      BtcLightClientId: alice.accountId,
+    }),
     "Not owner",
   );
 
-  await assertFailure(t, setNConfirmation(contract, alice, 1), "Not owner");
+  await assertFailure(
+    t,
+    proposeConfigChange(contract, alice, { NConfirmation: 1 }),
+    "Missing role",
+  );
 
   await assertFailure(
     t,
-    setWithdrawWaitingTime(contract, alice, 1),
-    "Not owner",
+    proposeConfigChange(contract, alice, { WithdrawalWaitingTime: 1 }),
+    "Missing role",
   );
 
-  await assertFailure(t, setPaused(contract, alice, true), "Not owner");
+  await assertFailure(t, setPaused(contract, alice, true), "Missing role");
 });
 
 test("change owner", async (t) => {
@@ -63,22 +89,186 @@ test("only pending owner can accept change owner", async (t) => {
   await assertFailure(t, acceptChangeOwner(contract, bob), "Not pending owner");
 });
 
-test("set n confirmation", async (t) => {
+test("set n confirmation after timelock", async (t) => {
+  const { contract, owner } = t.context.accounts;
+
+  const id = await proposeConfigChange(contract, owner, { NConfirmation: 1 });
+  const summary = await getSummary(contract);
+  t.deepEqual(await listPendingConfigChanges(contract), [
+    {
+      id,
+      change: { NConfirmation: 1 },
+      eta_ms: daysToMs(3) + summary.config_timelock_ms,
+    },
+  ]);
+  await assertFailure(
+    t,
+    executeConfigChange(contract, owner, id),
+    "Config change not ready",
+  );
+
+  await fastForward(contract, summary.config_timelock_ms);
+  await executeConfigChange(contract, owner, id);
+
+  t.is((await getSummary(contract)).n_confirmation, 1);
+  t.deepEqual(await listPendingConfigChanges(contract), []);
+});
+
+test("cancel withdrawal waiting time change", async (t) => {
+  const { contract, owner } = t.context.accounts;
+
+  const id = await proposeConfigChange(contract, owner, {
+    WithdrawalWaitingTime: 111,
+  });
+  await cancelConfigChange(contract, owner, id);
+  t.deepEqual(await listPendingConfigChanges(contract), []);
+
+  await fastForward(contract, daysToMs(30));
+  await assertFailure(
+    t,
+    executeConfigChange(contract, owner, id),
+    "Config change not found",
+  );
+});
+
+test("set withdrawal waiting tiers", async (t) => {
+  const { contract, owner } = t.context.accounts;
+
+  const tiers = [
+    { min_amount: 1000, waiting_time_ms: daysToMs(3) },
+    { min_amount: 5000, waiting_time_ms: daysToMs(5) },
+  ];
+  await applyConfigChange(contract, owner, { WithdrawalWaitingTiers: tiers });
+
+  const summary = await getSummary(contract);
+  t.deepEqual(summary.withdrawal_waiting_tiers, tiers);
+});
+
+test("withdrawal waiting tiers must be monotonic", async (t) => {
+  const { contract, owner } = t.context.accounts;
+
+  await assertFailure(
+    t,
+    proposeConfigChange(contract, owner, {
+      WithdrawalWaitingTiers: [
+        { min_amount: 5000, waiting_time_ms: daysToMs(3) },
+        { min_amount: 1000, waiting_time_ms: daysToMs(5) },
+      ],
+    }),
+    "tier amounts must be strictly increasing",
+  );
+  await assertFailure(
+    t,
+    proposeConfigChange(contract, owner, {
+      WithdrawalWaitingTiers: [
+        { min_amount: 1000, waiting_time_ms: daysToMs(1) },
+      ],
+    }),
+    "tier waiting times must be non-decreasing",
+  );
+});
+
+test("set guardian recovery config", async (t) => {
   const { contract, owner } = t.context.accounts;
 
-  await setNConfirmation(contract, owner, 1);
+  await setGuardianRecoverySequenceHeights(contract, owner, [100, 200]);
+  await applyConfigChange(contract, owner, {
+    GuardianRecoveryWaitingTime: daysToMs(7),
+  });
 
   const summary = await getSummary(contract);
-  t.is(summary.n_confirmation, 1);
+  t.deepEqual(summary.guardian_recovery_sequence_heights, [100, 200]);
+  t.is(summary.guardian_recovery_waiting_time_ms, daysToMs(7));
 });
 
-test("set withdrawal waiting time", async (t) => {
+test("set deposit terms", async (t) => {
   const { contract, owner } = t.context.accounts;
 
-  await setWithdrawWaitingTime(contract, owner, 111);
+  const terms = [
+    { maturity_height: 900000, stake_weight_bps: 12000 },
+    { maturity_height: 950000, stake_weight_bps: 15000 },
+  ];
+  await setDepositTerms(contract, owner, terms);
 
   const summary = await getSummary(contract);
-  t.is(summary.withdrawal_waiting_time_ms, 111);
+  t.deepEqual(summary.deposit_terms, terms);
+
+  await assertFailure(
+    t,
+    setDepositTerms(contract, owner, [
+      { maturity_height: 900000, stake_weight_bps: 9000 },
+    ]),
+    "stake weight must not be less than the base weight",
+  );
+  await assertFailure(
+    t,
+    setDepositTerms(contract, owner, [
+      { maturity_height: 900000, stake_weight_bps: 100001 },
+    ]),
+    "stake weight must not be more than the max weight",
+  );
+  // maturity is used as absolute locktime, which must not be a timestamp
+  await assertFailure(
+    t,
+    setDepositTerms(contract, owner, [
+      { maturity_height: 500000000, stake_weight_bps: 12000 },
+    ]),
+    "maturity height must be a block height",
+  );
+});
+
+test("set pending sign psbt expiry", async (t) => {
+  const { contract, owner } = t.context.accounts;
+
+  await setPendingSignPsbtExpiry(contract, owner, 222);
+
+  const summary = await getSummary(contract);
+  t.is(summary.pending_sign_psbt_expiry_ms, 222);
+});
+
+test("update config", async (t) => {
+  const { contract, owner } = t.context.accounts;
+
+  await updateConfig(contract, owner, {
+    min_deposit_satoshi: 1000,
+    max_deposit_satoshi: 5000,
+    guardian_recovery_sequence_heights: [100],
+  });
+
+  let summary = await getSummary(contract);
+  t.is(summary.min_deposit_satoshi, 1000);
+  t.is(summary.max_deposit_satoshi, 5000);
+  t.deepEqual(summary.guardian_recovery_sequence_heights, [100]);
+
+  // a null value unsets the field
+  await updateConfig(contract, owner, { max_deposit_satoshi: null });
+  summary = await getSummary(contract);
+  t.is(summary.max_deposit_satoshi, null);
+
+  // invalid patch is rejected as a whole
+  await assertFailure(
+    t,
+    updateConfig(contract, owner, {
+      min_deposit_satoshi: 2000,
+      max_deposit_satoshi: 1500,
+    }),
+    "max deposit must not be less than min deposit",
+  );
+  summary = await getSummary(contract);
+  t.is(summary.min_deposit_satoshi, 1000);
+});
+
+test("disable legacy events", async (t) => {
+  const { contract, owner, alice } = t.context.accounts;
+  t.true((await getSummary(contract)).emit_legacy_events);
+
+  await assertFailure(
+    t,
+    setEmitLegacyEvents(contract, alice, false),
+    "Missing role",
+  );
+  await setEmitLegacyEvents(contract, owner, false);
+  t.false((await getSummary(contract)).emit_legacy_events);
 });
 
 test("pause contract", async (t) => {
@@ -100,3 +290,90 @@ test("pause contract", async (t) => {
     "Contract is paused",
   );
 });
+
+test("grant and revoke roles", async (t) => {
+  const { contract, owner, alice } = t.context.accounts;
+  await assertFailure(
+    t,
+    grantRole(contract, alice, alice.accountId, "Pauser"),
+    "Not owner",
+  );
+
+  await grantRole(contract, owner, alice.accountId, "Pauser");
+  t.deepEqual(await getRoles(contract, 0, 10), [
+    { account_id: alice.accountId, roles: ["Pauser"] },
+  ]);
+
+  // pauser can pause but cannot change configs
+  await setPaused(contract, alice, true);
+  t.is((await getSummary(contract)).paused, true);
+  await assertFailure(
+    t,
+    proposeConfigChange(contract, alice, { NConfirmation: 1 }),
+    "Missing role",
+  );
+
+  await revokeRole(contract, owner, alice.accountId, "Pauser");
+  t.deepEqual(await getRoles(contract, 0, 10), []);
+  await assertFailure(t, setPaused(contract, alice, false), "Missing role");
+});
+
+test("stage and deploy upgrade", async (t) => {
+  const { contract, owner } = t.context.accounts;
+  const code = fs.readFileSync("res/bithive_test.wasm");
+  const codeHash = createHash("sha256").update(code).digest("hex");
+
+  await stageUpgrade(contract, owner, code);
+  const staged = await getStagedUpgrade(contract);
+  t.is(staged!.code_hash, codeHash);
+  await assertFailure(
+    t,
+    deployStagedUpgrade(contract, owner, codeHash),
+    "Staged upgrade not ready",
+  );
+
+  const summary = await getSummary(contract);
+  await fastForward(contract, summary.upgrade_delay_ms);
+  await assertFailure(
+    t,
+    deployStagedUpgrade(contract, owner, "00"),
+    "Code hash mismatch",
+  );
+  await deployStagedUpgrade(contract, owner, codeHash);
+  t.is(await getStagedUpgrade(contract), null);
+});
+
+test("state version after init", async (t) => {
+  const { contract, owner } = t.context.accounts;
+  t.deepEqual(await getStateVersion(contract), {
+    version: 2,
+    migrating_from: null,
+    migrated_accounts: 0,
+    unmigrated_accounts: 0,
+  });
+  await assertFailure(
+    t,
+    migrateAccounts(contract, owner, 10),
+    "No state migration in progress",
+  );
+  t.is(await migrateAccountRecords(contract, owner, 0, 10), 0);
+});
+
+test("contract source metadata", async (t) => {
+  const { contract } = t.context.accounts;
+  const metadata = await getContractSourceMetadata(contract);
+  t.is(metadata.version, "0.1.0");
+  t.true(
+    metadata.standards.some(
+      (s) => s.standard === "nep330" && s.version === "1.2.0",
+    ),
+  );
+  if (metadata.build_info) {
+    t.deepEqual(metadata.build_info.build_command, ["make", "bithive-test"]);
+    t.is(metadata.build_info.contract_path, "contracts/bithive");
+  }
+
+  const summary = await getSummary(contract);
+  t.is(summary.state_version, 2);
+  t.deepEqual(summary.supported_redeem_versions, ["V1", "V2", "V3"]);
+});
@@ -1,5 +1,10 @@
 import { toXOnly } from "bitcoinjs-lib/src/psbt/bip371";
-import { fastForward, viewAccount } from "./helpers/bithive";
+import {
+  fastForward,
+  freezeAccount,
+  unfreezeAccount,
+  viewAccount,
+} from "./helpers/bithive";
 import { initUnit } from "./helpers/context";
 import { TestTransactionBuilder } from "./helpers/txn_builder";
 import { assertFailure, daysToMs } from "./helpers/utils";
@@ -53,8 +58,20 @@ test("valid queue withdrawal", async (t) => {
   const account = await viewAccount(contract, builder.userPubkeyHex);
   t.is(account.queue_withdrawal_amount, 100);
   t.is(account.queue_withdrawal_start_ts, daysToMs(3));
+  t.is(account.queue_withdrawal_end_ts, daysToMs(5));
+  t.deepEqual(account.withdrawal_tickets, [
+    {
+      nonce: 0,
+      amount: 100,
+      start_ts: daysToMs(3),
+      waiting_time_ms: daysToMs(2),
+      start_btc_height: null,
+      unlock_ts: daysToMs(5),
+      unlock_btc_height: null,
+    },
+  ]);
   t.is(account.nonce, 1);
-  t.is(account.pending_sign_psbt, null);
+  t.is(account.pending_sign_psbts_len, 0);
 });
 
 test("queue withdrawal with wrong amount in signature", async (t) => {
@@ -92,7 +109,7 @@ test("queue withdrawal with bad amount", async (t) => {
   );
 });
 
-test("a second queue withdrawal request should reset the waiting period", async (t) => {
+test("a second queue withdrawal request should not reset the waiting period", async (t) => {
   const { builder, contract } = await makeDeposit(t);
   const sig = builder.queueWithdrawSignature(100, 0);
   await builder.queueWithdraw(100, sig);
@@ -103,7 +120,13 @@ test("a second queue withdrawal request should reset the waiting period", async
 
   const account = await viewAccount(contract, builder.userPubkeyHex);
   t.is(account.queue_withdrawal_amount, 1100);
-  t.is(account.queue_withdrawal_start_ts, daysToMs(4));
+  t.is(account.withdrawal_tickets.length, 2);
+  t.is(account.withdrawal_tickets[0].unlock_ts, daysToMs(5));
+  t.is(account.withdrawal_tickets[1].nonce, 1);
+  t.is(account.withdrawal_tickets[1].amount, 1000);
+  t.is(account.withdrawal_tickets[1].unlock_ts, daysToMs(6));
+  t.is(account.queue_withdrawal_start_ts, daysToMs(3));
+  t.is(account.queue_withdrawal_end_ts, daysToMs(6));
 });
 
 test("queue withdrawal again after deposit", async (t) => {
@@ -123,10 +146,10 @@ test("queue withdrawal again after deposit", async (t) => {
 
   const account = await viewAccount(contract, builder.userPubkeyHex);
   t.is(account.queue_withdrawal_amount, 1e8 + 100);
-  t.is(account.queue_withdrawal_start_ts, daysToMs(4));
+  t.is(account.withdrawal_tickets[1].start_ts, daysToMs(4));
 });
 
-test("queue withdrawal should clear pending withdrawal psbt", async (t) => {
+test("queue withdrawal should keep pending withdrawal psbt", async (t) => {
   const { builder, contract } = await makeDeposit(t, 1e8);
 
   const sig = builder.queueWithdrawSignature(100, 0);
@@ -137,15 +160,14 @@ test("queue withdrawal should clear pending withdrawal psbt", async (t) => {
   await builder.signWithdraw(0);
 
   let account = await viewAccount(contract, builder.userPubkeyHex);
-  t.assert(account.pending_sign_psbt);
-  t.assert(account.pending_sign_psbt!.psbt);
-  t.assert(account.pending_sign_psbt!.reinvest_deposit_vout);
+  t.is(account.pending_sign_psbts_len, 1);
 
   const sig2 = builder.queueWithdrawSignature(100, 1);
   await builder.queueWithdraw(100, sig2);
 
   account = await viewAccount(contract, builder.userPubkeyHex);
-  t.assert(account.pending_sign_psbt === null);
+  t.is(account.pending_sign_psbts_len, 1);
+  t.is(account.queue_withdrawal_amount, 100);
 });
 
 test("queue withdrawal with bip322 signature", async (t) => {
@@ -162,3 +184,21 @@ test("queue withdrawal with bip322 signature", async (t) => {
   const sigBad = "01"; // dummy signature, just to test the function
   t.assert(!(await builder.queueWithdrawBip322(100, sigBad, address)));
 });
+
+test("queue withdrawal of frozen account", async (t) => {
+  const { builder, contract } = await makeDeposit(t);
+  const { owner } = t.context.accounts;
+
+  await freezeAccount(contract, owner, builder.userPubkeyHex, 7);
+  let account = await viewAccount(contract, builder.userPubkeyHex);
+  t.is(account.freeze!.reason_code, 7);
+
+  const sig = builder.queueWithdrawSignature(100, 0);
+  await assertFailure(t, builder.queueWithdraw(100, sig), "Account is frozen");
+
+  await unfreezeAccount(contract, owner, builder.userPubkeyHex);
+  await builder.queueWithdraw(100, sig);
+  account = await viewAccount(contract, builder.userPubkeyHex);
+  t.is(account.queue_withdrawal_amount, 100);
+  t.is(account.freeze, null);
+});
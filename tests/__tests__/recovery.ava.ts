@@ -0,0 +1,156 @@
+import { message } from "@okxweb3/coin-bitcoin";
+import {
+  cancelRecovery,
+  getCancelRecoveryMsg,
+  getRequestRecoveryMsg,
+  listUserActiveDeposits,
+  requestRecovery,
+  setGuardianRecoverySequenceHeights,
+  viewAccount,
+} from "./helpers/bithive";
+import { initUnit } from "./helpers/context";
+import { TestTransactionBuilder } from "./helpers/txn_builder";
+import { assertFailure } from "./helpers/utils";
+import { ECPairInterface } from "ecpair";
+
+const test = initUnit();
+
+function signMsg(keyPair: ECPairInterface, msg: string) {
+  const sigBase64 = message.sign(keyPair.toWIF(), msg);
+  return Buffer.from(sigBase64, "base64").toString("hex");
+}
+
+async function submitV2Deposit(t: any): Promise<TestTransactionBuilder> {
+  const { contract, owner, alice } = t.context.accounts;
+  await setGuardianRecoverySequenceHeights(contract, owner, [100]);
+
+  const builder = new TestTransactionBuilder(contract, alice, {
+    userKeyPair: t.context.aliceKeyPair,
+    bithivePubkey: t.context.bithivePubkeyV2,
+    guardian: {
+      pubkey: t.context.bobKeyPair.publicKey,
+      seq: 100,
+    },
+  });
+  await builder.submit();
+  return builder;
+}
+
+test("submit v2 deposit with guardian", async (t) => {
+  const { contract } = t.context.accounts;
+  const builder = await submitV2Deposit(t);
+
+  const deposits = await listUserActiveDeposits(
+    contract,
+    builder.userPubkeyHex,
+    0,
+    1,
+  );
+  t.deepEqual(deposits[0].redeem_version as any, {
+    V2: {
+      guardian_pubkey: t.context.bobKeyPair.publicKey.toString("hex"),
+      recovery_sequence: 100,
+    },
+  });
+});
+
+test("submit v2 deposit with invalid recovery sequence height", async (t) => {
+  const { contract, owner, alice } = t.context.accounts;
+  await setGuardianRecoverySequenceHeights(contract, owner, [100]);
+
+  const builder = new TestTransactionBuilder(contract, alice, {
+    userKeyPair: t.context.aliceKeyPair,
+    bithivePubkey: t.context.bithivePubkeyV2,
+    guardian: {
+      pubkey: t.context.bobKeyPair.publicKey,
+      seq: 50,
+    },
+  });
+  await assertFailure(
+    t,
+    builder.submit(),
+    "Invalid guardian recovery seq height",
+  );
+});
+
+test("request and cancel recovery", async (t) => {
+  const { contract, alice } = t.context.accounts;
+  const builder = await submitV2Deposit(t);
+  const depositId = `${builder.tx.getId()}:0`;
+
+  // recovery must be requested by the guardian
+  const requestMsg = await getRequestRecoveryMsg(
+    contract,
+    builder.userPubkeyHex,
+    depositId,
+  );
+  t.is(requestMsg, `bithive.request_recovery:0:${depositId}`);
+  await assertFailure(
+    t,
+    requestRecovery(
+      contract,
+      alice,
+      builder.userPubkeyHex,
+      depositId,
+      signMsg(t.context.aliceKeyPair, requestMsg),
+      "ECDSA",
+    ),
+    "Invalid bitcoin signature",
+  );
+
+  await requestRecovery(
+    contract,
+    alice,
+    builder.userPubkeyHex,
+    depositId,
+    signMsg(t.context.bobKeyPair, requestMsg),
+    "ECDSA",
+  );
+  let account = await viewAccount(contract, builder.userPubkeyHex);
+  t.is(account.recovery_requests.length, 1);
+  t.is(account.recovery_requests[0].deposit_id, depositId);
+  // the guardian does not use up the nonce of the user
+  t.is(account.guardian_nonce, 1);
+  t.is(account.nonce, 0);
+
+  // the user could cancel it while the primary key is still available
+  const cancelMsg = await getCancelRecoveryMsg(
+    contract,
+    builder.userPubkeyHex,
+    depositId,
+  );
+  t.is(cancelMsg, `bithive.cancel_recovery:0:${depositId}`);
+  await cancelRecovery(
+    contract,
+    alice,
+    builder.userPubkeyHex,
+    depositId,
+    signMsg(t.context.aliceKeyPair, cancelMsg),
+    "ECDSA",
+  );
+  account = await viewAccount(contract, builder.userPubkeyHex);
+  t.is(account.recovery_requests.length, 0);
+});
+
+test("request recovery of v1 deposit", async (t) => {
+  const { contract, alice } = t.context.accounts;
+
+  const builder = new TestTransactionBuilder(contract, alice, {
+    userKeyPair: t.context.aliceKeyPair,
+    bithivePubkey: t.context.bithivePubkey,
+  });
+  await builder.submit();
+
+  await assertFailure(
+    t,
+    requestRecovery(
+      contract,
+      alice,
+      builder.userPubkeyHex,
+      `${builder.tx.getId()}:0`,
+      "00",
+      "ECDSA",
+    ),
+    "Deposit has no guardian",
+  );
+});
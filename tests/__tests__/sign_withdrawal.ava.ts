@@ -1,5 +1,17 @@
 import * as bitcoin from "bitcoinjs-lib";
-import { fastForward, signWithdrawal, viewAccount } from "./helpers/bithive";
+import {
+  discardExpiredPendingSignPsbt,
+  fastForward,
+  getSummary,
+  listUserPendingSignPsbts,
+  resetWithdrawalRateLimit,
+  setWithdrawalRateLimit,
+  applyConfigChange,
+  signWithdrawal,
+  syncBtcTipHeight,
+  viewAccount,
+} from "./helpers/bithive";
+import { setLastBlockHeight } from "./helpers/btc_light_client";
 import { initUnit } from "./helpers/context";
 import { TestTransactionBuilder } from "./helpers/txn_builder";
 import { assertFailure, buildDepositEmbedMsg, daysToMs } from "./helpers/utils";
@@ -91,9 +103,17 @@ test("sign withdrawal should set pending withdrawal psbt", async (t) => {
   builder.generateWithdrawPsbt(undefined, 1e8 - 100);
   await builder.signWithdraw(0);
 
-  const account = await viewAccount(contract, builder.userPubkeyHex);
-  t.is(account.pending_sign_psbt!.psbt, builder.psbt!.toHex());
-  t.is(account.pending_sign_psbt!.reinvest_deposit_vout, 1);
+  const pendingSignPsbts = await listUserPendingSignPsbts(
+    contract,
+    builder.userPubkeyHex,
+    0,
+    10,
+  );
+  t.is(pendingSignPsbts.length, 1);
+  t.is(pendingSignPsbts[0].tx_id, builder.extractWithdrawTx().getId());
+  t.is(pendingSignPsbts[0].psbt, builder.psbt!.toHex());
+  t.is(pendingSignPsbts[0].withdraw_amount, 100);
+  t.is(pendingSignPsbts[0].reinvest_deposit_vout, 1);
 });
 
 test("sign withdrawal should reset queue withdrawal amount", async (t) => {
@@ -108,7 +128,185 @@ test("sign withdrawal should reset queue withdrawal amount", async (t) => {
 
   const account = await viewAccount(contract, builder.userPubkeyHex);
   t.is(account.queue_withdrawal_amount, 0);
-  t.is(account.queue_withdrawal_start_ts, 0);
+  t.deepEqual(account.withdrawal_tickets, []);
+});
+
+test("sign withdrawal with matured ticket while a newer one waits", async (t) => {
+  const { builder, contract } = await makeDeposit(t, 1e8);
+
+  const sig = builder.queueWithdrawSignature(100, 0);
+  await builder.queueWithdraw(100, sig);
+  await fastForward(contract, daysToMs(2));
+
+  const sig2 = builder.queueWithdrawSignature(200, 1);
+  await builder.queueWithdraw(200, sig2);
+
+  builder.generateWithdrawPsbt(undefined, 1e8 - 100);
+  await builder.signWithdraw(0);
+
+  const account = await viewAccount(contract, builder.userPubkeyHex);
+  t.is(account.queue_withdrawal_amount, 200);
+  t.is(account.withdrawal_tickets.length, 1);
+  t.is(account.withdrawal_tickets[0].nonce, 1);
+});
+
+test("sign withdrawal exceeding rate limit", async (t) => {
+  const { builder, contract } = await makeDeposit(t, 1e8);
+  const { owner } = t.context.accounts;
+
+  await setWithdrawalRateLimit(contract, owner, 50, daysToMs(1));
+
+  const sig = builder.queueWithdrawSignature(100, 0);
+  await builder.queueWithdraw(100, sig);
+  await fastForward(contract, daysToMs(2));
+
+  builder.generateWithdrawPsbt(undefined, 1e8 - 100);
+  t.is(await builder.signWithdraw(0), null);
+  t.is(
+    (await listUserPendingSignPsbts(contract, builder.userPubkeyHex, 0, 10))
+      .length,
+    0,
+  );
+  const account = await viewAccount(contract, builder.userPubkeyHex);
+  t.is(account.queue_withdrawal_amount, 100);
+
+  // owner raises the limit
+  await setWithdrawalRateLimit(contract, owner, 100, daysToMs(1));
+  await builder.signWithdraw(0);
+
+  const summary = await getSummary(contract);
+  t.deepEqual(summary.withdrawal_rate_limit, {
+    max_amount: 100,
+    window_ms: daysToMs(1),
+    used_amount: 100,
+  });
+
+  await resetWithdrawalRateLimit(contract, owner);
+  t.is((await getSummary(contract)).withdrawal_rate_limit!.used_amount, 0);
+});
+
+test("sign withdrawal to destination not allowed by user policy", async (t) => {
+  const { builder, contract } = await makeDeposit(t, 1e8);
+
+  await builder.setWithdrawalPolicy({
+    daily_limit: null,
+    allowed_destinations: ["0014" + "00".repeat(20)],
+    loosen_delay_ms: 0,
+  });
+
+  const sig = builder.queueWithdrawSignature(100, 1);
+  await builder.queueWithdraw(100, sig);
+  await fastForward(contract, daysToMs(2));
+
+  builder.generateWithdrawPsbt(undefined, 1e8 - 100);
+  await assertFailure(
+    t,
+    builder.signWithdraw(0),
+    "Withdrawal destination not allowed",
+  );
+});
+
+test("sign withdrawal exceeding user daily limit", async (t) => {
+  const { builder, contract } = await makeDeposit(t, 1e8);
+
+  await builder.setWithdrawalPolicy({
+    daily_limit: 50,
+    allowed_destinations: null,
+    loosen_delay_ms: daysToMs(1),
+  });
+
+  const sig = builder.queueWithdrawSignature(100, 1);
+  await builder.queueWithdraw(100, sig);
+  await fastForward(contract, daysToMs(2));
+
+  builder.generateWithdrawPsbt(undefined, 1e8 - 100);
+  await assertFailure(
+    t,
+    builder.signWithdraw(0),
+    "Daily withdrawal limit exceeded",
+  );
+
+  // loosening the limit only takes effect after the delay
+  await builder.setWithdrawalPolicy({
+    daily_limit: 100,
+    allowed_destinations: null,
+    loosen_delay_ms: daysToMs(1),
+  });
+  let account = await viewAccount(contract, builder.userPubkeyHex);
+  t.is(account.withdrawal_policy.daily_limit, 50);
+  t.is(account.pending_withdrawal_policy!.policy.daily_limit, 100);
+  await assertFailure(
+    t,
+    builder.signWithdraw(0),
+    "Daily withdrawal limit exceeded",
+  );
+
+  await fastForward(contract, daysToMs(1));
+  await builder.signWithdraw(0);
+
+  account = await viewAccount(contract, builder.userPubkeyHex);
+  t.is(account.withdrawal_policy.daily_limit, 100);
+  t.is(account.pending_withdrawal_policy, null);
+  t.is(account.daily_withdrawal_amount, 100);
+});
+
+test("sign withdrawal with waiting time in btc blocks", async (t) => {
+  const { builder, contract } = await makeDeposit(t, 1e8);
+  const { owner, bob, mockLightclient } = t.context.accounts;
+  await applyConfigChange(contract, owner, { WithdrawalWaitingBtcBlocks: 6 });
+  await setLastBlockHeight(mockLightclient, 100);
+
+  const sig = builder.queueWithdrawSignature(100, 0);
+  await builder.queueWithdraw(100, sig);
+
+  let account = await viewAccount(contract, builder.userPubkeyHex);
+  t.is(account.withdrawal_tickets[0].start_btc_height, 100);
+  t.is(account.withdrawal_tickets[0].unlock_btc_height, 106);
+
+  // waiting time in ms does not matter any more
+  await fastForward(contract, daysToMs(2));
+  builder.generateWithdrawPsbt(undefined, 1e8 - 100);
+  await assertFailure(t, builder.signWithdraw(0), "Not ready to withdraw now");
+
+  // tip height is only checked after synced from light client
+  await setLastBlockHeight(mockLightclient, 106);
+  await assertFailure(t, builder.signWithdraw(0), "Not ready to withdraw now");
+  await syncBtcTipHeight(contract, bob);
+  await builder.signWithdraw(0);
+
+  account = await viewAccount(contract, builder.userPubkeyHex);
+  t.deepEqual(account.withdrawal_tickets, []);
+});
+
+test("discard expired pending sign psbt", async (t) => {
+  const { builder, contract } = await makeDeposit(t, 1e8);
+  const { bob } = t.context.accounts;
+
+  const sig = builder.queueWithdrawSignature(100, 0);
+  await builder.queueWithdraw(100, sig);
+  await fastForward(contract, daysToMs(2));
+
+  builder.generateWithdrawPsbt(undefined, 1e8 - 100);
+  await builder.signWithdraw(0);
+  const txId = builder.extractWithdrawTx().getId();
+
+  await assertFailure(
+    t,
+    discardExpiredPendingSignPsbt(contract, bob, builder.userPubkeyHex, txId),
+    "Pending sign PSBT not expired yet",
+  );
+
+  // the signed txn could still be broadcasted, so its amount stays reserved
+  await fastForward(contract, daysToMs(7));
+  await assertFailure(
+    t,
+    discardExpiredPendingSignPsbt(contract, bob, builder.userPubkeyHex, txId),
+    "Pending sign PSBT already signed by bithive",
+  );
+
+  const account = await viewAccount(contract, builder.userPubkeyHex);
+  t.is(account.pending_sign_psbts_len, 1);
+  t.is(account.queue_withdrawal_amount, 0);
 });
 
 test("sign withdrawal with multiple deposit inputs", async (t) => {
@@ -182,6 +380,31 @@ test("sign withdrawal RBF", async (t) => {
   await builder.signWithdraw(0);
 });
 
+test("sign withdrawal RBF without user signature", async (t) => {
+  const { builder, contract, userPubkey, account } = await makeDeposit(t, 1e8);
+
+  const sig = builder.queueWithdrawSignature(100, 0);
+  await builder.queueWithdraw(100, sig);
+  await fastForward(contract, daysToMs(2));
+
+  builder.generateWithdrawPsbt(undefined, 1e8 - 100, 100);
+  await builder.signWithdraw(0);
+
+  // anyone else cannot replace the pending PSBT
+  const psbt = builder.generateWithdrawPsbt(undefined, 1e8 - 100, 10, false);
+  await assertFailure(
+    t,
+    signWithdrawal(
+      contract,
+      account,
+      psbt.toHex(),
+      userPubkey.toString("hex"),
+      0,
+    ),
+    "Missing partial sig for given input",
+  );
+});
+
 test("sign withdrawal twice but with different PSBT", async (t) => {
   const { builder: builder1, contract } = await makeDeposit(t, 1e8);
   const { builder: builder2 } = await makeDeposit(t, 100);
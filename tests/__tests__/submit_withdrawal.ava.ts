@@ -123,8 +123,8 @@ test("submit solo withdrawal", async (t) => {
   const accountAfter = await viewAccount(contract, builder1.userPubkeyHex);
   t.is(accountAfter.queue_withdrawal_amount, 2e8);
   t.is(
-    accountAfter.queue_withdrawal_start_ts,
-    accountBefore.queue_withdrawal_start_ts,
+    accountAfter.withdrawal_tickets[0].start_ts,
+    accountBefore.withdrawal_tickets[0].start_ts,
   );
 });
 
@@ -197,9 +197,9 @@ test("submit multisig withdrawal", async (t) => {
     accountAfter.queue_withdrawal_amount,
     accountBefore.queue_withdrawal_amount,
   );
-  t.is(
-    accountAfter.queue_withdrawal_start_ts,
-    accountBefore.queue_withdrawal_start_ts,
+  t.deepEqual(
+    accountAfter.withdrawal_tickets,
+    accountBefore.withdrawal_tickets,
   );
 });
 